@@ -0,0 +1,249 @@
+//! API key lifecycle for in-process daemon instances.
+//!
+//! The master key lives in `{data_dir}/api_key` (the file the daemon itself creates and
+//! `get_daemon_api_key` reads). Additional named keys are stored in
+//! `{data_dir}/api_keys.json`; each one is served by its own IPC listener, which checks
+//! every call against the key's scopes (see `listeners`). Both files hold secrets and are
+//! written owner-only through `state_file::write_private`.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state_file;
+
+/// Namespaces a named key can be granted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyScope {
+    /// Full access to `data.*`
+    Data,
+    /// Read-only subset of `data.*` (list, status, providers)
+    DataRead,
+    /// Full access to `tunnel.*`
+    Tunnel,
+    /// `settlement.*` methods, served by the CraftOBJ handler
+    Settlement,
+}
+
+/// A named key as persisted in `api_keys.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScopedKey {
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<KeyScope>,
+    /// Unix seconds
+    pub created_at: u64,
+    /// Unix seconds; `None` means the key never expires
+    pub expires_at: Option<u64>,
+    /// Dedicated socket this key's listener binds
+    pub socket_path: String,
+    /// Optional WebSocket port for this key's listener
+    pub ws_port: Option<u16>,
+    /// Event topics forwarded to this key's clients; empty means all
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+impl ScopedKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= now_secs())
+    }
+
+    /// Public view of the key, without the secret.
+    pub fn info(&self) -> ApiKeyInfo {
+        ApiKeyInfo {
+            name: self.name.clone(),
+            scopes: self.scopes.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            expired: self.is_expired(),
+            socket_path: self.socket_path.clone(),
+            ws_port: self.ws_port,
+            topics: self.topics.clone(),
+        }
+    }
+}
+
+/// What `list_api_keys` returns — everything but the secret.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    pub scopes: Vec<KeyScope>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub expired: bool,
+    pub socket_path: String,
+    pub ws_port: Option<u16>,
+    pub topics: Vec<String>,
+}
+
+/// Request to issue a new named key.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IssueKeyRequest {
+    pub name: String,
+    pub scopes: Vec<KeyScope>,
+    pub expires_in_secs: Option<u64>,
+    pub ws_port: Option<u16>,
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+/// Named keys for one data directory, backed by `{data_dir}/api_keys.json`.
+pub struct KeyStore {
+    path: PathBuf,
+}
+
+impl KeyStore {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join("api_keys.json"),
+        }
+    }
+
+    /// Load all keys. A missing file is an empty list; a file that can't be read or parsed
+    /// is an error, so a later save can't silently drop every key in it.
+    pub fn load(&self) -> Result<Vec<ScopedKey>, String> {
+        let raw = match fs::read_to_string(&self.path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.path.display(), e)),
+        };
+        serde_json::from_str(&raw).map_err(|e| {
+            format!("{} is corrupt ({}); fix or remove it to manage API keys", self.path.display(), e)
+        })
+    }
+
+    pub fn save(&self, keys: &[ScopedKey]) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(keys)
+            .map_err(|e| format!("Failed to serialize API keys: {}", e))?;
+        state_file::write_private(&self.path, json.as_bytes())
+    }
+
+    /// Create and persist a new key for an instance whose master socket is `socket_path`.
+    pub fn issue(&self, socket_path: &str, req: IssueKeyRequest) -> Result<ScopedKey, String> {
+        validate_key_name(&req.name)?;
+        if req.scopes.is_empty() {
+            return Err("A key needs at least one scope".to_string());
+        }
+        let mut keys = self.load()?;
+        if keys.iter().any(|k| k.name == req.name) {
+            return Err(format!("An API key named '{}' already exists", req.name));
+        }
+        if let Some(port) = req.ws_port {
+            if keys.iter().any(|k| k.ws_port == Some(port)) {
+                return Err(format!("ws_port {} is already used by another key", port));
+            }
+        }
+
+        let created_at = now_secs();
+        let key = ScopedKey {
+            socket_path: scoped_socket_path(socket_path, &req.name),
+            name: req.name,
+            key: generate_key(),
+            scopes: req.scopes,
+            created_at,
+            expires_at: req.expires_in_secs.map(|s| created_at + s),
+            ws_port: req.ws_port,
+            topics: req.topics,
+        };
+        keys.push(key.clone());
+        self.save(&keys)?;
+        Ok(key)
+    }
+
+    /// Remove a key by name, returning it.
+    pub fn revoke(&self, name: &str) -> Result<ScopedKey, String> {
+        let mut keys = self.load()?;
+        let pos = keys
+            .iter()
+            .position(|k| k.name == name)
+            .ok_or_else(|| format!("No API key named '{}'", name))?;
+        let removed = keys.remove(pos);
+        self.save(&keys)?;
+        Ok(removed)
+    }
+}
+
+/// Generate a fresh random key (32 bytes, hex).
+pub fn generate_key() -> String {
+    let bytes: [u8; 32] = rand::random();
+    hex::encode(bytes)
+}
+
+/// Replace the master key in `{data_dir}/api_key`.
+pub fn write_master_key(data_dir: &Path, key: &str) -> Result<(), String> {
+    state_file::write_private(&data_dir.join("api_key"), key.as_bytes())
+}
+
+/// `/tmp/craftobj.sock` + `ci` -> `/tmp/craftobj-ci.sock`
+fn scoped_socket_path(socket_path: &str, name: &str) -> String {
+    let stem = socket_path.strip_suffix(".sock").unwrap_or(socket_path);
+    format!("{}-{}.sock", stem, name)
+}
+
+fn validate_key_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 64 {
+        return Err("Key name must be 1-64 characters".to_string());
+    }
+    if name == "master" {
+        return Err("'master' is reserved".to_string());
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Key name may only contain letters, digits, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(name: &str) -> IssueKeyRequest {
+        IssueKeyRequest {
+            name: name.to_string(),
+            scopes: vec![KeyScope::DataRead],
+            expires_in_secs: None,
+            ws_port: None,
+            topics: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn corrupt_key_file_is_not_overwritten() {
+        let dir = std::env::temp_dir().join(format!("craftstudio-api-keys-corrupt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = KeyStore::new(&dir);
+        std::fs::write(dir.join("api_keys.json"), b"[{\"name\": \"ci\"").unwrap();
+
+        assert!(store.load().is_err());
+        assert!(store.issue("/tmp/craftobj.sock", request("ci2")).is_err());
+        assert!(store.revoke("ci").is_err());
+        assert_eq!(std::fs::read(dir.join("api_keys.json")).unwrap(), b"[{\"name\": \"ci\"");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("craftstudio-api-keys-mode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        KeyStore::new(&dir).issue("/tmp/craftobj.sock", request("ci")).unwrap();
+        write_master_key(&dir, &generate_key()).unwrap();
+        for name in ["api_keys.json", "api_key"] {
+            let mode = std::fs::metadata(dir.join(name)).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode, 0o600, "{}", name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+//! IPC call auditing and per-instance method policy.
+//!
+//! Every handler a listener registers is wrapped in an `AuditedHandler`, which checks the
+//! instance's `MethodPolicy` (and the listener's own `Gate`, if any) before dispatch and appends one JSON line per call to
+//! `{data_dir}/audit.log`. Lines are written by a thread of their own, so a call never
+//! waits on the disk. Its queue is bounded: when the writer falls behind, records are
+//! dropped and counted in the instance metrics. Past `MAX_LOG_BYTES` the log is rotated to
+//! `audit.log.1`, replacing the previous one. The policy is persisted in
+//! `{data_dir}/ipc_policy.json`.
+//!
+//! Records name the listener (API key) a call came in on and its transport. Each transport
+//! of a listener has handlers of its own (see `listeners`), so the wrapper knows which one
+//! a call used.
+
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use craftec_ipc::server::IpcHandler;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use crate::compat::canonical_method;
+use crate::metrics::InstanceMetrics;
+use crate::state_file;
+
+/// Records waiting for the writer thread before new ones are dropped.
+const AUDIT_QUEUE: usize = 4096;
+/// Size at which audit.log is rotated.
+const MAX_LOG_BYTES: u64 = 64 * 1024 * 1024;
+
+/// One audited IPC call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix milliseconds when the call was received
+    pub ts_ms: u64,
+    /// API key name of the listener the call arrived on (`master` for the instance key)
+    pub caller: String,
+    /// `socket`, `ws`, or `memory` for calls made in-process
+    #[serde(default)]
+    pub transport: String,
+    /// Fully qualified method, e.g. `data.publish`
+    pub method: String,
+    /// SHA-256 of the serialized params, hex; `None` when the call had no params
+    pub params_hash: Option<String>,
+    pub duration_ms: u64,
+    /// `ok`, `error` or `denied`
+    pub status: String,
+}
+
+/// Where the records of the current audit.log start.
+#[derive(Default)]
+struct LineIndex {
+    /// Records rotated out since the log was opened; record numbers keep counting from them
+    base: usize,
+    /// Byte offset of every complete line, so reads seek instead of rescanning the file
+    offsets: Vec<u64>,
+}
+
+/// Append-only audit file for one instance.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Lines waiting for the writer thread; `None` when the file couldn't be opened
+    writer: Mutex<Option<mpsc::SyncSender<String>>>,
+    index: Arc<Mutex<LineIndex>>,
+}
+
+impl AuditLog {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join("audit.log");
+        let index = Arc::new(Mutex::new(LineIndex::default()));
+        let writer = match Self::start_writer(&path, Arc::clone(&index)) {
+            Ok(tx) => Some(tx),
+            Err(e) => {
+                warn!("Failed to open audit log {}: {}", path.display(), e);
+                None
+            }
+        };
+        Self {
+            path,
+            writer: Mutex::new(writer),
+            index,
+        }
+    }
+
+    /// Index the existing lines and start the thread that appends new ones. It ends when
+    /// the log is dropped.
+    fn start_writer(path: &Path, index: Arc<Mutex<LineIndex>>) -> std::io::Result<mpsc::SyncSender<String>> {
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let mut end = 0u64;
+        {
+            let mut lines = index.lock().unwrap();
+            let mut reader = BufReader::new(&file);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                let n = reader.read_until(b'\n', &mut line)? as u64;
+                if n == 0 {
+                    break;
+                }
+                if line.ends_with(b"\n") {
+                    lines.offsets.push(end);
+                }
+                end += n;
+            }
+            // Finish a line cut short by a crash so the next record starts on its own line
+            if end > 0 && !line.ends_with(b"\n") {
+                (&file).write_all(b"\n")?;
+                end += 1;
+            }
+        }
+
+        let (tx, rx) = mpsc::sync_channel::<String>(AUDIT_QUEUE);
+        let path = path.to_path_buf();
+        std::thread::Builder::new().name("audit-log".to_string()).spawn(move || {
+            for line in rx {
+                if end > 0 && end + line.len() as u64 > MAX_LOG_BYTES {
+                    // Under the index lock, so a read never pairs offsets with the wrong file
+                    let mut lines = index.lock().unwrap();
+                    match rotate(&path) {
+                        Ok(fresh) => {
+                            file = fresh;
+                            end = 0;
+                            lines.base += lines.offsets.len();
+                            lines.offsets.clear();
+                        }
+                        Err(e) => warn!("Failed to rotate audit log {}: {}", path.display(), e),
+                    }
+                }
+                match file.write_all(line.as_bytes()) {
+                    Ok(()) => {
+                        index.lock().unwrap().offsets.push(end);
+                        end += line.len() as u64;
+                    }
+                    Err(e) => warn!("Failed to write audit record: {}", e),
+                }
+            }
+        })?;
+        Ok(tx)
+    }
+
+    /// Queue a record for the writer. Returns `false` when it was dropped instead.
+    pub fn append(&self, record: &AuditRecord) -> bool {
+        let Ok(mut line) = serde_json::to_string(record) else {
+            return false;
+        };
+        line.push('\n');
+        match self.writer.lock().unwrap().as_ref() {
+            Some(tx) => tx.try_send(line).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Read records starting at record `since`, at most `limit` of them. Reading from a
+    /// record that was rotated out starts at the oldest one still in audit.log.
+    pub fn read(&self, since: usize, limit: usize) -> Result<Vec<AuditRecord>, String> {
+        let (mut file, start, count) = {
+            let index = self.index.lock().unwrap();
+            let skip = since.saturating_sub(index.base);
+            let Some(start) = index.offsets.get(skip).copied() else {
+                return Ok(Vec::new());
+            };
+            let file = match File::open(&self.path) {
+                Ok(f) => f,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(format!("Failed to read audit log: {}", e)),
+            };
+            (file, start, limit.min(index.offsets.len() - skip))
+        };
+        file.seek(SeekFrom::Start(start))
+            .map_err(|e| format!("Failed to read audit log: {}", e))?;
+        Ok(BufReader::new(file)
+            .lines()
+            .take(count)
+            .map_while(Result::ok)
+            .filter_map(|l| serde_json::from_str(&l).ok())
+            .collect())
+    }
+}
+
+/// Move audit.log to audit.log.1 and open a fresh one.
+fn rotate(path: &Path) -> std::io::Result<File> {
+    fs::rename(path, path.with_extension("log.1"))?;
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Allow/deny lists of method patterns. A pattern is an exact method (`data.publish`),
+/// a namespace wildcard (`tunnel.*`) or `*`. Deny always wins; an empty allowlist
+/// allows everything not denied.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MethodPolicy {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Reject unprefixed legacy methods instead of mapping them to `data.*`
+    #[serde(default)]
+    pub strict_namespaces: bool,
+}
+
+impl MethodPolicy {
+    fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join("ipc_policy.json")
+    }
+
+    /// Load the policy for a data dir. A missing or corrupt file yields the open policy.
+    pub fn load(data_dir: &Path) -> Self {
+        fs::read_to_string(Self::path(data_dir))
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, data_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize IPC policy: {}", e))?;
+        state_file::write(&Self::path(data_dir), json.as_bytes())
+    }
+
+    pub fn permits(&self, method: &str) -> bool {
+        if self.deny.iter().any(|p| pattern_matches(p, method)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|p| pattern_matches(p, method))
+    }
+}
+
+pub(crate) fn pattern_matches(pattern: &str, method: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => method.starts_with(prefix),
+        None => pattern == method,
+    }
+}
+
+/// Audit sink, policy, metrics and concurrency cap shared by all listeners of an instance.
+#[derive(Clone)]
+pub struct CallGuard {
+    pub log: Arc<AuditLog>,
+    pub policy: Arc<RwLock<MethodPolicy>>,
+    pub metrics: Arc<InstanceMetrics>,
+    /// Slots for concurrently running requests; `None` is unlimited
+    pub slots: Option<Arc<Semaphore>>,
+}
+
+impl CallGuard {
+    pub fn open(data_dir: &Path, metrics: Arc<InstanceMetrics>, max_concurrent: Option<usize>) -> Self {
+        Self {
+            log: Arc::new(AuditLog::open(data_dir)),
+            policy: Arc::new(RwLock::new(MethodPolicy::load(data_dir))),
+            metrics,
+            slots: max_concurrent.map(|n| Arc::new(Semaphore::new(n))),
+        }
+    }
+}
+
+/// Per-listener check of a method (as the handler receives it) on top of the instance
+/// policy; the error says why the call is refused.
+pub type Gate = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// Wraps a namespace handler with policy checks and audit records.
+pub struct AuditedHandler {
+    /// Namespace the handler is registered under; empty for the default handler
+    namespace: &'static str,
+    caller: String,
+    transport: &'static str,
+    inner: Arc<dyn IpcHandler>,
+    guard: CallGuard,
+    gate: Option<Gate>,
+    /// Set when the listener the handler belongs to has been closed
+    closed: Arc<AtomicBool>,
+}
+
+impl AuditedHandler {
+    pub fn wrap(
+        namespace: &'static str,
+        caller: &str,
+        transport: &'static str,
+        inner: Arc<dyn IpcHandler>,
+        guard: &CallGuard,
+        gate: Option<Gate>,
+        closed: &Arc<AtomicBool>,
+    ) -> Arc<dyn IpcHandler> {
+        Arc::new(Self {
+            namespace,
+            caller: caller.to_string(),
+            transport,
+            inner,
+            guard: guard.clone(),
+            gate,
+            closed: Arc::clone(closed),
+        })
+    }
+}
+
+impl IpcHandler for AuditedHandler {
+    fn handle(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + '_>> {
+        // Legacy names are checked and recorded as the `data.*` method they stand for
+        let full_method = if self.namespace.is_empty() {
+            canonical_method(method).to_string()
+        } else {
+            format!("{}.{}", self.namespace, method)
+        };
+        let params_hash = params.as_ref().map(|p| hex::encode(Sha256::digest(p.to_string())));
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let refused = if self.closed.load(Ordering::SeqCst) {
+            Some(format!("API key '{}' is no longer valid", self.caller))
+        } else if let Some(Err(e)) = self.gate.as_ref().map(|gate| gate(method)) {
+            Some(e)
+        } else if !self.guard.policy.read().unwrap().permits(&full_method) {
+            Some(format!("Method '{}' is not allowed on this instance", full_method))
+        } else {
+            None
+        };
+        let permitted = refused.is_none();
+        let method = method.to_string();
+
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = if let Some(refused) = refused {
+                Err(refused)
+            } else {
+                // Waiting for a slot counts toward the request's duration. The semaphore
+                // is never closed, so `acquire` only returns once a slot is free.
+                let _slot = match &self.guard.slots {
+                    Some(slots) => slots.acquire().await.ok(),
+                    None => None,
+                };
+                self.inner.handle(&method, params).await
+            };
+            let status = match (&result, permitted) {
+                (_, false) => "denied",
+                (Ok(_), true) => "ok",
+                (Err(_), true) => "error",
+            };
+            let elapsed = started.elapsed();
+            self.guard.metrics.observe_ipc(self.namespace, status, elapsed);
+            let queued = self.guard.log.append(&AuditRecord {
+                ts_ms,
+                caller: self.caller.clone(),
+                transport: self.transport.to_string(),
+                method: full_method,
+                params_hash,
+                duration_ms: elapsed.as_millis() as u64,
+                status: status.to_string(),
+            });
+            if !queued {
+                self.guard.metrics.observe_audit_drop();
+            }
+            result
+        })
+    }
+}
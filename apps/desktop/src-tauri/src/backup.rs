@@ -0,0 +1,475 @@
+//! Instance backups as portable archives.
+//!
+//! A backup is a gzipped tar of the data dir. Its first entry is `backup.json`, which
+//! lists every file with its size and SHA-256, and the files follow under `data/`. Next to
+//! the archive, `<archive>.sha256` holds the archive's own hash in `sha256sum` format.
+//! Piece stores (`chunks`, `storage`) are only included on request. Caches and fault
+//! injection backups are never included, and neither are sockets. A `storage_path`
+//! outside the data dir is not followed.
+//!
+//! A running instance is stopped, and its tasks have ended, while its files are read, then
+//! started again with the config it was started with; it comes back under a new instance
+//! ID. Pausing its loops would not be enough, since IPC calls, jobs, folder sync and the VFS
+//! keep writing to the data dir. Restore checks both hashes, unpacks into a temporary dir next to
+//! the target, and moves it into place only when every file matches. It then starts the
+//! instance from there through the manager.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::api_keys::now_secs;
+use crate::daemon_manager::{DaemonConfig, DaemonInstance, DaemonManager};
+use crate::relocate::{self, restart_config};
+
+const FORMAT: &str = "craftstudio-backup/1";
+const MANIFEST_ENTRY: &str = "backup.json";
+const DATA_PREFIX: &str = "data";
+/// Piece stores, included only with `include_chunks`.
+const PIECE_DIRS: &[&str] = &["chunks", "storage"];
+/// Never worth carrying over.
+const EXCLUDED_DIRS: &[&str] = &["content-cache", ".faults"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFile {
+    /// Relative to the data dir, `/`-separated
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    pub mode: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format: String,
+    pub created_at: u64,
+    /// Data dir the backup was taken from
+    pub source_data_dir: String,
+    pub include_chunks: bool,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub archive: String,
+    /// SHA-256 of the archive, also written to `<archive>.sha256`
+    pub sha256: String,
+    pub files: usize,
+    pub bytes: u64,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackedUpInstance {
+    pub backup: BackupInfo,
+    /// The instance as restarted after the backup
+    pub instance: DaemonInstance,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoredInstance {
+    pub instance: DaemonInstance,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+fn sha256_reader(mut reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    sha256_reader(BufReader::new(file)).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_meta: &std::fs::Metadata) -> u32 {
+    0o644
+}
+
+/// Regular files of the data dir that go into a backup.
+fn collect(data_dir: &Path, include_chunks: bool) -> Result<Vec<BackupFile>, String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(data_dir.join(&dir))
+            .map_err(|e| format!("Failed to read {}: {}", data_dir.join(&dir).display(), e))?;
+        for entry in entries.flatten() {
+            let rel = dir.join(entry.file_name());
+            let Ok(meta) = entry.metadata() else { continue };
+            if meta.is_dir() {
+                let top = dir.as_os_str().is_empty();
+                let name = entry.file_name().to_string_lossy().to_string();
+                let skipped = EXCLUDED_DIRS.contains(&name.as_str())
+                    || (!include_chunks && PIECE_DIRS.contains(&name.as_str()));
+                if !(top && skipped) {
+                    dirs.push(rel);
+                }
+            } else if meta.is_file() {
+                let path = rel.to_string_lossy().replace('\\', "/");
+                files.push(BackupFile {
+                    sha256: sha256_file(&data_dir.join(&rel))?,
+                    size: meta.len(),
+                    mode: file_mode(&meta),
+                    path,
+                });
+            }
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Write a backup of `data_dir` to `archive`. The data dir must not change meanwhile.
+pub fn create_backup(data_dir: &Path, archive: &Path, include_chunks: bool) -> Result<BackupInfo, String> {
+    let files = collect(data_dir, include_chunks)?;
+    let created_at = now_secs();
+    let manifest = BackupManifest {
+        format: FORMAT.to_string(),
+        created_at,
+        source_data_dir: data_dir.display().to_string(),
+        include_chunks,
+        files,
+    };
+    if let Some(parent) = archive.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+
+    let write = || -> std::io::Result<()> {
+        let out = File::create(archive)?;
+        let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+        let raw = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(raw.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(created_at);
+        tar.append_data(&mut header, MANIFEST_ENTRY, raw.as_slice())?;
+        for file in &manifest.files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(file.size);
+            header.set_mode(file.mode);
+            header.set_mtime(created_at);
+            let source = File::open(data_dir.join(&file.path))?;
+            // A file that grew since it was hashed is cut at the hashed size and fails restore
+            tar.append_data(&mut header, format!("{}/{}", DATA_PREFIX, file.path), source.take(file.size))?;
+        }
+        tar.into_inner()?.finish()?.flush()
+    };
+    if let Err(e) = write() {
+        let _ = std::fs::remove_file(archive);
+        return Err(format!("Failed to write backup {}: {}", archive.display(), e));
+    }
+
+    let sha256 = sha256_file(archive)?;
+    let name = archive.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    std::fs::write(checksum_path(archive), format!("{}  {}\n", sha256, name))
+        .map_err(|e| format!("Failed to write checksum for {}: {}", archive.display(), e))?;
+    Ok(BackupInfo {
+        archive: archive.display().to_string(),
+        sha256,
+        files: manifest.files.len(),
+        bytes: manifest.files.iter().map(|f| f.size).sum(),
+        created_at,
+    })
+}
+
+fn checksum_path(archive: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.sha256", archive.display()))
+}
+
+/// `data/<rel>` entry name to a relative path that stays inside the target.
+fn entry_path(name: &Path) -> Option<String> {
+    let rest = name.strip_prefix(DATA_PREFIX).ok()?;
+    let mut parts = Vec::new();
+    for component in rest.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Check and unpack `archive` into `target`, which must not exist yet.
+pub fn restore_backup(archive: &Path, target: &Path) -> Result<BackupManifest, String> {
+    if target.exists() && std::fs::read_dir(target).map_or(true, |mut entries| entries.next().is_some()) {
+        return Err(format!("{} already exists and is not empty", target.display()));
+    }
+    let checksum = checksum_path(archive);
+    if checksum.exists() {
+        let expected = std::fs::read_to_string(&checksum).map_err(|e| e.to_string())?;
+        let expected = expected.split_whitespace().next().unwrap_or_default();
+        if sha256_file(archive)? != expected {
+            return Err(format!("{} does not match its checksum", archive.display()));
+        }
+    }
+
+    let staging = PathBuf::from(format!("{}.restore-{}", target.display(), now_secs()));
+    let unpacked = unpack(archive, &staging);
+    let manifest = match unpacked {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
+    let _ = std::fs::remove_dir(target);
+    std::fs::rename(&staging, target).map_err(|e| {
+        let _ = std::fs::remove_dir_all(&staging);
+        format!("Failed to move restored data to {}: {}", target.display(), e)
+    })?;
+    Ok(manifest)
+}
+
+fn open_archive(archive: &Path) -> Result<tar::Archive<GzDecoder<BufReader<File>>>, String> {
+    let file = File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    Ok(tar::Archive::new(GzDecoder::new(BufReader::new(file))))
+}
+
+/// The manifest, which must be the first entry.
+fn read_manifest<R: Read>(entries: &mut tar::Entries<'_, R>) -> Result<BackupManifest, String> {
+    let mut first = entries
+        .next()
+        .ok_or("Backup is empty")?
+        .map_err(|e| format!("Invalid backup: {}", e))?;
+    if first.path().map_err(|e| e.to_string())?.as_ref() != Path::new(MANIFEST_ENTRY) {
+        return Err(format!("Backup does not start with {}", MANIFEST_ENTRY));
+    }
+    let mut raw = Vec::new();
+    first.read_to_end(&mut raw).map_err(|e| e.to_string())?;
+    let manifest: BackupManifest =
+        serde_json::from_slice(&raw).map_err(|e| format!("Invalid {}: {}", MANIFEST_ENTRY, e))?;
+    if manifest.format != FORMAT {
+        return Err(format!("Unsupported backup format '{}'", manifest.format));
+    }
+    Ok(manifest)
+}
+
+fn unpack(archive: &Path, staging: &Path) -> Result<BackupManifest, String> {
+    let mut tar = open_archive(archive)?;
+    let mut entries = tar.entries().map_err(|e| format!("Invalid backup: {}", e))?;
+    let manifest = read_manifest(&mut entries)?;
+
+    std::fs::create_dir_all(staging).map_err(|e| format!("Failed to create {}: {}", staging.display(), e))?;
+    let mut seen = 0;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("Invalid backup: {}", e))?;
+        let name = entry.path().map_err(|e| e.to_string())?.into_owned();
+        let rel = entry_path(&name).ok_or_else(|| format!("Unexpected entry {} in backup", name.display()))?;
+        let expected = manifest
+            .files
+            .iter()
+            .find(|f| f.path == rel)
+            .ok_or_else(|| format!("{} is not listed in {}", rel, MANIFEST_ENTRY))?;
+
+        let dest = staging.join(&rel);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let mut out = File::create(&dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = entry.read(&mut buf).map_err(|e| format!("Failed to read {}: {}", rel, e))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            out.write_all(&buf[..n]).map_err(|e| format!("Failed to write {}: {}", dest.display(), e))?;
+            size += n as u64;
+        }
+        if size != expected.size || hex::encode(hasher.finalize()) != expected.sha256 {
+            return Err(format!("{} in the backup is corrupt", rel));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(expected.mode));
+        }
+        seen += 1;
+    }
+    if seen != manifest.files.len() {
+        return Err(format!("Backup holds {} of {} listed files", seen, manifest.files.len()));
+    }
+    Ok(manifest)
+}
+
+impl DaemonManager {
+    /// Back up a running instance. It is stopped while its files are read and started again
+    /// afterwards, whether or not the backup succeeded.
+    pub async fn backup_instance(
+        &self,
+        pid: u32,
+        archive: &str,
+        include_chunks: bool,
+    ) -> Result<BackedUpInstance, String> {
+        let launch = self.with_daemon(pid, |d| d.launch.clone())?;
+        let instance = self.running_ipc(pid)?.2;
+        let data_dir = PathBuf::from(&instance.data_dir);
+        self.stop_and_wait(pid).await?;
+
+        let archive = PathBuf::from(archive);
+        let source = data_dir.clone();
+        let result = tokio::task::spawn_blocking(move || create_backup(&source, &archive, include_chunks))
+            .await
+            .map_err(|e| format!("Backup task failed: {}", e))
+            .and_then(|r| r);
+        let restarted = self
+            .start(restart_config(&launch, &instance, &data_dir, &data_dir))
+            .map_err(|e| format!("Instance {} failed to start again after the backup: {}", pid, e));
+
+        let backup = match result {
+            Ok(backup) => backup,
+            Err(e) => {
+                return Err(match restarted {
+                    Ok(restarted) => format!("{} (instance restarted as {})", e, restarted.pid),
+                    Err(restart) => format!("{}; {}", e, restart),
+                })
+            }
+        };
+        let instance = restarted?;
+        info!(
+            "Instance {} backed up to {} ({} files); now instance {}",
+            pid, backup.archive, backup.files, instance.pid
+        );
+        Ok(BackedUpInstance { backup, instance })
+    }
+
+    /// Restore a backup into `data_dir` (the original location when absent) and start the
+    /// instance from it.
+    pub async fn restore_instance(
+        &self,
+        archive: &str,
+        data_dir: Option<String>,
+        config: DaemonConfig,
+    ) -> Result<RestoredInstance, String> {
+        let archive = PathBuf::from(archive);
+        let target = data_dir.map(PathBuf::from);
+        let (manifest, target) = tokio::task::spawn_blocking(move || {
+            let target = match target {
+                Some(target) => target,
+                None => peek_source(&archive)?,
+            };
+            restore_backup(&archive, &target).map(|manifest| (manifest, target))
+        })
+        .await
+        .map_err(|e| format!("Restore task failed: {}", e))??;
+
+        let source = PathBuf::from(&manifest.source_data_dir);
+        if source != target {
+            relocate::rewrite_config_paths(&target, &source)?;
+        }
+        let instance = self.start(DaemonConfig {
+            data_dir: Some(target.display().to_string()),
+            ..config
+        })?;
+        info!("Restored {} into {} as instance {}", manifest.source_data_dir, target.display(), instance.pid);
+        Ok(RestoredInstance {
+            instance,
+            files: manifest.files.len(),
+            bytes: manifest.files.iter().map(|f| f.size).sum(),
+        })
+    }
+}
+
+/// Data dir a backup was taken from, read from its manifest entry.
+fn peek_source(archive: &Path) -> Result<PathBuf, String> {
+    let mut tar = open_archive(archive)?;
+    let mut entries = tar.entries().map_err(|e| format!("Invalid backup: {}", e))?;
+    Ok(PathBuf::from(read_manifest(&mut entries)?.source_data_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("craftstudio-backup-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn write(path: &Path, bytes: &[u8]) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn backup_round_trips_without_excluded_dirs() {
+        let root = scratch("round-trip");
+        let data = root.join("data");
+        write(&data.join("config.json"), b"{}");
+        write(&data.join("nested/file.bin"), &vec![7u8; 100_000]);
+        write(&data.join("chunks/piece"), b"piece");
+        write(&data.join("content-cache/cached"), b"cached");
+
+        let archive = root.join("out/instance.tar.gz");
+        let info = create_backup(&data, &archive, false).unwrap();
+        assert_eq!(info.files, 2);
+
+        let target = root.join("restored");
+        let manifest = restore_backup(&archive, &target).unwrap();
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(std::fs::read(target.join("config.json")).unwrap(), b"{}");
+        assert_eq!(std::fs::read(target.join("nested/file.bin")).unwrap(), vec![7u8; 100_000]);
+        assert!(!target.join("chunks").exists());
+        assert!(!target.join("content-cache").exists());
+
+        // Restoring over existing data is refused
+        assert!(restore_backup(&archive, &target).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn archive_that_fails_its_checksum_is_not_restored() {
+        let root = scratch("checksum");
+        let data = root.join("data");
+        write(&data.join("config.json"), b"{}");
+        let archive = root.join("instance.tar.gz");
+        create_backup(&data, &archive, false).unwrap();
+
+        let mut raw = std::fs::read(&archive).unwrap();
+        let last = raw.len() - 1;
+        raw[last] ^= 0xFF;
+        std::fs::write(&archive, raw).unwrap();
+
+        let target = root.join("restored");
+        let err = restore_backup(&archive, &target).unwrap_err();
+        assert!(err.contains("checksum"), "got: {}", err);
+        assert!(!target.exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn entry_paths_stay_inside_the_target() {
+        assert_eq!(entry_path(Path::new("data/a/b.json")).as_deref(), Some("a/b.json"));
+        assert_eq!(entry_path(Path::new("data/../escape")), None);
+        assert_eq!(entry_path(Path::new("data/a/../../escape")), None);
+        assert_eq!(entry_path(Path::new("/data/etc/passwd")), None);
+        assert_eq!(entry_path(Path::new("other/file")), None);
+        assert_eq!(entry_path(Path::new("data")), None);
+    }
+}
@@ -0,0 +1,194 @@
+//! Bootstrap peer management.
+//!
+//! Boot peers come from two places. Configured peers are the user's list in
+//! `{data_dir}/config.json` and are only changed through these commands or the config
+//! editor. Ephemeral peers are the running sibling instances (or an explicit list from the
+//! caller) and are handed to the daemon for one run without being written anywhere.
+//! Every entry is a full multiaddr ending in `/p2p/<peer_id>`, so the dialer can verify
+//! who answered. Older versions wrote bare sibling addresses into config.json; those are
+//! dropped from the file when the instance starts.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::daemon_manager::{loopback_addr, DaemonManager};
+use crate::swarm_ctl::SwarmControl;
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+const TEST_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootPeers {
+    /// Persisted in config.json
+    pub configured: Vec<String>,
+    /// Running siblings the instance would boot from this run
+    pub ephemeral: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootPeerTest {
+    pub addr: String,
+    pub peer_id: String,
+    pub reachable: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Parse a boot peer address and return its peer ID. The address must end in `/p2p/<id>`.
+pub fn validate_boot_peer(addr: &str) -> Result<String, String> {
+    let parsed: Multiaddr = addr.parse().map_err(|e| format!("Invalid multiaddr '{}': {}", addr, e))?;
+    match parsed.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Ok(peer_id.to_string()),
+        _ => Err(format!("Boot peer '{}' must end in /p2p/<peer_id>", addr)),
+    }
+}
+
+/// Remove configured peers `validate_boot_peer` rejects and save config.json if any were
+/// removed. `config` is changed even if saving fails.
+pub(crate) fn drop_invalid_boot_peers(
+    data_dir: &Path,
+    config: &mut craftobj_daemon::config::DaemonConfig,
+) -> Result<(), String> {
+    let before = config.boot_peers.len();
+    config.boot_peers.retain(|peer| match validate_boot_peer(peer) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Dropping boot peer from config.json: {}", e);
+            false
+        }
+    });
+    if config.boot_peers.len() == before {
+        return Ok(());
+    }
+    save_config(data_dir, config)
+}
+
+fn load_config(data_dir: &Path) -> craftobj_daemon::config::DaemonConfig {
+    craftobj_daemon::config::DaemonConfig::load_from(&data_dir.join("config.json"))
+}
+
+fn save_config(data_dir: &Path, config: &craftobj_daemon::config::DaemonConfig) -> Result<(), String> {
+    std::fs::create_dir_all(data_dir).map_err(|e| format!("Failed to create data dir: {}", e))?;
+    config
+        .save_to(&data_dir.join("config.json"))
+        .map_err(|e| format!("Failed to write daemon config: {}", e))
+}
+
+impl DaemonManager {
+    pub fn list_boot_peers(&self, data_dir: &str) -> BootPeers {
+        BootPeers {
+            configured: load_config(Path::new(data_dir)).boot_peers,
+            ephemeral: self.sibling_boot_peers(Some(data_dir)),
+        }
+    }
+
+    /// Add a peer to the configured list. A running instance on `data_dir` dials it right away.
+    pub async fn add_boot_peer(&self, data_dir: &str, addr: &str) -> Result<BootPeers, String> {
+        validate_boot_peer(addr)?;
+        let dir = Path::new(data_dir);
+        let mut config = load_config(dir);
+        if !config.boot_peers.iter().any(|p| p == addr) {
+            config.boot_peers.push(addr.to_string());
+            save_config(dir, &config)?;
+            info!("Added boot peer {} to {}", addr, data_dir);
+        }
+        if let Some(pid) = self.pid_for_data_dir(data_dir) {
+            if let Ok((ipc, _, _)) = self.running_ipc(pid) {
+                SwarmControl::new(ipc.handler).dial(addr).await?;
+            }
+        }
+        Ok(self.list_boot_peers(data_dir))
+    }
+
+    pub fn remove_boot_peer(&self, data_dir: &str, addr: &str) -> Result<BootPeers, String> {
+        let dir = Path::new(data_dir);
+        let mut config = load_config(dir);
+        let before = config.boot_peers.len();
+        config.boot_peers.retain(|p| p != addr);
+        if config.boot_peers.len() == before {
+            return Err(format!("{} is not a configured boot peer", addr));
+        }
+        save_config(dir, &config)?;
+        info!("Removed boot peer {} from {}", addr, data_dir);
+        Ok(self.list_boot_peers(data_dir))
+    }
+
+    /// Dial `addr` from a running instance and wait for the connection.
+    pub async fn test_boot_peer(&self, pid: u32, addr: &str) -> Result<BootPeerTest, String> {
+        let peer_id = validate_boot_peer(addr)?;
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        let swarm = SwarmControl::new(ipc.handler);
+        let mut result = BootPeerTest {
+            addr: addr.to_string(),
+            peer_id: peer_id.clone(),
+            reachable: false,
+            latency_ms: None,
+            error: None,
+        };
+
+        let started = Instant::now();
+        if let Err(e) = swarm.dial(addr).await {
+            result.error = Some(e);
+            return Ok(result);
+        }
+        while started.elapsed() < TEST_TIMEOUT {
+            if swarm.connected_peers().await?.contains(&peer_id) {
+                result.reachable = true;
+                result.latency_ms = Some(started.elapsed().as_millis() as u64);
+                return Ok(result);
+            }
+            tokio::time::sleep(TEST_POLL_INTERVAL).await;
+        }
+        result.error = Some(format!("No connection after {}s", TEST_TIMEOUT.as_secs()));
+        Ok(result)
+    }
+
+    /// Dialable addresses of running instances, excluding the one on `except_data_dir` and
+    /// isolated test nodes.
+    pub(crate) fn sibling_boot_peers(&self, except_data_dir: Option<&str>) -> Vec<String> {
+        self.list()
+            .iter()
+            .filter(|d| Some(d.data_dir.as_str()) != except_data_dir)
+            .filter(|d| !self.with_daemon(d.pid, |m| m.launch.isolated).unwrap_or(false))
+            .map(loopback_addr)
+            .collect()
+    }
+
+    fn pid_for_data_dir(&self, data_dir: &str) -> Option<u32> {
+        self.list().into_iter().find(|d| d.data_dir == data_dir).map(|d| d.pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOTSTRAP: &str = "/ip4/104.131.131.82/tcp/4001/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ";
+
+    #[test]
+    fn validates_peer_suffix() {
+        assert_eq!(validate_boot_peer(BOOTSTRAP).unwrap(), "QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ");
+        assert!(validate_boot_peer("/ip4/127.0.0.1/tcp/4001").is_err());
+        assert!(validate_boot_peer("not a multiaddr").is_err());
+    }
+
+    #[test]
+    fn legacy_sibling_addresses_are_dropped_from_config() {
+        let dir = std::env::temp_dir().join(format!("craftstudio-boot-peers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = load_config(&dir);
+        config.boot_peers = vec!["/ip4/127.0.0.1/tcp/4002".to_string(), BOOTSTRAP.to_string()];
+        save_config(&dir, &config).unwrap();
+
+        let mut config = load_config(&dir);
+        drop_invalid_boot_peers(&dir, &mut config).unwrap();
+        assert_eq!(config.boot_peers, [BOOTSTRAP.to_string()]);
+        assert_eq!(load_config(&dir).boot_peers, [BOOTSTRAP.to_string()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
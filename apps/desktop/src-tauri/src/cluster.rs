@@ -0,0 +1,343 @@
+//! Local multi-node test clusters.
+//!
+//! `DaemonManager::start_cluster` launches N in-process instances with fresh data dirs under
+//! the system temp dir, wires them in a full mesh or a star via explicit boot peers, and
+//! waits until every node sees the peers its topology promises. `stop_cluster` stops the
+//! nodes, waits until their tasks have ended and then deletes their data dirs.
+//!
+//! Nodes are started `isolated`: they listen on loopback with ports the OS picks, are never
+//! the primary instance, and the user's own instances don't boot from them. A cluster that
+//! fails to come up, or whose start is cancelled, is torn down again in the background.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::api_keys::now_secs;
+use crate::daemon_manager::{loopback_addr, DaemonConfig, DaemonInstance, DaemonManager};
+use crate::swarm_ctl::SwarmControl;
+
+const PEER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_READY_TIMEOUT_SECS: u64 = 60;
+
+/// Which capabilities cluster nodes get.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterProfile {
+    /// Every node is a client
+    Client,
+    /// Every node is a client and storage provider
+    Storage,
+    /// The first half of the nodes store, the rest are clients
+    Mixed,
+}
+
+impl ClusterProfile {
+    fn capabilities(self, index: u32, nodes: u32) -> Vec<String> {
+        let storage = match self {
+            ClusterProfile::Client => false,
+            ClusterProfile::Storage => true,
+            ClusterProfile::Mixed => index < nodes.div_ceil(2),
+        };
+        let mut caps = vec!["client".to_string()];
+        if storage {
+            caps.push("storage".to_string());
+        }
+        caps
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "client" => Ok(ClusterProfile::Client),
+            "storage" => Ok(ClusterProfile::Storage),
+            "mixed" => Ok(ClusterProfile::Mixed),
+            other => Err(format!("Unknown cluster profile '{}' (client, storage, mixed)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    /// Every node boots from every earlier node
+    FullMesh,
+    /// Every node boots from node 0 only
+    Star,
+}
+
+impl Topology {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "full_mesh" | "mesh" => Ok(Topology::FullMesh),
+            "star" => Ok(Topology::Star),
+            other => Err(format!("Unknown topology '{}' (mesh, star)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClusterSpec {
+    pub nodes: u32,
+    pub profile: ClusterProfile,
+    pub topology: Topology,
+    /// How long to wait for the nodes to see each other (default 60s)
+    pub ready_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClusterInfo {
+    pub id: u32,
+    pub topology: Topology,
+    pub data_root: String,
+    pub nodes: Vec<DaemonInstance>,
+}
+
+/// A running cluster tracked by the manager.
+pub struct Cluster {
+    pub info: ClusterInfo,
+}
+
+/// A cluster being started. Dropping it while armed (on error, or when the future running
+/// `start_cluster` is dropped) stops every node started so far and removes the data.
+struct PendingCluster<'a> {
+    manager: &'a DaemonManager,
+    info: ClusterInfo,
+    armed: bool,
+}
+
+impl Drop for PendingCluster<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            warn!("Cluster {} did not come up; tearing it down", self.info.id);
+            self.manager.runtime.spawn(self.manager.teardown(&self.info));
+        }
+    }
+}
+
+impl DaemonManager {
+    /// Launch `spec.nodes` isolated instances and wait until they are connected.
+    /// On failure every node started so far is torn down again.
+    pub async fn start_cluster(&self, spec: ClusterSpec) -> Result<ClusterInfo, String> {
+        if spec.nodes < 2 {
+            return Err("A cluster needs at least 2 nodes".to_string());
+        }
+        let id = {
+            let mut next = self.next_cluster_id.lock().unwrap();
+            *next += 1;
+            *next - 1
+        };
+        let data_root = std::env::temp_dir().join(format!("craftstudio-cluster-{}-{}", id, now_secs()));
+        let mut pending = PendingCluster {
+            manager: self,
+            info: ClusterInfo {
+                id,
+                topology: spec.topology,
+                data_root: data_root.to_string_lossy().to_string(),
+                nodes: Vec::new(),
+            },
+            armed: true,
+        };
+
+        let timeout = Duration::from_secs(spec.ready_timeout_secs.unwrap_or(DEFAULT_READY_TIMEOUT_SECS));
+        let deadline = Instant::now() + timeout;
+        self.launch_nodes(&spec, &data_root, &mut pending.info.nodes, deadline).await?;
+        self.wait_for_topology(&pending.info, deadline, timeout).await?;
+
+        pending.armed = false;
+        let info = pending.info.clone();
+        info!("Cluster {} ready: {} nodes ({:?})", id, info.nodes.len(), spec.topology);
+        self.clusters.lock().unwrap().push(Cluster { info: info.clone() });
+        Ok(info)
+    }
+
+    /// Stop every node of a cluster and delete its data dirs once the nodes have stopped.
+    pub async fn stop_cluster(&self, id: u32) -> Result<(), String> {
+        let cluster = {
+            let mut clusters = self.clusters.lock().unwrap();
+            let pos = clusters
+                .iter()
+                .position(|c| c.info.id == id)
+                .ok_or_else(|| format!("No cluster with ID {}", id))?;
+            clusters.remove(pos)
+        };
+        self.teardown(&cluster.info).await;
+        Ok(())
+    }
+
+    pub fn list_clusters(&self) -> Vec<ClusterInfo> {
+        self.clusters.lock().unwrap().iter().map(|c| c.info.clone()).collect()
+    }
+
+    /// Start the nodes one by one. Each node's bound address is awaited before the next one
+    /// starts, since later nodes boot from it.
+    async fn launch_nodes(
+        &self,
+        spec: &ClusterSpec,
+        data_root: &std::path::Path,
+        nodes: &mut Vec<DaemonInstance>,
+        deadline: Instant,
+    ) -> Result<(), String> {
+        for i in 0..spec.nodes {
+            let data_dir = data_root.join(format!("node-{}", i));
+            let _ = std::fs::remove_dir_all(&data_dir);
+            std::fs::create_dir_all(&data_dir)
+                .map_err(|e| format!("Failed to create {}: {}", data_dir.display(), e))?;
+
+            let boot_peers = match spec.topology {
+                Topology::FullMesh => nodes.iter().map(loopback_addr).collect(),
+                Topology::Star => nodes.first().map(loopback_addr).into_iter().collect(),
+            };
+            let instance = self.start(DaemonConfig {
+                data_dir: Some(data_dir.to_string_lossy().to_string()),
+                socket_path: Some(data_root.join(format!("node-{}.sock", i)).to_string_lossy().to_string()),
+                capabilities: Some(spec.profile.capabilities(i, spec.nodes)),
+                boot_peers: Some(boot_peers),
+                isolated: true,
+                ..Default::default()
+            })?;
+            let pid = instance.pid;
+            nodes.push(instance);
+            let bound = loop {
+                match self.list().into_iter().find(|d| d.pid == pid) {
+                    Some(node) if !node.bound_addrs.is_empty() => break node,
+                    Some(_) if Instant::now() < deadline => tokio::time::sleep(PEER_POLL_INTERVAL).await,
+                    Some(_) => return Err(format!("Cluster node {} never bound a listen address", i)),
+                    None => return Err(format!("Cluster node {} exited while starting", i)),
+                }
+            };
+            *nodes.last_mut().unwrap() = bound;
+        }
+        Ok(())
+    }
+
+    /// Poll `connected_peers` on every node until the topology is satisfied.
+    async fn wait_for_topology(&self, info: &ClusterInfo, deadline: Instant, timeout: Duration) -> Result<(), String> {
+        let hub = &info.nodes[0].peer_id;
+        loop {
+            let mut pending = Vec::new();
+            for (i, node) in info.nodes.iter().enumerate() {
+                let expected: Vec<&String> = match info.topology {
+                    Topology::FullMesh => info.nodes.iter().filter(|n| n.pid != node.pid).map(|n| &n.peer_id).collect(),
+                    Topology::Star if i == 0 => info.nodes[1..].iter().map(|n| &n.peer_id).collect(),
+                    Topology::Star => vec![hub],
+                };
+                let seen = self.connected_peer_ids(node.pid).await;
+                if !expected.iter().all(|p| seen.contains(*p)) {
+                    pending.push(node.pid);
+                }
+            }
+            if pending.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "Cluster {} not connected after {}s; waiting on instances {:?}",
+                    info.id,
+                    timeout.as_secs(),
+                    pending
+                ));
+            }
+            tokio::time::sleep(PEER_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Peer IDs an instance reports as connected; empty while it is still starting.
+    pub(crate) async fn connected_peer_ids(&self, pid: u32) -> HashSet<String> {
+        let Ok((ipc, _, _)) = self.running_ipc(pid) else {
+            return HashSet::new();
+        };
+        SwarmControl::new(ipc.handler).connected_peers().await.unwrap_or_default()
+    }
+
+    /// Stop the nodes right away; the returned future waits until their tasks have ended,
+    /// so nothing writes to the data dirs any more, and then deletes them.
+    fn teardown(&self, info: &ClusterInfo) -> impl Future<Output = ()> + Send + 'static {
+        let mut stopping = Vec::new();
+        for node in &info.nodes {
+            match self.stop_detached(node.pid) {
+                Ok(stopped) => stopping.push(stopped),
+                Err(e) => warn!("Cluster {}: {}", info.id, e),
+            }
+        }
+        let root = PathBuf::from(&info.data_root);
+        async move {
+            for stopped in stopping {
+                stopped.await;
+            }
+            if let Err(e) = std::fs::remove_dir_all(&root) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to remove cluster data {}: {}", root.display(), e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn profiles_assign_storage() {
+        let storage = |profile: ClusterProfile, nodes: u32| {
+            (0..nodes)
+                .filter(|&i| profile.capabilities(i, nodes).contains(&"storage".to_string()))
+                .count()
+        };
+        assert_eq!(storage(ClusterProfile::Client, 4), 0);
+        assert_eq!(storage(ClusterProfile::Storage, 4), 4);
+        assert_eq!(storage(ClusterProfile::Mixed, 5), 3);
+        assert_eq!(ClusterProfile::Mixed.capabilities(4, 5), ["client".to_string()]);
+    }
+
+    #[test]
+    fn parses_names() {
+        assert!(matches!(ClusterProfile::parse("mixed"), Ok(ClusterProfile::Mixed)));
+        assert!(ClusterProfile::parse("all").is_err());
+        assert_eq!(Topology::parse("mesh"), Ok(Topology::FullMesh));
+        assert_eq!(Topology::parse("full_mesh"), Ok(Topology::FullMesh));
+        assert_eq!(Topology::parse("star"), Ok(Topology::Star));
+        assert!(Topology::parse("ring").is_err());
+    }
+
+    #[test]
+    fn undersized_and_unknown_clusters_are_refused() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let manager = DaemonManager::new_in_memory(Arc::new(Mutex::new(HashMap::new())), rt.handle().clone());
+        rt.block_on(async {
+            let spec = ClusterSpec {
+                nodes: 1,
+                profile: ClusterProfile::Client,
+                topology: Topology::Star,
+                ready_timeout_secs: None,
+            };
+            assert!(manager.start_cluster(spec).await.is_err());
+            assert!(manager.stop_cluster(7).await.is_err());
+        });
+    }
+
+    #[test]
+    fn teardown_removes_the_data_root_after_the_nodes_stop() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let manager = DaemonManager::new_in_memory(Arc::new(Mutex::new(HashMap::new())), rt.handle().clone());
+        let root = std::env::temp_dir().join(format!("craftstudio-cluster-teardown-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("node-0")).unwrap();
+        let info = ClusterInfo {
+            id: 0,
+            topology: Topology::Star,
+            data_root: root.to_string_lossy().to_string(),
+            nodes: Vec::new(),
+        };
+
+        let teardown = manager.teardown(&info);
+        assert!(root.exists(), "nothing is removed before the nodes have stopped");
+        rt.block_on(teardown);
+        assert!(!root.exists());
+    }
+}
@@ -167,3 +167,11 @@ pub fn pick_file() -> Option<String> {
         .pick_file()?;
     Some(file.to_string_lossy().into_owned())
 }
+
+#[tauri::command]
+pub fn pick_folder() -> Option<String> {
+    let folder = rfd::FileDialog::new()
+        .set_title("Select folder to publish")
+        .pick_folder()?;
+    Some(folder.to_string_lossy().into_owned())
+}
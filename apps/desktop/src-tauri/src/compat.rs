@@ -0,0 +1,420 @@
+//! Compatibility layer for unnamespaced IPC methods.
+//!
+//! Older clients call CraftOBJ methods without the `data.` prefix (`publish`, `list`, ...).
+//! Instead of forwarding every unprefixed method to CraftOBJ, `LegacyHandler` maps the
+//! known legacy names to their `data.*` equivalents, counts each use, and answers anything
+//! else with "Method not found" plus suggestions. With `strict_namespaces` set in the
+//! instance's IPC policy, legacy names are rejected too. Counts are kept in
+//! `{data_dir}/legacy_calls.json` across restarts. Method policy and audit records see a
+//! legacy call under its `data.*` name, so a rule on `data.publish` also covers `publish`.
+//!
+//! `IpcHandler` can only fail with a message, which the server sends with its generic error
+//! code. These errors always start with `Method not found:`, and the app's client
+//! (`services/daemon.ts`) turns them into JSON-RPC's -32601 by that prefix.
+//!
+//! The name tables below mirror what CraftOBJ serves. When CraftOBJ itself answers a name
+//! from them with "method not found", the table is stale and a warning names the entry.
+//! `SwarmControl` calls CraftOBJ directly rather than through `LegacyHandler`, so its
+//! methods are listed in `CORE_METHODS` and checked with `check_core`.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use craftec_ipc::server::IpcHandler;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::audit::CallGuard;
+
+/// Legacy unprefixed name → the `data.*` method it is equivalent to.
+const LEGACY_METHODS: &[(&str, &str)] = &[
+    ("publish", "data.publish"),
+    ("fetch", "data.fetch"),
+    ("list", "data.list"),
+    ("status", "data.status"),
+    ("providers", "data.providers"),
+    ("extend", "data.extend"),
+    ("remove", "data.remove"),
+    ("delete_local", "data.delete_local"),
+];
+
+/// Unprefixed methods CraftOBJ serves that have no namespaced equivalent yet.
+const CORE_METHODS: &[&str] = &[
+    "connected_peers",
+    "shutdown",
+    "peers",
+    "get-config",
+    "set-config",
+    "connect",
+    "disconnect_peer",
+    "ban_peer",
+    "unban_peer",
+    "set_bandwidth_limit",
+    "bandwidth_stats",
+    "nat_status",
+];
+
+/// `CORE_METHODS` entries found stale by `check_core`, warned about once per process.
+static STALE_CORE: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Namespaces that have no handler of their own and are served by CraftOBJ directly.
+const CRAFTOBJ_NAMESPACES: &[&str] = &[
+    "node",
+    "network",
+    "content",
+    "access",
+    "settlement",
+    "receipts",
+    "receipt",
+    "channel",
+];
+
+/// Namespaced methods offered as suggestions for unknown or mis-namespaced calls.
+const KNOWN_NAMESPACED: &[&str] = &[
+    "tunnel.status",
+    "tunnel.connect",
+    "tunnel.disconnect",
+    "tunnel.set_exit",
+    "tunnel.get_exits",
+    "tunnel.get_peers",
+    "tunnel.history",
+    "tunnel.set_mode",
+    "tunnel.set_privacy",
+    "tunnel.start_proxy",
+    "tunnel.stop_proxy",
+    "node.stats",
+    "node.capabilities",
+    "network.health",
+    "network.storage",
+    "content.list_detailed",
+    "content.health",
+    "content.segments",
+    "events.topics",
+    "events.replay",
+];
+
+/// The `data.*` method a legacy unprefixed name stands for; any other name unchanged.
+pub fn canonical_method(method: &str) -> &str {
+    LEGACY_METHODS
+        .iter()
+        .find(|(legacy, _)| *legacy == method)
+        .map_or(method, |(_, replacement)| *replacement)
+}
+
+/// How often changed legacy call counts are written out while an instance runs.
+pub(crate) const LEGACY_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Per-method counts of legacy calls served by an instance, persisted by `save`.
+pub struct LegacyStats {
+    path: PathBuf,
+    counts: Mutex<HashMap<String, u64>>,
+    dirty: AtomicBool,
+    /// Table entries CraftOBJ turned out not to serve, warned about once each
+    stale: Mutex<HashSet<String>>,
+}
+
+impl LegacyStats {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join("legacy_calls.json");
+        let counts = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            counts: Mutex::new(counts),
+            dirty: AtomicBool::new(false),
+            stale: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn record(&self, method: &str) {
+        *self.counts.lock().unwrap().entry(method.to_string()).or_insert(0) += 1;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> HashMap<String, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    /// Write the counts if they changed since the last save.
+    pub fn save(&self) {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let json = match serde_json::to_string_pretty(&*self.counts.lock().unwrap()) {
+            Ok(json) => json,
+            Err(_) => return,
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        if let Err(e) = std::fs::write(&tmp, json).and_then(|_| std::fs::rename(&tmp, &self.path)) {
+            warn!("Failed to save legacy call counts: {}", e);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Warn once when CraftOBJ doesn't know a method the tables say it serves.
+    fn check_served(&self, method: &str, result: &Result<Value, String>) {
+        warn_if_unserved(&self.stale, method, result);
+    }
+}
+
+/// Same check for a core method called from inside the crate. `method` must be listed in
+/// `CORE_METHODS`.
+pub(crate) fn check_core(method: &str, result: &Result<Value, String>) {
+    debug_assert!(CORE_METHODS.contains(&method), "'{}' is missing from CORE_METHODS", method);
+    warn_if_unserved(&STALE_CORE, method, result);
+}
+
+fn warn_if_unserved(stale: &Mutex<HashSet<String>>, method: &str, result: &Result<Value, String>) {
+    let Err(e) = result else { return };
+    let e = e.to_ascii_lowercase();
+    if (e.contains("method not found") || e.contains("unknown method"))
+        && stale.lock().unwrap().insert(method.to_string())
+    {
+        warn!(
+            "CraftOBJ does not serve '{}', which the IPC compatibility tables list; they need updating",
+            method
+        );
+    }
+}
+
+/// Default handler for a full-access listener.
+pub struct LegacyHandler {
+    inner: Arc<dyn IpcHandler>,
+    guard: CallGuard,
+    stats: Arc<LegacyStats>,
+}
+
+impl LegacyHandler {
+    pub fn new(inner: Arc<dyn IpcHandler>, guard: CallGuard, stats: Arc<LegacyStats>) -> Self {
+        Self { inner, guard, stats }
+    }
+
+    /// Forward to CraftOBJ, watching for names it doesn't serve.
+    fn forward(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + '_>> {
+        let method = method.to_string();
+        Box::pin(async move {
+            let result = self.inner.handle(&method, params).await;
+            self.stats.check_served(&method, &result);
+            result
+        })
+    }
+}
+
+impl IpcHandler for LegacyHandler {
+    fn handle(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + '_>> {
+        if let Some((ns, _)) = method.split_once('.') {
+            if CRAFTOBJ_NAMESPACES.contains(&ns) {
+                return self.forward(method, params);
+            }
+            return not_found(method);
+        }
+        if CORE_METHODS.contains(&method) {
+            return self.forward(method, params);
+        }
+
+        let Some((_, replacement)) = LEGACY_METHODS.iter().find(|(legacy, _)| *legacy == method) else {
+            return not_found(method);
+        };
+
+        self.stats.record(method);
+
+        if self.guard.policy.read().unwrap().strict_namespaces {
+            let msg = format!(
+                "Method not found: {} (unprefixed methods are disabled on this instance, use {})",
+                method, replacement
+            );
+            return Box::pin(async move { Err(msg) });
+        }
+
+        warn!("Deprecated unprefixed IPC method '{}', use '{}'", method, replacement);
+        self.forward(method, params)
+    }
+}
+
+fn not_found(method: &str) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + 'static>> {
+    let suggestions = suggest(method);
+    let msg = if suggestions.is_empty() {
+        format!("Method not found: {}", method)
+    } else {
+        format!("Method not found: {} (did you mean {}?)", method, suggestions.join(", "))
+    };
+    Box::pin(async move { Err(msg) })
+}
+
+/// Up to three known methods close to `method`, either by edit distance or because
+/// `method` is the right name in the wrong namespace.
+fn suggest(method: &str) -> Vec<String> {
+    let bare = method.rsplit('.').next().unwrap_or(method);
+    let mut candidates: Vec<(usize, &str)> = LEGACY_METHODS
+        .iter()
+        .map(|(_, namespaced)| *namespaced)
+        .chain(KNOWN_NAMESPACED.iter().copied())
+        .filter_map(|known| {
+            let known_bare = known.rsplit('.').next().unwrap_or(known);
+            let distance = edit_distance(method, known).min(edit_distance(bare, known_bare));
+            (distance <= 2).then_some((distance, known))
+        })
+        .collect();
+    candidates.sort();
+    candidates.into_iter().take(3).map(|(_, m)| m.to_string()).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_names_map_to_the_same_data_method() {
+        let mut seen = HashSet::new();
+        for (legacy, namespaced) in LEGACY_METHODS {
+            assert!(seen.insert(*legacy), "{} listed twice", legacy);
+            assert_eq!(*namespaced, format!("data.{}", legacy));
+            assert!(!CORE_METHODS.contains(legacy));
+        }
+    }
+
+    #[test]
+    fn known_methods_are_namespaced() {
+        for method in KNOWN_NAMESPACED {
+            assert!(method.split_once('.').is_some_and(|(ns, m)| !ns.is_empty() && !m.is_empty()));
+        }
+    }
+
+    #[test]
+    fn suggests_close_and_renamespaced_methods() {
+        assert_eq!(suggest("pubish").first().map(String::as_str), Some("data.publish"));
+        assert!(suggest("node.status").contains(&"tunnel.status".to_string()));
+        assert!(suggest("completely_unrelated").is_empty());
+    }
+
+    struct Echo;
+
+    impl IpcHandler for Echo {
+        fn handle(
+            &self,
+            _method: &str,
+            _params: Option<Value>,
+        ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + '_>> {
+            Box::pin(async { Ok(Value::Null) })
+        }
+    }
+
+    /// Method names the app's client passes to `this.call(...)`.
+    fn client_methods(source: &str) -> Vec<String> {
+        let mut methods = Vec::new();
+        for (at, _) in source.match_indices("this.call") {
+            let rest = &source[at + "this.call".len()..];
+            // Skip a type argument, which may nest `<>` and contain `=>`
+            let mut chars = rest.char_indices().peekable();
+            let mut depth = 0;
+            let mut prev = ' ';
+            let mut start = 0;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '<' => depth += 1,
+                    '>' if prev != '=' => depth -= 1,
+                    _ => {}
+                }
+                prev = c;
+                if depth == 0 {
+                    start = if c == '>' { i + 1 } else { i };
+                    break;
+                }
+            }
+            let Some(args) = rest[start..].strip_prefix('(') else { continue };
+            let args = args.trim_start();
+            let Some(quote) = args.chars().next().filter(|q| *q == '"' || *q == '\'') else { continue };
+            if let Some(end) = args[1..].find(quote) {
+                methods.push(args[1..=end].to_string());
+            }
+        }
+        methods
+    }
+
+    #[test]
+    fn every_method_the_client_calls_is_routed() {
+        let methods = client_methods(include_str!("../../src/services/daemon.ts"));
+        assert!(methods.len() > 30, "parsed only {:?}", methods);
+
+        let dir = std::env::temp_dir().join(format!("craftstudio-legacy-client-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let guard = CallGuard::open(&dir, Arc::new(crate::metrics::InstanceMetrics::new(0)), None);
+        let legacy = LegacyHandler::new(Arc::new(Echo), guard, Arc::new(LegacyStats::open(&dir)));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        for method in &methods {
+            // These namespaces have handlers of their own on the master listener
+            if ["data.", "tunnel.", "events."].iter().any(|ns| method.starts_with(ns)) {
+                continue;
+            }
+            let result = rt.block_on(legacy.handle(method, None));
+            assert!(result.is_ok(), "{} is not routed: {:?}", method, result);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn denying_a_data_method_also_denies_its_legacy_name() {
+        let dir = std::env::temp_dir().join(format!("craftstudio-legacy-policy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let guard = CallGuard::open(&dir, Arc::new(crate::metrics::InstanceMetrics::new(0)), None);
+        guard.policy.write().unwrap().deny.push("data.publish".to_string());
+        let legacy = Arc::new(LegacyHandler::new(Arc::new(Echo), guard.clone(), Arc::new(LegacyStats::open(&dir))));
+        let handler = crate::audit::AuditedHandler::wrap(
+            "",
+            "master",
+            "memory",
+            legacy,
+            &guard,
+            None,
+            &Arc::new(AtomicBool::new(false)),
+        );
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let err = rt.block_on(handler.handle("publish", None)).unwrap_err();
+        assert!(err.contains("data.publish"), "got: {}", err);
+        assert!(rt.block_on(handler.handle("list", None)).is_ok());
+        drop(handler);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn counts_survive_a_reopen() {
+        let dir = std::env::temp_dir().join(format!("craftstudio-legacy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stats = LegacyStats::open(&dir);
+        stats.record("publish");
+        stats.record("publish");
+        stats.save();
+        assert_eq!(LegacyStats::open(&dir).counts().get("publish"), Some(&2));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -96,3 +96,29 @@ fn default_daemon_config_json() -> String {
     })
     .to_string()
 }
+
+/// Point instance references in the app config at a data directory that moved.
+/// Returns whether any reference changed.
+pub(crate) fn relink_instance_dir(old: &str, new: &str) -> Result<bool, String> {
+    let path = config_path();
+    if !path.exists() {
+        return Ok(false);
+    }
+    let raw = fs::read_to_string(&path).map_err(|e| format!("Failed to read config: {e}"))?;
+    let mut config: serde_json::Value =
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid JSON: {e}"))?;
+    let mut changed = false;
+    if let Some(instances) = config.get_mut("instances").and_then(|v| v.as_array_mut()) {
+        for instance in instances {
+            if instance.get("dataDir").and_then(|v| v.as_str()) == Some(old) {
+                instance["dataDir"] = new.into();
+                changed = true;
+            }
+        }
+    }
+    if changed {
+        let json = serde_json::to_string_pretty(&config).map_err(|e| format!("Invalid JSON: {e}"))?;
+        fs::write(&path, json).map_err(|e| format!("Failed to write config: {e}"))?;
+    }
+    Ok(changed)
+}
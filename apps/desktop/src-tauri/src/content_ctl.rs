@@ -0,0 +1,173 @@
+//! Typed access to the content operations of an instance.
+//!
+//! Like `SwarmControl`, this wraps the CraftOBJ IPC handler so the Rust side calls
+//! `publish`/`fetch`/... through one place instead of building JSON-RPC params by hand.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use craftec_ipc::server::IpcHandler;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Published {
+    pub cid: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub segments: u64,
+}
+
+/// One entry of the instance's content catalog (`list`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct CatalogEntry {
+    pub content_id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub total_size: u64,
+}
+
+#[derive(Clone)]
+pub struct ContentControl(Arc<dyn IpcHandler>);
+
+impl ContentControl {
+    pub fn new(handler: Arc<dyn IpcHandler>) -> Self {
+        Self(handler)
+    }
+
+    /// Publish a local file unencrypted.
+    pub async fn publish(&self, path: &Path) -> Result<Published, String> {
+        let v = self
+            .0
+            .handle("publish", Some(json!({ "path": path.to_string_lossy() })))
+            .await?;
+        serde_json::from_value(v).map_err(|e| format!("Unexpected publish response: {}", e))
+    }
+
+    /// Fetch content into `output` and return where it was written.
+    pub async fn fetch(&self, cid: &str, output: &Path) -> Result<PathBuf, String> {
+        let v = self
+            .0
+            .handle("fetch", Some(json!({ "cid": cid, "output": output.to_string_lossy() })))
+            .await?;
+        Ok(v.get("path")
+            .and_then(|p| p.as_str())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| output.to_path_buf()))
+    }
+
+    /// Content this instance published or holds.
+    pub async fn list(&self) -> Result<Vec<CatalogEntry>, String> {
+        let v = self.0.handle("list", None).await?;
+        serde_json::from_value(v).map_err(|e| format!("Unexpected list response: {}", e))
+    }
+
+    /// Drop the local copy of content, which also releases the pin on it.
+    pub async fn delete_local(&self, cid: &str) -> Result<Value, String> {
+        self.0.handle("delete_local", Some(json!({ "cid": cid }))).await
+    }
+
+    /// Generate fresh pieces for content whose redundancy dropped.
+    pub async fn extend(&self, cid: &str) -> Result<Value, String> {
+        self.0.handle("extend", Some(json!({ "cid": cid }))).await
+    }
+}
+
+/// Size the content cache is trimmed back to after each fetch.
+pub const DEFAULT_CACHE_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// A part file this old belongs to a fetch that was interrupted.
+const STALE_PART_AGE: Duration = Duration::from_secs(3600);
+
+/// Reassembled content kept in `{data_dir}/content-cache/<cid>` for local readers (mount,
+/// gateway), fetched through the instance on first use. CraftOBJ only fetches whole
+/// objects, so a reader waits for the full fetch. After each fetch the least recently used
+/// entries are evicted until the cache is under its size cap.
+#[derive(Clone)]
+pub struct ContentCache {
+    content: ContentControl,
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl ContentCache {
+    pub fn new(content: ContentControl, data_dir: &Path) -> Self {
+        Self {
+            content,
+            dir: data_dir.join("content-cache"),
+            max_bytes: DEFAULT_CACHE_MAX_BYTES,
+        }
+    }
+
+    pub fn content(&self) -> &ContentControl {
+        &self.content
+    }
+
+    /// Local path of `cid`, fetching it if it isn't cached yet.
+    pub async fn get(&self, cid: &str) -> Result<PathBuf, String> {
+        // Content IDs become file names
+        if cid.is_empty() || !cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("Invalid content ID '{}'", cid));
+        }
+        let path = self.dir.join(cid);
+        if path.exists() {
+            // The mtime orders entries for eviction
+            let _ = std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|f| f.set_modified(SystemTime::now()));
+            return Ok(path);
+        }
+        std::fs::create_dir_all(&self.dir).map_err(|e| format!("Failed to create {}: {}", self.dir.display(), e))?;
+        // Concurrent readers of the same content fetch to their own part file
+        static NEXT_PART: AtomicU64 = AtomicU64::new(0);
+        let part = self.dir.join(format!("{}.{}.part", cid, NEXT_PART.fetch_add(1, Ordering::Relaxed)));
+        let fetched = match self.content.fetch(cid, &part).await {
+            Ok(written) => std::fs::rename(&written, &path).map_err(|e| {
+                let _ = std::fs::remove_file(&written);
+                format!("Failed to cache {}: {}", cid, e)
+            }),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = fetched {
+            let _ = std::fs::remove_file(&part);
+            return Err(e);
+        }
+        let (dir, max_bytes, keep) = (self.dir.clone(), self.max_bytes, path.clone());
+        let _ = tokio::task::spawn_blocking(move || evict(&dir, max_bytes, &keep)).await;
+        Ok(path)
+    }
+}
+
+/// Remove the least recently used entries of `dir` until it holds at most `max_bytes`,
+/// never `keep`, and any part file left over from an interrupted fetch.
+fn evict(dir: &Path, max_bytes: u64, keep: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    let now = SystemTime::now();
+    let mut cached = Vec::new();
+    for entry in entries.flatten() {
+        let (path, Ok(meta)) = (entry.path(), entry.metadata()) else { continue };
+        let modified = meta.modified().unwrap_or(now);
+        if path.extension().is_some_and(|ext| ext == "part") {
+            if now.duration_since(modified).is_ok_and(|age| age > STALE_PART_AGE) {
+                let _ = std::fs::remove_file(&path);
+            }
+            continue;
+        }
+        cached.push((modified, meta.len(), path));
+    }
+    let mut total: u64 = cached.iter().map(|(_, len, _)| len).sum();
+    cached.sort();
+    for (_, len, path) in cached {
+        if total <= max_bytes {
+            break;
+        }
+        if path != keep && std::fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
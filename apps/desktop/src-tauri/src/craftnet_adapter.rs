@@ -1,21 +1,60 @@
 //! CraftNet IPC adapter
 //!
 //! Wraps `craftnet_daemon::DaemonService` to implement `craftec_ipc::server::IpcHandler`,
-//! bridging the CraftNet daemon's local IpcHandler with the unified craftec-ipc trait.
+//! bridging the CraftNet daemon's local IpcHandler with the unified craftec-ipc trait,
+//! and registers CraftNet as the `tunnel` node service.
 
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
+use craftec_ipc::server::IpcHandler;
 use craftnet_daemon::DaemonService as CraftNetService;
-use serde_json::Value;
+use serde_json::{json, Value};
+use tokio::task::AbortHandle;
+use tracing::warn;
+
+use crate::events::EventSink;
+use crate::services::{NodeService, ServiceContext, ServiceFuture, ServiceSeed};
+
+/// How often the tunnel status is sampled for state-change events.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Adapter that makes a `CraftNetService` available as a `craftec_ipc::server::IpcHandler`.
 ///
 /// CraftNet's `DaemonService` already implements its own `IpcHandler` trait (defined in
 /// `craftnet-daemon`) with the same signature. This adapter delegates to that impl
 /// so the service can be registered as a namespace in `craftec_ipc::ServerBuilder`.
-pub struct CraftNetAdapter(pub Arc<CraftNetService>);
+///
+/// CraftNet has no push channel of its own. Of the `tunnel.*` events the adapter publishes,
+/// only `peer_connected` and `peer_disconnected` are forwarded from real events: the swarm's
+/// own notifications on the instance bus, re-published under `tunnel.` because tunnels ride
+/// the same swarm and tunnel-scoped keys can't see CraftOBJ's unprefixed events. Each one also
+/// samples `status` right away. The rest are synthesized:
+///
+/// - `state_changed`, `peers_changed` and `relay_stats` from polling `status` every
+///   `STATUS_POLL_INTERVAL`; a change undone within one interval is never seen.
+/// - `bandwidth` from polling the swarm's `bandwidth_stats` on the same interval. CraftNet
+///   reports no byte counts, so these are whole-swarm totals and rates, tunnel traffic
+///   included, not tunnel traffic alone.
+/// - `exit_selected` from successful `set_exit` calls.
+#[derive(Clone)]
+pub struct CraftNetAdapter {
+    service: Arc<CraftNetService>,
+    events: Arc<OnceLock<EventSink>>,
+    watcher: Arc<Mutex<Option<AbortHandle>>>,
+}
+
+impl CraftNetAdapter {
+    pub fn new(service: Arc<CraftNetService>) -> Self {
+        Self {
+            service,
+            events: Arc::new(OnceLock::new()),
+            watcher: Arc::new(Mutex::new(None)),
+        }
+    }
+}
 
 impl craftec_ipc::server::IpcHandler for CraftNetAdapter {
     fn handle(
@@ -23,7 +62,177 @@ impl craftec_ipc::server::IpcHandler for CraftNetAdapter {
         method: &str,
         params: Option<Value>,
     ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + '_>> {
-        // Delegate to CraftNet's own IpcHandler implementation
-        craftnet_daemon::IpcHandler::handle(self.0.as_ref(), method, params)
+        if method != "set_exit" {
+            // Delegate to CraftNet's own IpcHandler implementation
+            return craftnet_daemon::IpcHandler::handle(self.service.as_ref(), method, params);
+        }
+        Box::pin(async move {
+            let result =
+                craftnet_daemon::IpcHandler::handle(self.service.as_ref(), "set_exit", params.clone()).await;
+            if result.is_ok() {
+                if let Some(sink) = self.events.get() {
+                    sink.emit("exit_selected", params.unwrap_or(Value::Null));
+                }
+            }
+            result
+        })
     }
 }
+
+impl NodeService for CraftNetAdapter {
+    fn namespace(&self) -> &'static str {
+        "tunnel"
+    }
+
+    fn handler(&self) -> Arc<dyn craftec_ipc::server::IpcHandler> {
+        Arc::new(self.clone())
+    }
+
+    fn init<'a>(&'a self, ctx: &'a ServiceContext) -> ServiceFuture<'a> {
+        Box::pin(async move {
+            let handles = ctx
+                .take_swarm::<craftnet_daemon::SwarmHandles>()
+                .ok_or_else(|| "CraftNet needs the swarm handles but they were already taken".to_string())?;
+            self.service.set_swarm_handles(handles).await;
+            // Auto-start CraftNet so it joins the network immediately
+            if let Err(e) = self.service.start().await {
+                warn!("CraftNet auto-start failed: {}", e);
+            }
+
+            let sink = ctx.sink(self.namespace());
+            let _ = self.events.set(sink.clone());
+            let watcher = tokio::spawn(watch_status(
+                Arc::clone(&self.service),
+                Arc::clone(&ctx.data),
+                ctx.subscribe(),
+                sink,
+            ));
+            *self.watcher.lock().unwrap() = Some(watcher.abort_handle());
+            Ok(())
+        })
+    }
+
+    fn shutdown(&self) -> ServiceFuture<'_> {
+        Box::pin(async move {
+            if let Some(watcher) = self.watcher.lock().unwrap().take() {
+                watcher.abort();
+            }
+            self.service.stop().await.map_err(|e| format!("CraftNet stop failed: {}", e))
+        })
+    }
+}
+
+/// Publish the events described on `CraftNetAdapter`: forward swarm peer events as they
+/// arrive, and sample `status` and `bandwidth_stats` every interval (and `status` after each
+/// peer event) to derive the rest.
+async fn watch_status(
+    service: Arc<CraftNetService>,
+    data: Arc<dyn IpcHandler>,
+    mut bus: tokio::sync::broadcast::Receiver<String>,
+    sink: EventSink,
+) {
+    let mut last: Option<Value> = None;
+    let mut last_traffic: Option<(u64, u64, Instant)> = None;
+    let mut interval = tokio::time::interval(STATUS_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Some(traffic) = sample_bandwidth(data.as_ref(), last_traffic, &sink).await {
+                    last_traffic = Some(traffic);
+                }
+            }
+            received = bus.recv() => match received {
+                Ok(notification) if forward_peer_event(&notification, &sink) => {}
+                Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                // The bus only closes when the instance is gone
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+        }
+
+        let Ok(status) = craftnet_daemon::IpcHandler::handle(service.as_ref(), "status", None).await else {
+            continue;
+        };
+        let field = |v: &Value, k: &str| v.get(k).cloned().unwrap_or(Value::Null);
+        let changed = |keys: &[&str]| match &last {
+            Some(prev) => keys.iter().any(|k| field(prev, k) != field(&status, k)),
+            None => true,
+        };
+
+        if changed(&["status", "connected", "mode", "privacy_level"]) {
+            sink.emit(
+                "state_changed",
+                json!({
+                    "status": field(&status, "status"),
+                    "connected": field(&status, "connected"),
+                    "mode": field(&status, "mode"),
+                    "privacy_level": field(&status, "privacy_level"),
+                }),
+            );
+        }
+        if changed(&["peer_count"]) {
+            sink.emit("peers_changed", json!({ "peer_count": field(&status, "peer_count") }));
+        }
+        if changed(&["shards_relayed", "requests_exited", "credits"]) {
+            sink.emit(
+                "relay_stats",
+                json!({
+                    "shards_relayed": field(&status, "shards_relayed"),
+                    "requests_exited": field(&status, "requests_exited"),
+                    "credits": field(&status, "credits"),
+                }),
+            );
+        }
+        last = Some(status);
+    }
+}
+
+/// Publish `bandwidth` if the swarm's byte totals moved since `last`. Returns the new sample.
+async fn sample_bandwidth(
+    data: &dyn IpcHandler,
+    last: Option<(u64, u64, Instant)>,
+    sink: &EventSink,
+) -> Option<(u64, u64, Instant)> {
+    let stats = data.handle("bandwidth_stats", None).await.ok()?;
+    let field = |k: &str| stats.get(k).and_then(|n| n.as_u64()).unwrap_or(0);
+    let (bytes_in, bytes_out, now) = (field("bytes_in"), field("bytes_out"), Instant::now());
+    let rate = |total: u64, prev: u64, at: Instant| {
+        (total.saturating_sub(prev) as f64 / now.duration_since(at).as_secs_f64().max(f64::EPSILON)) as u64
+    };
+    let (in_rate, out_rate) = match last {
+        Some((i, o, _)) if (i, o) == (bytes_in, bytes_out) => return Some((bytes_in, bytes_out, now)),
+        Some((i, o, at)) => (rate(bytes_in, i, at), rate(bytes_out, o, at)),
+        None => (0, 0),
+    };
+    sink.emit(
+        "bandwidth",
+        json!({
+            "bytes_in": bytes_in,
+            "bytes_out": bytes_out,
+            "in_bytes_per_sec": in_rate,
+            "out_bytes_per_sec": out_rate,
+            "scope": "swarm",
+        }),
+    );
+    Some((bytes_in, bytes_out, now))
+}
+
+/// Re-publish a swarm `peer_connected`/`peer_disconnected` notification as `tunnel.*`.
+/// Returns whether `notification` was one.
+fn forward_peer_event(notification: &str, sink: &EventSink) -> bool {
+    let Ok(v) = serde_json::from_str::<Value>(notification) else {
+        return false;
+    };
+    let event = match v.get("method").and_then(|m| m.as_str()) {
+        Some(event @ ("peer_connected" | "peer_disconnected")) => event,
+        _ => return false,
+    };
+    sink.emit(event, v.get("params").cloned().unwrap_or(Value::Null));
+    true
+}
+
+/// `ServiceRegistry` factory for CraftNet.
+pub fn build_service(seed: &ServiceSeed) -> Result<Arc<dyn NodeService>, String> {
+    let service = CraftNetService::new_with_data_dir(&seed.secret_key, &seed.data_dir)
+        .map_err(|e| format!("Failed to create CraftNet service: {}", e))?;
+    Ok(Arc::new(CraftNetAdapter::new(Arc::new(service))))
+}
@@ -2,27 +2,80 @@ use craftec_identity::Identity;
 use craftec_ipc::server::IpcHandler;
 use craftec_keystore;
 use craftec_network::NetworkConfig;
-use craftnet_daemon::DaemonService as CraftNetService;
 use libp2p::identity::Keypair;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use tokio::task::{AbortHandle, JoinHandle};
 use tracing::{info, warn, error, Instrument};
 use tracing_subscriber::Layer;
 
-use crate::craftnet_adapter::CraftNetAdapter;
+use crate::api_keys::{self, ApiKeyInfo, IssueKeyRequest, KeyStore, ScopedKey};
+use crate::audit::{AuditRecord, CallGuard, MethodPolicy};
+use crate::boot_peers::{drop_invalid_boot_peers, validate_boot_peer};
+use crate::cluster::Cluster;
+use crate::compat::{LegacyStats, LEGACY_SAVE_INTERVAL};
+use crate::content_ctl::{ContentCache, ContentControl};
+use crate::events::{lagged_notification, EventBus};
+use crate::faults::ActiveFaults;
+use crate::gateway::{self, GatewayOptions};
+use crate::jobs::{self, Job, JobContext, JobQueue};
+use crate::listeners::{spawn_listener, InstanceIpc, ListenerSpec, Routes};
+use crate::metrics::{self, InstanceMetrics};
+use crate::mirror::{self, MirrorStore};
+use crate::mount::Mount;
+use crate::nat::{self, NatOptions, Reachability, SharedNatStatus};
+use crate::peer_store::{self, PeerStore};
+use crate::resources::{self, IoLimiter, MemoryUsage, ResourceLimits};
+use crate::services::{BuildFn, NodeService, ServiceContext, ServiceRegistry, ServiceSeed};
+use crate::storage;
+use crate::swarm_ctl::SwarmControl;
+use crate::sync::{SyncContext, SyncManager};
+use crate::transports::{self, BoundAddrs};
+use crate::vfs::Vfs;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DaemonConfig {
     pub data_dir: Option<String>,
     pub socket_path: Option<String>,
     pub ws_port: Option<u16>,
-    pub listen_addr: Option<String>,
+    /// Swarm listen multiaddrs; a single string is accepted for older callers
+    #[serde(default, deserialize_with = "transports::one_or_many")]
+    pub listen_addr: Option<Vec<String>>,
     #[serde(default)]
     pub binary_path: Option<String>, // ignored, kept for API compat
     pub capabilities: Option<Vec<String>>,
+    /// Boot peers for this run only, on top of the ones in config.json; when absent the
+    /// instance boots from its running siblings. Never written to config.json.
+    #[serde(default)]
+    pub boot_peers: Option<Vec<String>>,
+    /// NAT traversal; everything off when absent
+    #[serde(default)]
+    pub nat: Option<NatOptions>,
+    /// Serve OpenMetrics on `127.0.0.1:<port>/metrics`
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    /// HTTP gateway serving `/content/<cid>`; off when absent
+    #[serde(default)]
+    pub gateway: Option<GatewayOptions>,
+    /// Notifications kept for `events.replay`; replay is off when absent
+    #[serde(default)]
+    pub event_replay: Option<usize>,
+    /// Storage quota written to config.json; a new instance gets one that fits the disk
+    #[serde(default)]
+    pub max_storage_bytes: Option<u64>,
+    /// Refuse to start, instead of warning, when the quotas on the disk exceed its free space
+    #[serde(default)]
+    pub strict_storage: bool,
+    /// Worker pool, IPC concurrency and disk I/O caps; shared runtime and no caps when absent
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    /// Throwaway node (test clusters): never the primary instance, free ports and a
+    /// loopback listen address unless given, and not a boot peer for other instances
+    #[serde(default)]
+    pub isolated: bool,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -31,9 +84,18 @@ pub struct DaemonInstance {
     pub ws_port: u16,
     pub data_dir: String,
     pub socket_path: String,
+    /// First configured listen address
     pub listen_addr: String,
+    pub listen_addrs: Vec<String>,
+    /// Addresses the swarm reported as bound
+    pub bound_addrs: Vec<String>,
     pub primary: bool,
     pub did: String,
+    pub peer_id: String,
+    pub reachability: Reachability,
+    pub external_addrs: Vec<String>,
+    pub limits: ResourceLimits,
+    pub memory: MemoryUsage,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -43,16 +105,128 @@ pub struct LogLine {
     pub is_stderr: bool,
 }
 
-struct ManagedDaemon {
-    info: DaemonInstance,
+pub(crate) struct ManagedDaemon {
+    pub(crate) info: DaemonInstance,
+    /// Config the instance was started with, to start it again elsewhere
+    pub(crate) launch: DaemonConfig,
     identity: Identity,
     _handle: JoinHandle<()>,
     abort: AbortHandle,
+    /// Filled in by the daemon task once `init_daemon` returns
+    ipc: Arc<Mutex<Option<InstanceIpc>>>,
+    /// Running IPC listeners keyed by API key name (`master` for the instance's own socket)
+    listeners: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+    services: Vec<Arc<dyn NodeService>>,
+    /// `true` holds the daemon loops (fault injection)
+    pub(crate) pause: tokio::sync::watch::Sender<bool>,
+    pub(crate) peers: Arc<PeerStore>,
+    pub(crate) vfs: Arc<Vfs>,
+    pub(crate) jobs: Arc<JobQueue>,
+    pub(crate) sync: Arc<SyncManager>,
+    pub(crate) mirrors: Arc<MirrorStore>,
+    /// FUSE mount of the instance's content, unmounted on stop
+    pub(crate) mount: Arc<Mutex<Option<Mount>>>,
+    nat: SharedNatStatus,
+    bound: BoundAddrs,
+    pub(crate) metrics: Arc<InstanceMetrics>,
+    /// Helper tasks tied to the instance's lifetime (peer manager, ...)
+    background: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    /// Runtime the instance's tasks run on: its own when `worker_threads` is set
+    pub(crate) rt: tokio::runtime::Handle,
+    own_runtime: Option<tokio::runtime::Runtime>,
+    pub(crate) io: Option<Arc<IoLimiter>>,
+}
+
+impl ManagedDaemon {
+    /// Instance info with the latest NAT status, bound addresses and memory use filled in.
+    fn snapshot(&self, log_buffer_bytes: u64) -> DaemonInstance {
+        let event_replay_bytes = match &*self.ipc.lock().unwrap() {
+            Some(ipc) => ipc.events.buffered_bytes() as u64,
+            None => 0,
+        };
+        let nat = self.nat.lock().unwrap();
+        DaemonInstance {
+            reachability: nat.reachability,
+            external_addrs: nat.external_addrs.clone(),
+            bound_addrs: self.bound.lock().unwrap().clone(),
+            memory: MemoryUsage::new(log_buffer_bytes, event_replay_bytes, self.peers.approx_bytes() as u64),
+            ..self.info.clone()
+        }
+    }
+
+    /// Abort the instance's tasks. Returns the ones that may still be winding down; awaiting
+    /// them (and the daemon task) guarantees nothing touches the data dir any more.
+    fn abort_all(&self, runtime: &tokio::runtime::Handle) -> Vec<JoinHandle<()>> {
+        if let Some(ipc) = &*self.ipc.lock().unwrap() {
+            ipc.legacy.save();
+        }
+        let mut tasks: Vec<JoinHandle<()>> = self.listeners.lock().unwrap().drain().map(|(_, l)| l).collect();
+        tasks.append(&mut self.background.lock().unwrap());
+        tasks.extend(self.sync.stop_all());
+        tasks.extend(self.jobs.stop_all());
+        for task in &tasks {
+            task.abort();
+        }
+        self.mount.lock().unwrap().take();
+        self.abort.abort();
+        for service in &self.services {
+            let service = Arc::clone(service);
+            tasks.push(runtime.spawn(async move {
+                if let Err(e) = service.shutdown().await {
+                    warn!("Service '{}' shutdown failed: {}", service.namespace(), e);
+                }
+            }));
+        }
+        tasks
+    }
+}
+
+impl Drop for ManagedDaemon {
+    fn drop(&mut self) {
+        // Tasks were aborted by `abort_all`; don't block on them winding down
+        if let Some(runtime) = self.own_runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
 }
 
 /// Shared log storage accessible from both the DaemonManager and tracing layer.
 pub type SharedLogs = Arc<Mutex<HashMap<u32, Vec<LogLine>>>>;
 
+/// Append a line to an instance's log buffer, if the instance still has one.
+pub(crate) fn push_log(logs: &SharedLogs, pid: u32, line: String, is_stderr: bool) {
+    let mut logs = logs.lock().unwrap();
+    if let Some(v) = logs.get_mut(&pid) {
+        v.push(LogLine { pid, line, is_stderr });
+        trim_log(pid, v);
+    }
+}
+
+/// A TCP port on loopback that nothing listens on right now.
+fn free_port() -> Result<u16, String> {
+    std::net::TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|l| l.local_addr())
+        .map(|a| a.port())
+        .map_err(|e| format!("No free port: {}", e))
+}
+
+/// Abort a listener and wait until its socket and port are released.
+async fn close_listener(listener: Option<JoinHandle<()>>) {
+    if let Some(listener) = listener {
+        listener.abort();
+        let _ = listener.await;
+    }
+}
+
+/// Keep the newest 500 lines and count what was dropped.
+fn trim_log(pid: u32, v: &mut Vec<LogLine>) {
+    if v.len() > 500 {
+        let dropped = v.len() - 500;
+        v.drain(..dropped);
+        metrics::count_dropped_log_lines(pid, dropped);
+    }
+}
+
 /// A tracing Layer that captures log events into the shared buffer.
 /// All daemon logs are routed to instance_id found in the current span's extensions,
 /// or to a default bucket.
@@ -102,9 +276,7 @@ where
                 line,
                 is_stderr: false,
             });
-            if v.len() > 500 {
-                v.drain(..v.len() - 500);
-            }
+            trim_log(id, v);
         }
     }
 
@@ -171,9 +343,22 @@ impl tracing::field::Visit for StringVisitor {
 
 pub struct DaemonManager {
     daemons: Mutex<Vec<ManagedDaemon>>,
-    logs: SharedLogs,
+    pub(crate) logs: SharedLogs,
     next_index: Mutex<u32>,
-    runtime: tokio::runtime::Handle,
+    pub(crate) runtime: tokio::runtime::Handle,
+    services: Mutex<ServiceRegistry>,
+    pub(crate) clusters: Mutex<Vec<Cluster>>,
+    pub(crate) next_cluster_id: Mutex<u32>,
+    pub(crate) faults: ActiveFaults,
+    pub(crate) next_fault_id: Mutex<u64>,
+    /// Job changes of all instances, forwarded to the GUI
+    pub(crate) job_updates: tokio::sync::broadcast::Sender<Job>,
+    /// Serve IPC only through `call` instead of sockets and WebSocket ports (test harness)
+    in_memory_ipc: bool,
+    /// Serializes API key rotation, issue and revocation so listeners never race for a port
+    key_changes: tokio::sync::Mutex<()>,
+    /// Last measured storage usage per data dir
+    pub(crate) usage: Arc<storage::UsageCache>,
 }
 
 impl DaemonManager {
@@ -183,23 +368,63 @@ impl DaemonManager {
             logs,
             next_index: Mutex::new(0),
             runtime,
+            services: Mutex::new(ServiceRegistry::default()),
+            clusters: Mutex::new(Vec::new()),
+            next_cluster_id: Mutex::new(0),
+            faults: ActiveFaults::default(),
+            next_fault_id: Mutex::new(0),
+            job_updates: tokio::sync::broadcast::channel(256).0,
+            in_memory_ipc: false,
+            key_changes: tokio::sync::Mutex::new(()),
+            usage: Arc::default(),
+        }
+    }
+
+    /// A manager whose instances bind no sockets or ports for IPC; use `call` to reach them.
+    pub fn new_in_memory(logs: SharedLogs, runtime: tokio::runtime::Handle) -> Self {
+        Self {
+            in_memory_ipc: true,
+            ..Self::new(logs, runtime)
         }
     }
 
+    /// Add a node service for instances started from now on. With a `capability`, only
+    /// instances that have it get the service.
+    pub fn register_service(
+        &self,
+        namespace: &'static str,
+        capability: Option<&'static str>,
+        build: BuildFn,
+    ) -> Result<(), String> {
+        self.services.lock().unwrap().register(namespace, capability, build)
+    }
+
     pub fn start(&self, config: DaemonConfig) -> Result<DaemonInstance, String> {
+        let launch = config.clone();
         let mut index = self.next_index.lock().unwrap();
-        let is_primary = *index == 0;
+        let is_primary = !config.isolated && self.takes_primary(config.data_dir.as_deref());
+        // Slot 0's ports are the primary's; other instances never take them
+        if !is_primary && !config.isolated && *index == 0 {
+            *index = 1;
+        }
         let instance_id = *index;
 
-        let ws_port = config.ws_port.unwrap_or(9091 + instance_id as u16);
-        let listen_port = if is_primary { 44001 } else { 44001 + instance_id as u16 };
+        let ws_port = match config.ws_port {
+            Some(port) => port,
+            None if config.isolated => free_port()?,
+            None if is_primary => 9091,
+            None => 9091 + instance_id as u16,
+        };
+        let listen_port = if config.isolated {
+            0
+        } else if is_primary {
+            44001
+        } else {
+            44001 + instance_id as u16
+        };
         let data_dir = config.data_dir.unwrap_or_else(|| {
             if is_primary {
-                dirs::home_dir()
-                    .unwrap_or_else(|| PathBuf::from("."))
-                    .join(".craftobj")
-                    .to_string_lossy()
-                    .to_string()
+                primary_data_dir().to_string_lossy().to_string()
             } else {
                 format!("/tmp/craftobj-node-{}", instance_id)
             }
@@ -211,9 +436,12 @@ impl DaemonManager {
                 format!("/tmp/craftobj-{}.sock", instance_id)
             }
         });
-        let listen_addr = config
-            .listen_addr
-            .unwrap_or_else(|| format!("/ip4/0.0.0.0/tcp/{}", listen_port));
+        let listen_addrs = config.listen_addr.unwrap_or_else(|| {
+            let host = if config.isolated { "127.0.0.1" } else { "0.0.0.0" };
+            vec![format!("/ip4/{}/tcp/{}", host, listen_port)]
+        });
+        let limits = config.limits.unwrap_or_default();
+        limits.validate()?;
 
         // Clean up finished tasks
         {
@@ -225,27 +453,19 @@ impl DaemonManager {
         }
 
         // Check if port already in use
-        if std::net::TcpStream::connect(format!("127.0.0.1:{}", ws_port)).is_ok() {
+        if !self.in_memory_ipc && std::net::TcpStream::connect(format!("127.0.0.1:{}", ws_port)).is_ok() {
             return Err(format!("Port {} already in use — daemon already running", ws_port));
         }
 
-        // Collect boot peers from already-running instances
-        let boot_peers: Vec<String> = {
-            let daemons = self.daemons.lock().unwrap();
-            daemons
-                .iter()
-                .filter(|d| !d._handle.is_finished())
-                .map(|d| {
-                    // Extract port from listen_addr (e.g. "/ip4/0.0.0.0/tcp/44001" -> 44001)
-                    // and construct a localhost multiaddr for it
-                    let port = d.info.listen_addr
-                        .rsplit('/')
-                        .next()
-                        .and_then(|p| p.parse::<u16>().ok())
-                        .unwrap_or(0);
-                    format!("/ip4/127.0.0.1/tcp/{}", port)
-                })
-                .collect()
+        // Ephemeral boot peers: already-running instances unless the caller chose them
+        let ephemeral_boot_peers: Vec<String> = match config.boot_peers {
+            Some(peers) => {
+                for peer in &peers {
+                    validate_boot_peer(peer)?;
+                }
+                peers
+            }
+            None => self.sibling_boot_peers(None),
         };
 
         // Write default config if not already present, using DaemonConfig struct
@@ -253,6 +473,7 @@ impl DaemonManager {
         {
             let config_path = std::path::Path::new(&data_dir).join("config.json");
             std::fs::create_dir_all(&data_dir).ok();
+            let quota = self.plan_storage(std::path::Path::new(&data_dir), config.max_storage_bytes, config.strict_storage)?;
             if !config_path.exists() {
                 let caps = config
                     .capabilities
@@ -264,22 +485,15 @@ impl DaemonManager {
                 daemon_cfg.listen_port = listen_port;
                 daemon_cfg.ws_port = ws_port;
                 daemon_cfg.socket_path = Some(socket_path.clone());
-                daemon_cfg.max_storage_bytes = 10_737_418_240;
-                daemon_cfg.boot_peers = boot_peers.clone();
+                daemon_cfg.max_storage_bytes = quota.unwrap_or(storage::DEFAULT_QUOTA);
                 if let Err(e) = daemon_cfg.save_to(&config_path) {
                     eprintln!(
                         "Warning: failed to write initial daemon config to {:?}: {}",
                         config_path, e
                     );
                 }
-            } else if !boot_peers.is_empty() {
-                // Config exists — load, update boot_peers, save back.
-                // Use DaemonConfig round-trip so no other fields are lost.
-                let mut existing = craftobj_daemon::config::DaemonConfig::load_from(&config_path);
-                existing.boot_peers = boot_peers.clone();
-                if let Err(e) = existing.save_to(&config_path) {
-                    eprintln!("Warning: failed to update boot_peers in {:?}: {}", config_path, e);
-                }
+            } else if let (Some(quota), Some(_)) = (quota, config.max_storage_bytes) {
+                storage::set_quota(std::path::Path::new(&data_dir), quota)?;
             }
         }
 
@@ -314,13 +528,12 @@ impl DaemonManager {
             secondary_protocol_prefix: Some("craftnet".to_string()),
             ..Default::default()
         };
+        config.nat.unwrap_or_default().apply(&mut network_config)?;
 
-        // Parse listen address
+        // Parse listen addresses
         let config_path_file = data_dir_path.join("config.json");
-        if !listen_addr.is_empty() {
-            network_config.listen_addrs = vec![listen_addr
-                .parse()
-                .map_err(|e| format!("Invalid listen addr: {}", e))?];
+        if !listen_addrs.is_empty() {
+            network_config.listen_addrs = transports::validate_listen_addrs(&listen_addrs)?;
         } else if config_path_file.exists() {
             if let Ok(raw) = std::fs::read_to_string(&config_path_file) {
                 if let Ok(json) = serde_json::from_str::<serde_json::Value>(&raw) {
@@ -355,27 +568,84 @@ impl DaemonManager {
         let (stream_tx, stream_rx) = tokio::sync::oneshot::channel();
 
         // Load daemon config from disk (or defaults) before init
-        let daemon_config = if let Some(ref path) = config_path_opt {
+        let mut daemon_config = if let Some(ref path) = config_path_opt {
             let cfg = craftobj_daemon::config::DaemonConfig::load_from(path);
             cfg
         } else {
             craftobj_daemon::config::DaemonConfig::load(&data_dir_path)
         };
+        if let Err(e) = drop_invalid_boot_peers(&data_dir_path, &mut daemon_config) {
+            warn!("{}", e);
+        }
+        if let Some(bps) = limits.disk_io_bytes_per_sec {
+            daemon_config.max_disk_io_bytes_per_sec = Some(bps);
+        }
+        // Configured boot peers stay as the user wrote them; siblings only join for this run
+        for peer in ephemeral_boot_peers {
+            if !daemon_config.boot_peers.contains(&peer) {
+                daemon_config.boot_peers.push(peer);
+            }
+        }
+
+        // ── Create the node services this instance's capabilities enable ──
+        let seed = ServiceSeed {
+            secret_key: node_signing_key.secret_key_bytes(),
+            data_dir: PathBuf::from(&data_dir),
+        };
+        let services = self.services.lock().unwrap().build_for(&daemon_config.capabilities, &seed)?;
+        let service_handlers: Vec<(&'static str, Arc<dyn IpcHandler>)> =
+            services.iter().map(|s| (s.namespace(), s.handler())).collect();
+        let services_for_init = services.clone();
+        let (bus_tx, bus_rx) =
+            tokio::sync::oneshot::channel::<(Arc<dyn IpcHandler>, Arc<EventBus>)>();
 
-        // ── Create CraftNet service for this instance ──
-        let craftnet_secret = node_signing_key.secret_key_bytes();
-        let craftnet_service = CraftNetService::new_with_data_dir(
-            &craftnet_secret,
-            &PathBuf::from(&data_dir),
-        )
-        .map_err(|e| format!("Failed to create CraftNet service: {}", e))?;
+        let ipc_slot: Arc<Mutex<Option<InstanceIpc>>> = Arc::new(Mutex::new(None));
+        let listeners: Arc<Mutex<HashMap<String, JoinHandle<()>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let ipc_slot_for_task = Arc::clone(&ipc_slot);
+        let listeners_for_task = Arc::clone(&listeners);
+        let key_store = KeyStore::new(&data_dir_path);
+        let instance_metrics = Arc::new(InstanceMetrics::new(instance_id));
+        let metrics_for_task = Arc::clone(&instance_metrics);
+        let guard = CallGuard::open(&data_dir_path, Arc::clone(&instance_metrics), limits.max_concurrent_ipc);
+        let in_memory_ipc = self.in_memory_ipc;
+        let event_replay = config.event_replay.unwrap_or(0);
+        let (pause, mut pause_rx) = tokio::sync::watch::channel(false);
+        let peers = Arc::new(PeerStore::open(&data_dir_path));
+        let peers_for_task = Arc::clone(&peers);
+        let vfs = Arc::new(Vfs::open(&data_dir_path));
+        let vfs_for_task = Arc::clone(&vfs);
+        let jobs = Arc::new(JobQueue::open(&data_dir_path, instance_id, self.job_updates.clone()));
+        let jobs_for_task = Arc::clone(&jobs);
+        let sync = Arc::new(SyncManager::open(&data_dir_path));
+        let sync_for_task = Arc::clone(&sync);
+        let mirrors = Arc::new(MirrorStore::open(&data_dir_path));
+        let mirrors_for_task = Arc::clone(&mirrors);
+        let (usage_for_task, usage_data_dir) = (Arc::clone(&self.usage), data_dir_path.clone());
+        let legacy = Arc::new(LegacyStats::open(&data_dir_path));
+        let legacy_for_task = Arc::clone(&legacy);
+        let background: Arc<Mutex<Vec<JoinHandle<()>>>> = Arc::new(Mutex::new(Vec::new()));
+        let background_for_task = Arc::clone(&background);
+        let nat_status = SharedNatStatus::default();
+        let nat_status_for_task = Arc::clone(&nat_status);
+        let bound = BoundAddrs::default();
+        let bound_for_task = Arc::clone(&bound);
 
-        let craftnet_service = Arc::new(craftnet_service);
-        let craftnet_for_adapter = Arc::clone(&craftnet_service);
+        let own_runtime = match limits.worker_threads {
+            Some(threads) => Some(resources::build_runtime(instance_id, threads)?),
+            None => None,
+        };
+        let rt = own_runtime
+            .as_ref()
+            .map_or_else(|| self.runtime.clone(), |runtime| runtime.handle().clone());
+        let io = limits.disk_io_bytes_per_sec.map(|bps| Arc::new(IoLimiter::new(bps)));
+        let io_for_task = io.clone();
+        let jobs_data_dir = data_dir_path.clone();
+        let gateway_data_dir = data_dir_path.clone();
+        let gateway_options = config.gateway.clone();
 
         let socket_path_for_ipc = socket_path.clone();
         let span = tracing::info_span!("daemon", daemon_instance_id = instance_id);
-        let handle = self.runtime.spawn(async move {
+        let handle = rt.spawn(async move {
             let socket_path = socket_path_for_ipc;
             // 1. Init CraftOBJ daemon (handler + swarm, no IPC)
             let daemon_handle = match craftobj_daemon::init_daemon(
@@ -403,65 +673,205 @@ impl DaemonManager {
                 }
             };
 
-            // 2. Build unified IPC server with namespace routing
-            let ipc = craftec_ipc::ServerBuilder::new(&socket_path)
-                .with_websocket(ws_port)
-                .with_api_key(daemon_handle.api_key.clone())
-                .namespace("data", daemon_handle.handler.clone())
-                .namespace("tunnel", Arc::new(CraftNetAdapter(craftnet_for_adapter.clone())) as Arc<dyn IpcHandler>)
-                .default_handler(daemon_handle.handler.clone()); // backward compat: unnamespaced methods go to CraftOBJ
+            // 2. Expose handlers to the manager and start the IPC listeners
+            let data_handler = daemon_handle.handler.clone() as Arc<dyn IpcHandler>;
+            let events = Arc::new(EventBus::new(event_replay));
+            let _ = bus_tx.send((data_handler.clone(), Arc::clone(&events)));
+            let instance_ipc = InstanceIpc {
+                handler: data_handler,
+                services: service_handlers,
+                events,
+                guard,
+                legacy: Arc::clone(&legacy_for_task),
+            };
+            *ipc_slot_for_task.lock().unwrap() = Some(instance_ipc.clone());
+            if !in_memory_ipc {
+                let mut active = listeners_for_task.lock().unwrap();
+                let master = spawn_listener(
+                    ListenerSpec::master(&socket_path, ws_port, daemon_handle.api_key.clone()),
+                    &instance_ipc,
+                );
+                active.insert("master".to_string(), master);
+                match key_store.load() {
+                    Ok(keys) => {
+                        for key in keys.iter().filter(|k| !k.is_expired()) {
+                            let listener = spawn_listener(ListenerSpec::scoped(key), &instance_ipc);
+                            active.insert(key.name.clone(), listener);
+                        }
+                    }
+                    Err(e) => error!("Named API keys not served: {}", e),
+                }
+            }
+
+            // Keep the peer address book and connection policy running off the bus
+            let peer_manager = tokio::spawn(
+                peer_store::maintain(
+                    peers_for_task,
+                    instance_ipc.events.subscribe(),
+                    SwarmControl::new(instance_ipc.handler.clone()),
+                )
+                .in_current_span(),
+            );
+            background_for_task.lock().unwrap().push(peer_manager);
+            let nat_watcher = tokio::spawn(nat::watch(
+                SwarmControl::new(instance_ipc.handler.clone()),
+                nat_status_for_task,
+            ));
+            background_for_task.lock().unwrap().push(nat_watcher);
+            let bound_tracker = tokio::spawn(transports::track_bound(instance_ipc.events.subscribe(), bound_for_task));
+            background_for_task.lock().unwrap().push(bound_tracker);
+            let sampler = tokio::spawn(metrics::sample(
+                Arc::clone(&metrics_for_task),
+                instance_ipc.events.subscribe(),
+                SwarmControl::new(instance_ipc.handler.clone()),
+            ));
+            background_for_task.lock().unwrap().push(sampler);
+            let usage_refresher = tokio::spawn(storage::refresh_usage(usage_for_task, instance_id, usage_data_dir));
+            background_for_task.lock().unwrap().push(usage_refresher);
+            let legacy_saver = tokio::spawn(async move {
+                let mut tick = tokio::time::interval(LEGACY_SAVE_INTERVAL);
+                loop {
+                    tick.tick().await;
+                    legacy_for_task.save();
+                }
+            });
+            background_for_task.lock().unwrap().push(legacy_saver);
+            let mirror_follower = tokio::spawn(
+                mirror::follow(
+                    Arc::clone(&mirrors_for_task),
+                    ContentControl::new(instance_ipc.handler.clone()),
+                    Arc::clone(&jobs_for_task),
+                    instance_ipc.events.subscribe(),
+                )
+                .in_current_span(),
+            );
+            background_for_task.lock().unwrap().push(mirror_follower);
+            let job_runner = tokio::spawn(
+                jobs::run(
+                    jobs_for_task,
+                    JobContext {
+                        content: ContentControl::new(instance_ipc.handler.clone()),
+                        io: io_for_task.clone(),
+                        vfs: Arc::clone(&vfs_for_task),
+                        mirrors: mirrors_for_task,
+                        data_dir: jobs_data_dir,
+                    },
+                    instance_ipc.events.subscribe(),
+                )
+                .in_current_span(),
+            );
+            background_for_task.lock().unwrap().push(job_runner);
+            sync_for_task.start(SyncContext {
+                content: ContentControl::new(instance_ipc.handler.clone()),
+                io: io_for_task,
+                vfs: vfs_for_task,
+            });
+            if let Some(options) = gateway_options {
+                let cache = ContentCache::new(ContentControl::new(instance_ipc.handler.clone()), &gateway_data_dir);
+                let gateway = tokio::spawn(
+                    async move {
+                        if let Err(e) = gateway::serve(cache, options).await {
+                            warn!("Content gateway stopped: {}", e);
+                        }
+                    }
+                    .in_current_span(),
+                );
+                background_for_task.lock().unwrap().push(gateway);
+            }
 
             // 3. Bridge DaemonEvent → String for the IPC event transport
-            let ipc_event_tx = ipc.event_sender();
+            let bus = Arc::clone(&instance_ipc.events);
             let mut daemon_event_rx = daemon_handle.event_tx.subscribe();
-            tokio::spawn(async move {
+            let bridge = tokio::spawn(async move {
                 loop {
                     match daemon_event_rx.recv().await {
                         Ok(event) => {
                             let notification: String = event.into();
-                            let _ = ipc_event_tx.send(notification);
+                            bus.publish(notification);
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                            // Every client missed these; say so on the bus itself
+                            warn!("Event bridge lagged, {} daemon events lost", missed);
+                            metrics_for_task.observe_lag(missed);
+                            bus.publish(lagged_notification(missed, bus.latest_seq()));
                         }
-                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
                         Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
                     }
                 }
             });
+            background_for_task.lock().unwrap().push(bridge);
 
-            // 4. Run everything concurrently
-            tokio::select! {
-                _ = daemon_handle.loops => {
-                    info!("Daemon instance {} loops ended", instance_id);
+            // 4. Run the daemon loops; listeners live alongside them. While paused the
+            //    loops future is simply not polled.
+            let mut loops = std::pin::pin!(daemon_handle.loops);
+            loop {
+                if *pause_rx.borrow_and_update() {
+                    warn!("Daemon instance {} loops paused", instance_id);
+                    while *pause_rx.borrow_and_update() {
+                        if pause_rx.changed().await.is_err() {
+                            break;
+                        }
+                    }
+                    info!("Daemon instance {} loops resumed", instance_id);
                 }
-                result = ipc.run() => {
-                    if let Err(e) = result {
-                        error!("IPC server error for instance {}: {}", instance_id, e);
+                tokio::select! {
+                    _ = &mut loops => break,
+                    changed = pause_rx.changed() => {
+                        if changed.is_err() {
+                            let _ = (&mut loops).await;
+                            break;
+                        }
                     }
                 }
             }
+            info!("Daemon instance {} loops ended", instance_id);
+            for (_, listener) in listeners_for_task.lock().unwrap().drain() {
+                listener.abort();
+            }
 
             info!("Daemon instance {} exited cleanly", instance_id);
         }.instrument(span));
 
         let abort = handle.abort_handle();
 
-        // Give CraftNet its swarm handles (once they become available) and auto-start
-        self.runtime.spawn(async move {
-            match stream_rx.await {
-                Ok((stream_control, incoming_streams_rx)) => {
-                    let handles = craftnet_daemon::SwarmHandles {
-                        cmd_tx,
-                        evt_rx,
-                        stream_control,
-                        incoming_streams_rx,
-                        local_peer_id: peer_id,
-                    };
-                    craftnet_service.set_swarm_handles(handles).await;
-                    // Auto-start CraftNet so it joins the network immediately
-                    if let Err(e) = craftnet_service.start().await {
-                        warn!("CraftNet auto-start failed: {}", e);
+        if let Some(port) = config.metrics_port {
+            let metrics_server = metrics::serve(Arc::clone(&instance_metrics), port);
+            let server = rt.spawn(
+                async move {
+                    if let Err(e) = metrics_server.await {
+                        warn!("Metrics endpoint stopped: {}", e);
                     }
                 }
-                Err(e) => warn!("Failed to receive CraftNet stream handles: {}", e),
+                .instrument(tracing::info_span!("metrics", daemon_instance_id = instance_id)),
+            );
+            background.lock().unwrap().push(server);
+        }
+
+        // Hand the swarm to the node services once it is up, then initialize them
+        let service_data_dir = PathBuf::from(&data_dir);
+        rt.spawn(async move {
+            let (stream_control, incoming_streams_rx) = match stream_rx.await {
+                Ok(streams) => streams,
+                Err(e) => {
+                    warn!("Failed to receive swarm stream handles: {}", e);
+                    return;
+                }
+            };
+            let Ok((data_handler, events)) = bus_rx.await else {
+                return;
+            };
+            let handles = craftnet_daemon::SwarmHandles {
+                cmd_tx,
+                evt_rx,
+                stream_control,
+                incoming_streams_rx,
+                local_peer_id: peer_id,
+            };
+            let ctx = ServiceContext::new(peer_id, service_data_dir, data_handler, events, Box::new(handles));
+            for service in &services_for_init {
+                if let Err(e) = service.init(&ctx).await {
+                    warn!("Service '{}' failed to initialize: {}", service.namespace(), e);
+                }
             }
         });
 
@@ -479,18 +889,43 @@ impl DaemonManager {
             ws_port,
             data_dir,
             socket_path,
-            listen_addr,
+            listen_addr: listen_addrs.first().cloned().unwrap_or_default(),
+            listen_addrs,
+            bound_addrs: Vec::new(),
             primary: is_primary,
             did: did_string,
+            peer_id: peer_id.to_string(),
+            reachability: Reachability::Unknown,
+            external_addrs: Vec::new(),
+            limits,
+            memory: MemoryUsage::default(),
         };
 
         {
             let mut daemons = self.daemons.lock().unwrap();
             daemons.push(ManagedDaemon {
                 info: instance.clone(),
+                launch,
                 identity,
                 _handle: handle,
                 abort,
+                ipc: ipc_slot,
+                listeners,
+                services,
+                pause,
+                peers,
+                vfs,
+                jobs,
+                sync,
+                mirrors,
+                mount: Arc::new(Mutex::new(None)),
+                nat: nat_status,
+                bound,
+                metrics: instance_metrics,
+                background,
+                rt,
+                own_runtime,
+                io,
             });
         }
 
@@ -498,27 +933,75 @@ impl DaemonManager {
         Ok(instance)
     }
 
+    /// Whether an instance on `data_dir` is the primary one. The primary is the instance on
+    /// `~/.craftobj`; one started without a data dir gets it unless a running instance has it.
+    /// Start order and isolated instances (clusters, sims) play no part.
+    fn takes_primary(&self, data_dir: Option<&str>) -> bool {
+        match data_dir {
+            Some(dir) => Path::new(dir) == primary_data_dir(),
+            None => !self
+                .daemons
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|d| d.info.primary && !d._handle.is_finished()),
+        }
+    }
+
     pub fn stop(&self, pid: u32) -> Result<(), String> {
+        let stopped = self.stop_detached(pid)?;
+        self.runtime.spawn(stopped);
+        Ok(())
+    }
+
+    /// Stop an instance and wait until its tasks have ended, so nothing writes to its data
+    /// dir any more.
+    pub async fn stop_and_wait(&self, pid: u32) -> Result<(), String> {
+        self.stop_detached(pid)?.await;
+        Ok(())
+    }
+
+    /// Stop an instance now and return a future that resolves once its tasks have ended.
+    /// The future doesn't borrow the manager, so it can be spawned from a sync context.
+    pub(crate) fn stop_detached(&self, pid: u32) -> Result<impl Future<Output = ()> + Send + 'static, String> {
+        let mut daemon = self.take_daemon(pid)?;
+        let tasks = daemon.abort_all(&self.runtime);
+        let faults = self.clear_faults(pid);
+        Ok(async move {
+            for task in tasks {
+                let _ = task.await;
+            }
+            let _ = (&mut daemon._handle).await;
+            // Damaged pieces go back only once nothing of the instance touches the data dir
+            faults.await;
+        })
+    }
+
+    /// Remove an instance from the table, along with its logs.
+    fn take_daemon(&self, pid: u32) -> Result<ManagedDaemon, String> {
         let mut daemons = self.daemons.lock().unwrap();
         let pos = daemons
             .iter()
             .position(|d| d.info.pid == pid)
             .ok_or_else(|| format!("No daemon with instance ID {}", pid))?;
-
         let daemon = daemons.remove(pos);
-        daemon.abort.abort();
-
-        // Clean up logs
-        let mut logs = self.logs.lock().unwrap();
-        logs.remove(&pid);
-
-        Ok(())
+        self.logs.lock().unwrap().remove(&pid);
+        Ok(daemon)
     }
 
     pub fn list(&self) -> Vec<DaemonInstance> {
         let mut daemons = self.daemons.lock().unwrap();
         daemons.retain(|d| !d._handle.is_finished());
-        daemons.iter().map(|d| d.info.clone()).collect()
+        let logs = self.logs.lock().unwrap();
+        daemons
+            .iter()
+            .map(|d| {
+                let log_bytes = logs
+                    .get(&d.info.pid)
+                    .map_or(0, |v| v.iter().map(|l| l.line.len() as u64).sum());
+                d.snapshot(log_bytes)
+            })
+            .collect()
     }
 
     pub fn get_logs(&self, pid: u32, since: usize) -> Vec<LogLine> {
@@ -534,13 +1017,158 @@ impl DaemonManager {
         }
     }
 
+    /// Handlers, listener table and info of a running instance.
+    pub(crate) fn running_ipc(
+        &self,
+        pid: u32,
+    ) -> Result<(InstanceIpc, Arc<Mutex<HashMap<String, JoinHandle<()>>>>, DaemonInstance), String> {
+        let (ipc, listeners, info) = self.with_daemon(pid, |d| {
+            (d.ipc.lock().unwrap().clone(), Arc::clone(&d.listeners), d.info.clone())
+        })?;
+        let ipc = ipc.ok_or_else(|| format!("Instance {} is still starting", pid))?;
+        Ok((ipc, listeners, info))
+    }
+
+    /// Call a method on an instance with master-key routing, without a socket.
+    pub async fn call(&self, pid: u32, method: &str, params: Option<serde_json::Value>) -> Result<serde_json::Value, String> {
+        let (ipc, _, info) = self.running_ipc(pid)?;
+        let spec = ListenerSpec {
+            subscribable: false,
+            ..ListenerSpec::master(&info.socket_path, info.ws_port, String::new())
+        };
+        Routes::build(&spec, &ipc, "memory").dispatch(method, params).await
+    }
+
+    /// Notifications an instance publishes on its event bus.
+    pub fn subscribe(&self, pid: u32) -> Result<tokio::sync::broadcast::Receiver<String>, String> {
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        Ok(ipc.events.subscribe())
+    }
+
+    /// Replace the master key and restart the master listener with it.
+    /// The daemon's swarm and loops keep running; connected clients must reconnect.
+    pub async fn rotate_api_key(&self, pid: u32) -> Result<String, String> {
+        let _serial = self.key_changes.lock().await;
+        let (ipc, listeners, info) = self.running_ipc(pid)?;
+        let key = api_keys::generate_key();
+        api_keys::write_master_key(std::path::Path::new(&info.data_dir), &key)?;
+
+        // The old listener has to release the socket and port before they are bound again
+        let old = listeners.lock().unwrap().remove("master");
+        close_listener(old).await;
+        let rt = self.with_daemon(pid, |d| d.rt.clone())?;
+        let master = {
+            let _guard = rt.enter();
+            spawn_listener(ListenerSpec::master(&info.socket_path, info.ws_port, key.clone()), &ipc)
+        };
+        listeners.lock().unwrap().insert("master".to_string(), master);
+        info!("Instance {} master API key rotated", pid);
+        Ok(key)
+    }
+
+    /// Issue a named key and start its scoped listener.
+    pub async fn issue_api_key(&self, pid: u32, req: IssueKeyRequest) -> Result<ScopedKey, String> {
+        let _serial = self.key_changes.lock().await;
+        let (ipc, listeners, info) = self.running_ipc(pid)?;
+        if let Some(port) = req.ws_port {
+            self.check_key_port(port)?;
+        }
+        let key = KeyStore::new(std::path::Path::new(&info.data_dir)).issue(&info.socket_path, req)?;
+
+        let rt = self.with_daemon(pid, |d| d.rt.clone())?;
+        let listener = {
+            let _guard = rt.enter();
+            spawn_listener(ListenerSpec::scoped(&key), &ipc)
+        };
+        listeners.lock().unwrap().insert(key.name.clone(), listener);
+        info!("Instance {} issued API key '{}' ({:?})", pid, key.name, key.scopes);
+        Ok(key)
+    }
+
+    /// Refuse a key ws_port that a running instance or any of its keys already uses, or
+    /// that something else is listening on.
+    fn check_key_port(&self, port: u16) -> Result<(), String> {
+        for daemon in self.daemons.lock().unwrap().iter() {
+            if daemon.info.ws_port == port {
+                return Err(format!("ws_port {} is the port of instance {}", port, daemon.info.pid));
+            }
+            let keys = KeyStore::new(std::path::Path::new(&daemon.info.data_dir)).load()?;
+            if let Some(key) = keys.iter().find(|k| k.ws_port == Some(port)) {
+                return Err(format!(
+                    "ws_port {} is already used by API key '{}' of instance {}",
+                    port, key.name, daemon.info.pid
+                ));
+            }
+        }
+        if !self.in_memory_ipc && std::net::TcpStream::connect(format!("127.0.0.1:{}", port)).is_ok() {
+            return Err(format!("Port {} already in use", port));
+        }
+        Ok(())
+    }
+
+    pub fn list_api_keys(&self, pid: u32) -> Result<Vec<ApiKeyInfo>, String> {
+        let info = self.with_daemon(pid, |d| d.info.clone())?;
+        let keys = KeyStore::new(std::path::Path::new(&info.data_dir)).load()?;
+        Ok(keys.iter().map(|k| k.info()).collect())
+    }
+
+    /// Delete a named key and close its listener. Calls on connections the listener
+    /// accepted earlier are refused from then on.
+    pub async fn revoke_api_key(&self, pid: u32, name: &str) -> Result<(), String> {
+        let _serial = self.key_changes.lock().await;
+        let (_, listeners, info) = self.running_ipc(pid)?;
+        KeyStore::new(std::path::Path::new(&info.data_dir)).revoke(name)?;
+
+        let old = listeners.lock().unwrap().remove(name);
+        close_listener(old).await;
+        info!("Instance {} revoked API key '{}'", pid, name);
+        Ok(())
+    }
+
+    /// Replace the instance's method allow/deny lists. Takes effect on the next call
+    /// without restarting the listeners.
+    pub fn set_ipc_policy(&self, pid: u32, policy: MethodPolicy) -> Result<(), String> {
+        let (ipc, _, info) = self.running_ipc(pid)?;
+        policy.save(std::path::Path::new(&info.data_dir))?;
+        *ipc.guard.policy.write().unwrap() = policy;
+        Ok(())
+    }
+
+    pub fn get_ipc_policy(&self, pid: u32) -> Result<MethodPolicy, String> {
+        let info = self.with_daemon(pid, |d| d.info.clone())?;
+        Ok(MethodPolicy::load(std::path::Path::new(&info.data_dir)))
+    }
+
+    pub fn read_audit_log(&self, pid: u32, since: usize, limit: usize) -> Result<Vec<AuditRecord>, String> {
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        ipc.guard.log.read(since, limit)
+    }
+
+    /// Number of calls per deprecated unprefixed method, across restarts.
+    pub fn legacy_call_stats(&self, pid: u32) -> Result<HashMap<String, u64>, String> {
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        Ok(ipc.legacy.counts())
+    }
+
+    /// Run `f` on an instance's state while the daemon list is locked. Clone what's needed
+    /// out of it rather than doing slow work inside `f`.
+    pub(crate) fn with_daemon<T>(&self, pid: u32, f: impl FnOnce(&ManagedDaemon) -> T) -> Result<T, String> {
+        let daemons = self.daemons.lock().unwrap();
+        daemons
+            .iter()
+            .find(|d| d.info.pid == pid)
+            .map(f)
+            .ok_or_else(|| format!("No daemon with instance ID {}", pid))
+    }
+
     pub fn stop_all(&self) {
         let mut daemons = self.daemons.lock().unwrap();
         for d in daemons.iter() {
-            d.abort.abort();
+            d.abort_all(&self.runtime);
         }
         daemons.clear();
         self.logs.lock().unwrap().clear();
+        self.clear_all_faults();
     }
 }
 
@@ -549,3 +1177,41 @@ impl Drop for DaemonManager {
         self.stop_all();
     }
 }
+
+/// Data dir of the primary instance.
+fn primary_data_dir() -> PathBuf {
+    dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")).join(".craftobj")
+}
+
+/// Localhost multiaddr for dialing an instance, from the first TCP address it is bound to
+/// or listens on (e.g. "/ip4/0.0.0.0/tcp/44001" -> "/ip4/127.0.0.1/tcp/44001/p2p/12D3Koo...").
+/// Instances without a TCP listener are dialed on their first address as-is.
+pub(crate) fn loopback_addr(instance: &DaemonInstance) -> String {
+    // Bound addresses first: a configured `/tcp/0` doesn't say which port was picked
+    let tcp = instance.bound_addrs.iter().chain(&instance.listen_addrs).find_map(|addr| {
+        let mut parts = addr.split('/').skip(1);
+        let (family, _, proto, port) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        (proto == "tcp" && port != "0").then(|| match family {
+            "ip6" => format!("/ip6/::1/tcp/{}", port),
+            _ => format!("/ip4/127.0.0.1/tcp/{}", port),
+        })
+    });
+    let base = tcp.unwrap_or_else(|| instance.listen_addr.clone());
+    format!("{}/p2p/{}", base, instance.peer_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primary_follows_the_data_dir_not_start_order() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let manager = DaemonManager::new_in_memory(Arc::new(Mutex::new(HashMap::new())), rt.handle().clone());
+        let home = primary_data_dir();
+
+        assert!(manager.takes_primary(None));
+        assert!(manager.takes_primary(Some(&home.to_string_lossy())));
+        assert!(!manager.takes_primary(Some("/tmp/craftobj-node-3")));
+    }
+}
@@ -0,0 +1,512 @@
+//! Publishing whole directories.
+//!
+//! `publish_directory` walks a local folder, skips anything matched by the ignore patterns
+//! (the request's own plus a `.craftignore` at the root), and publishes each file through
+//! the instance's handler, several at a time. It then publishes a `DirectoryManifest`
+//! listing every file's relative path, CID, size and SHA-256, so the whole folder can be
+//! fetched back by the manifest's CID.
+//!
+//! Ignore patterns follow a small subset of gitignore: `*` and `?` match within a path
+//! component and `**` matches any number of components. A pattern containing `/` is matched
+//! against the path relative to the root, any other pattern against each component, and a
+//! trailing `/` restricts it to directories.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+use tracing::{info, warn};
+
+use crate::api_keys::now_secs;
+use crate::content_ctl::ContentControl;
+use crate::daemon_manager::DaemonManager;
+use crate::resources::IoLimiter;
+use crate::vfs::{self, Vfs};
+
+pub const MANIFEST_FORMAT: &str = "craftobj-directory/1";
+const DEFAULT_PARALLELISM: usize = 4;
+const MAX_PARALLELISM: usize = 32;
+const DEFAULT_IGNORES: &[&str] = &[".git/", ".DS_Store", ".craftignore"];
+const HASH_CHUNK: usize = 1024 * 1024;
+/// Content larger than this is never read as a directory manifest.
+pub const MAX_MANIFEST_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PublishDirRequest {
+    pub path: String,
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Files published at once (default 4)
+    #[serde(default)]
+    pub parallelism: Option<usize>,
+    /// Also link the published files into the instance's VFS under this directory
+    #[serde(default)]
+    pub vfs_path: Option<String>,
+    /// Manifest CID of the version this publish replaces, recorded in the new manifest
+    #[serde(default)]
+    pub previous: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Relative to the directory root, `/`-separated
+    pub path: String,
+    pub cid: String,
+    pub size: u64,
+    pub sha256: String,
+    pub mode: u32,
+    pub modified_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryManifest {
+    pub format: String,
+    /// Name of the directory that was published
+    pub name: String,
+    pub created_at: u64,
+    /// Manifest of the version this one replaces
+    #[serde(default)]
+    pub previous: Option<String>,
+    pub total_size: u64,
+    /// Sorted by path
+    pub files: Vec<ManifestFile>,
+}
+
+impl DirectoryManifest {
+    /// Parse a manifest and reject paths that would escape the directory.
+    pub fn parse(raw: &[u8]) -> Result<Self, String> {
+        let manifest: Self =
+            serde_json::from_slice(raw).map_err(|e| format!("Not a directory manifest: {}", e))?;
+        if manifest.format != MANIFEST_FORMAT {
+            return Err(format!("Unsupported directory manifest format '{}'", manifest.format));
+        }
+        for file in &manifest.files {
+            let unsafe_path = file.path.is_empty()
+                || file.path.starts_with('/')
+                || file.path.split('/').any(|c| c.is_empty() || c == "." || c == ".." || c.contains('\\'));
+            if unsafe_path {
+                return Err(format!("Manifest contains an unsafe path '{}'", file.path));
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublishedDirectory {
+    pub cid: String,
+    pub files: usize,
+    pub total_size: u64,
+    /// Files left out by ignore patterns, plus symlinks
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublishProgress {
+    pub root: String,
+    /// File that just finished, relative to the root; empty for the manifest step
+    pub file: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub error: Option<String>,
+}
+
+pub type ProgressFn = Arc<dyn Fn(&PublishProgress) + Send + Sync>;
+
+/// Compiled ignore patterns.
+pub struct IgnoreRules(Vec<(Vec<String>, bool, bool)>);
+
+impl IgnoreRules {
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        let rules = patterns
+            .into_iter()
+            .map(str::trim)
+            .filter(|p| !p.is_empty() && !p.starts_with('#'))
+            .map(|p| {
+                let dir_only = p.ends_with('/');
+                let p = p.trim_end_matches('/');
+                let anchored = p.contains('/');
+                let parts = p.trim_start_matches('/').split('/').map(str::to_string).collect();
+                (parts, anchored, dir_only)
+            })
+            .collect();
+        Self(rules)
+    }
+
+    /// Built-in ignores, `extra`, and the root's `.craftignore`.
+    pub fn for_root(root: &Path, extra: &[String]) -> Self {
+        let craftignore = std::fs::read_to_string(root.join(".craftignore")).unwrap_or_default();
+        Self::new(
+            DEFAULT_IGNORES
+                .iter()
+                .copied()
+                .chain(extra.iter().map(String::as_str))
+                .chain(craftignore.lines()),
+        )
+    }
+
+    /// `rel` is `/`-separated and relative to the root.
+    pub fn is_ignored(&self, rel: &str, is_dir: bool) -> bool {
+        let components: Vec<&str> = rel.split('/').collect();
+        self.0.iter().any(|(parts, anchored, dir_only)| {
+            if *dir_only && !is_dir {
+                return false;
+            }
+            if *anchored {
+                let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+                match_components(&parts, &components)
+            } else {
+                components.last().is_some_and(|name| glob(&parts[0], name))
+            }
+        })
+    }
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            match_components(&pattern[1..], path) || (!path.is_empty() && match_components(pattern, &path[1..]))
+        }
+        (Some(p), Some(c)) => glob(p, c) && match_components(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+/// `*` and `?` wildcard match of a single component.
+fn glob(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    let (mut pi, mut ni) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        match p.get(pi) {
+            Some('*') => {
+                backtrack = Some((pi, ni));
+                pi += 1;
+            }
+            Some(&c) if c == '?' || c == n[ni] => {
+                pi += 1;
+                ni += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    pi = bp + 1;
+                    ni = bn + 1;
+                    backtrack = Some((bp, bn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+pub(crate) struct LocalFile {
+    pub abs: PathBuf,
+    pub rel: String,
+    pub size: u64,
+    pub mode: u32,
+    pub modified_at: u64,
+}
+
+/// Files under `root` that aren't ignored, in path order, and how many entries were skipped.
+pub(crate) fn walk(root: &Path, rules: &IgnoreRules) -> Result<(Vec<LocalFile>, usize), String> {
+    let mut files = Vec::new();
+    let mut skipped = 0;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = std::fs::read_dir(&dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        for entry in entries.flatten() {
+            let abs = entry.path();
+            let rel = abs
+                .strip_prefix(root)
+                .unwrap_or(&abs)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let Ok(meta) = std::fs::symlink_metadata(&abs) else {
+                skipped += 1;
+                continue;
+            };
+            if meta.file_type().is_symlink() || rules.is_ignored(&rel, meta.is_dir()) {
+                skipped += 1;
+            } else if meta.is_dir() {
+                pending.push(abs);
+            } else if meta.is_file() {
+                files.push(LocalFile {
+                    abs,
+                    rel,
+                    size: meta.len(),
+                    mode: file_mode(&meta),
+                    modified_at: meta
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map_or(0, |d| d.as_secs()),
+                });
+            }
+        }
+    }
+    files.sort_by(|a, b| a.rel.cmp(&b.rel));
+    Ok((files, skipped))
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &std::fs::Metadata) -> u32 {
+    if meta.permissions().readonly() { 0o444 } else { 0o644 }
+}
+
+/// SHA-256 of a file, read within the instance's disk budget.
+pub async fn hash_file(path: &Path, io: Option<&IoLimiter>) -> Result<String, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; HASH_CHUNK];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        if let Some(io) = io {
+            io.acquire(n as u64).await;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Publish `req.path` file by file, then its manifest.
+pub async fn publish_directory(
+    content: ContentControl,
+    io: Option<Arc<IoLimiter>>,
+    vfs: Option<Arc<Vfs>>,
+    staging: &Path,
+    req: PublishDirRequest,
+    progress: ProgressFn,
+) -> Result<PublishedDirectory, String> {
+    let root = PathBuf::from(&req.path);
+    if !root.is_dir() {
+        return Err(format!("'{}' is not a directory", req.path));
+    }
+    let vfs_root = req.vfs_path.as_deref().map(vfs::normalize).transpose()?;
+    let parallelism = req.parallelism.unwrap_or(DEFAULT_PARALLELISM).clamp(1, MAX_PARALLELISM);
+    let rules = IgnoreRules::for_root(&root, &req.ignore);
+
+    let walk_root = root.clone();
+    let (files, skipped) = tokio::task::spawn_blocking(move || walk(&walk_root, &rules))
+        .await
+        .map_err(|e| format!("Directory walk failed: {}", e))??;
+    let files_total = files.len();
+    let bytes_total: u64 = files.iter().map(|f| f.size).sum();
+    info!("Publishing {} ({} files, {} bytes, {} skipped)", root.display(), files_total, bytes_total, skipped);
+
+    let slots = Arc::new(tokio::sync::Semaphore::new(parallelism));
+    let done = Arc::new(AtomicU64::new(0));
+    let bytes_done = Arc::new(AtomicU64::new(0));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, file) in files.into_iter().enumerate() {
+        let (content, io, slots) = (content.clone(), io.clone(), Arc::clone(&slots));
+        let (done, bytes_done, progress) = (Arc::clone(&done), Arc::clone(&bytes_done), Arc::clone(&progress));
+        let root_name = req.path.clone();
+        tasks.spawn(async move {
+            let _slot = slots.acquire_owned().await.map_err(|e| e.to_string())?;
+            let result = async {
+                let sha256 = hash_file(&file.abs, io.as_deref()).await?;
+                let published = content.publish(&file.abs).await?;
+                Ok::<_, String>(ManifestFile {
+                    path: file.rel.clone(),
+                    cid: published.cid,
+                    size: file.size,
+                    sha256,
+                    mode: file.mode,
+                    modified_at: file.modified_at,
+                })
+            }
+            .await;
+            let files_done = done.fetch_add(1, Ordering::Relaxed) as usize + 1;
+            let bytes = bytes_done.fetch_add(file.size, Ordering::Relaxed) + file.size;
+            progress(&PublishProgress {
+                root: root_name,
+                file: file.rel.clone(),
+                files_done,
+                files_total,
+                bytes_done: bytes,
+                bytes_total,
+                error: result.as_ref().err().cloned(),
+            });
+            result.map(|entry| (index, entry)).map_err(|e| format!("{}: {}", file.rel, e))
+        });
+    }
+
+    let mut published = Vec::with_capacity(files_total);
+    let mut failures = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined.map_err(|e| e.to_string()).and_then(|r| r) {
+            Ok(entry) => published.push(entry),
+            Err(e) => failures.push(e),
+        }
+    }
+    if let Some(first) = failures.first() {
+        warn!("Publishing {} failed for {} files", root.display(), failures.len());
+        return Err(format!("{} of {} files failed to publish; first: {}", failures.len(), files_total, first));
+    }
+    published.sort_by_key(|(index, _)| *index);
+
+    let manifest = DirectoryManifest {
+        format: MANIFEST_FORMAT.to_string(),
+        name: root.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        created_at: now_secs(),
+        previous: req.previous.clone(),
+        total_size: bytes_total,
+        files: published.into_iter().map(|(_, f)| f).collect(),
+    };
+    std::fs::create_dir_all(staging).map_err(|e| format!("Failed to create {}: {}", staging.display(), e))?;
+    // Same-named folders published within a second each stage a manifest of their own
+    static NEXT_MANIFEST: AtomicU64 = AtomicU64::new(0);
+    let manifest_path = staging.join(format!(
+        "{}-{}-{}.json",
+        manifest.name,
+        manifest.created_at,
+        NEXT_MANIFEST.fetch_add(1, Ordering::Relaxed)
+    ));
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    std::fs::write(&manifest_path, json).map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))?;
+    let manifest_cid = content.publish(&manifest_path).await?.cid;
+    progress(&PublishProgress {
+        root: req.path.clone(),
+        file: String::new(),
+        files_done: files_total,
+        files_total,
+        bytes_done: bytes_total,
+        bytes_total,
+        error: None,
+    });
+
+    if let (Some(vfs), Some(vfs_root)) = (vfs, vfs_root) {
+        for file in &manifest.files {
+            vfs.put_file(&vfs::join(&vfs_root, &file.path), &file.cid, file.size, Some(file.mode))?;
+        }
+    }
+
+    Ok(PublishedDirectory {
+        cid: manifest_cid,
+        files: manifest.files.len(),
+        total_size: bytes_total,
+        skipped,
+    })
+}
+
+impl DaemonManager {
+    pub async fn publish_directory(
+        &self,
+        pid: u32,
+        req: PublishDirRequest,
+        progress: ProgressFn,
+    ) -> Result<PublishedDirectory, String> {
+        let (ipc, _, info) = self.running_ipc(pid)?;
+        let (vfs, io) = self.with_daemon(pid, |d| (req.vfs_path.as_ref().map(|_| Arc::clone(&d.vfs)), d.io.clone()))?;
+        let staging = Path::new(&info.data_dir).join("dir-manifests");
+        let root = req.path.clone();
+        let published =
+            publish_directory(ContentControl::new(ipc.handler), io, vfs, &staging, req, progress).await?;
+        info!("Instance {} published directory {} as {} ({} files)", pid, root, published.cid, published.files);
+        Ok(published)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(patterns: &[&str]) -> IgnoreRules {
+        IgnoreRules::new(patterns.iter().copied())
+    }
+
+    #[test]
+    fn unanchored_patterns_match_the_name_at_any_depth() {
+        let r = rules(&["*.log", "Thumbs.db"]);
+        assert!(r.is_ignored("debug.log", false));
+        assert!(r.is_ignored("a/b/debug.log", false));
+        assert!(r.is_ignored("a/Thumbs.db", false));
+        assert!(!r.is_ignored("log", false));
+        assert!(!r.is_ignored("debug.log.txt", false));
+        // Only the last component is matched
+        assert!(!r.is_ignored("x.log/file", false));
+    }
+
+    #[test]
+    fn anchored_patterns_match_from_the_root() {
+        let r = rules(&["/build", "docs/*.md"]);
+        assert!(r.is_ignored("build", true));
+        assert!(!r.is_ignored("src/build", true));
+        assert!(r.is_ignored("docs/readme.md", false));
+        assert!(!r.is_ignored("x/docs/readme.md", false));
+        assert!(!r.is_ignored("docs/sub/readme.md", false));
+    }
+
+    #[test]
+    fn dir_only_patterns_skip_files() {
+        let r = rules(&["target/", "/out/"]);
+        assert!(r.is_ignored("target", true));
+        assert!(r.is_ignored("crates/a/target", true));
+        assert!(!r.is_ignored("target", false));
+        assert!(r.is_ignored("out", true));
+        assert!(!r.is_ignored("out", false));
+        assert!(!r.is_ignored("a/out", true));
+    }
+
+    #[test]
+    fn double_star_spans_any_number_of_dirs() {
+        let r = rules(&["**/node_modules", "a/**/z", "logs/**"]);
+        assert!(r.is_ignored("node_modules", true));
+        assert!(r.is_ignored("web/app/node_modules", true));
+        assert!(r.is_ignored("a/z", false));
+        assert!(r.is_ignored("a/b/c/z", false));
+        assert!(!r.is_ignored("b/a/z", false));
+        assert!(!r.is_ignored("a/b/zz", false));
+        assert!(r.is_ignored("logs/today", false));
+        assert!(r.is_ignored("logs/2024/01/today", false));
+        assert!(!r.is_ignored("old/logs/today", false));
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_not_patterns() {
+        let r = rules(&["# *", "", "   "]);
+        assert!(!r.is_ignored("anything", false));
+        assert!(!r.is_ignored("# x", false));
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("*", ""));
+        assert!(glob("*", "anything"));
+        assert!(glob("a?c", "abc"));
+        assert!(!glob("a?c", "ac"));
+        assert!(!glob("a?c", "abbc"));
+        assert!(glob("?", "é"));
+        assert!(glob("*ab", "aab"));
+        assert!(glob("*a*b", "xaxxb"));
+        assert!(glob("a*b*c", "abcbc"));
+        assert!(glob("a**", "a"));
+        assert!(!glob("a*", "ba"));
+        assert!(!glob("*.rs", "main.rsx"));
+        assert!(!glob("", "a"));
+        assert!(glob("", ""));
+    }
+}
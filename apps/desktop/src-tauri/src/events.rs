@@ -0,0 +1,342 @@
+//! Instance event bus helpers.
+//!
+//! Every instance has one `EventBus` of JSON-RPC notifications (`InstanceIpc::events`).
+//! CraftOBJ `DaemonEvent`s are bridged onto it unprefixed; node services publish through an
+//! `EventSink`, which prefixes the method with the service namespace (`tunnel.state_changed`).
+//! Each listener forwards the bus to its clients through a `TopicFilter`: the topics its
+//! API key was issued with, within what the key's scopes cover (a `tunnel` key only sees
+//! `tunnel.*`, a `data` or `data_read` key only CraftOBJ's own events, and so on).
+//!
+//! craftec_ipc pushes notifications to every connection of a listener and doesn't tell
+//! handlers which connection a call came from, so a client can't narrow the topics of the
+//! connection it is on. `events.subscribe {topics}` instead opens a listener of its own on a
+//! fresh socket (and a fresh WebSocket port, if the caller's listener has one), with the
+//! same key and routes, that forwards only those topics (within the ones the caller
+//! already sees). It lives until `events.unsubscribe {socket_path}` or until the listener
+//! it was opened from closes.
+//!
+//! The bus stamps every notification with a top-level `seq`. A consumer that falls behind
+//! gets a synthetic `events.lagged {missed, latest}` notification instead of a silent gap.
+//! With a replay buffer configured, a reconnecting client calls `events.replay {since}` to
+//! fetch what it missed, or learns from `complete: false` that it has to resync.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use craftec_ipc::server::IpcHandler;
+use serde_json::{json, Value};
+
+use crate::api_keys::KeyScope;
+use crate::audit::pattern_matches;
+use crate::listeners::Subscriptions;
+
+/// Capacity of the broadcast channel behind each bus.
+const BUS_CAPACITY: usize = 1024;
+
+#[derive(Default)]
+struct BusState {
+    next_seq: u64,
+    replay: VecDeque<(u64, String)>,
+}
+
+/// Sequenced broadcast of an instance's notifications.
+pub struct EventBus {
+    tx: tokio::sync::broadcast::Sender<String>,
+    state: Mutex<BusState>,
+    replay_capacity: usize,
+}
+
+impl EventBus {
+    /// `replay_capacity` notifications are kept for `events.replay`; 0 disables replay.
+    pub fn new(replay_capacity: usize) -> Self {
+        let (tx, _) = tokio::sync::broadcast::channel(BUS_CAPACITY);
+        Self {
+            tx,
+            state: Mutex::new(BusState {
+                next_seq: 1,
+                replay: VecDeque::new(),
+            }),
+            replay_capacity,
+        }
+    }
+
+    /// Stamp a serialized notification with the next `seq` and broadcast it.
+    pub fn publish(&self, notification: String) {
+        let Ok(Value::Object(mut obj)) = serde_json::from_str::<Value>(&notification) else {
+            let _ = self.tx.send(notification);
+            return;
+        };
+        // Hold the state lock through the send so sequence order is delivery order
+        let mut state = self.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        obj.insert("seq".to_string(), json!(seq));
+        let stamped = Value::Object(obj).to_string();
+        if self.replay_capacity > 0 {
+            if state.replay.len() == self.replay_capacity {
+                state.replay.pop_front();
+            }
+            state.replay.push_back((seq, stamped.clone()));
+        }
+        let _ = self.tx.send(stamped);
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<String> {
+        self.tx.subscribe()
+    }
+
+    /// Bytes held by the replay buffer.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.state.lock().unwrap().replay.iter().map(|(_, n)| n.len() as u64).sum()
+    }
+
+    /// Sequence number of the most recent notification (0 before the first).
+    pub fn latest_seq(&self) -> u64 {
+        self.state.lock().unwrap().next_seq - 1
+    }
+
+    /// Buffered notifications after `since`, and whether nothing in between was evicted.
+    pub fn replay_since(&self, since: u64) -> (Vec<String>, bool) {
+        let state = self.state.lock().unwrap();
+        let complete = match state.replay.front() {
+            Some((oldest, _)) => *oldest <= since.saturating_add(1),
+            None => since >= state.next_seq - 1,
+        };
+        let events = state
+            .replay
+            .iter()
+            .filter(|(seq, _)| *seq > since)
+            .map(|(_, n)| n.clone())
+            .collect();
+        (events, complete)
+    }
+}
+
+/// `events.lagged` notification for a consumer that skipped `missed` notifications.
+pub fn lagged_notification(missed: u64, latest: u64) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "events.lagged",
+        "params": { "missed": missed, "latest": latest },
+    })
+    .to_string()
+}
+
+/// Publishes a service's events onto the instance bus under its namespace.
+#[derive(Clone)]
+pub struct EventSink {
+    namespace: &'static str,
+    bus: Arc<EventBus>,
+}
+
+impl EventSink {
+    pub fn new(namespace: &'static str, bus: Arc<EventBus>) -> Self {
+        Self { namespace, bus }
+    }
+
+    pub fn emit(&self, event: &str, params: Value) {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": format!("{}.{}", self.namespace, event),
+            "params": params,
+        });
+        self.bus.publish(notification.to_string());
+    }
+}
+
+/// Topics a listener forwards. Patterns use the same syntax as the IPC method policy
+/// (`tunnel.*`, `peer_connected`, `*`). A filter is made of layers, which a topic must all
+/// match; an empty layer matches everything. Scoped keys are further limited to the
+/// namespaces their scopes grant.
+#[derive(Clone)]
+pub struct TopicFilter {
+    layers: Arc<Vec<Vec<String>>>,
+    /// `None` for full-access listeners
+    scopes: Option<Arc<Vec<KeyScope>>>,
+}
+
+impl Default for TopicFilter {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl TopicFilter {
+    pub fn new(topics: Vec<String>) -> Self {
+        Self {
+            layers: Arc::new(vec![topics]),
+            scopes: None,
+        }
+    }
+
+    /// Only forward topics of namespaces `scopes` grant; `None` keeps full access.
+    pub fn within_scopes(mut self, scopes: Option<&[KeyScope]>) -> Self {
+        self.scopes = scopes.map(|s| Arc::new(s.to_vec()));
+        self
+    }
+
+    /// A filter that also has to match `topics`.
+    pub fn narrowed(&self, topics: Vec<String>) -> Self {
+        let mut layers = (*self.layers).clone();
+        layers.push(topics);
+        Self {
+            layers: Arc::new(layers),
+            scopes: self.scopes.clone(),
+        }
+    }
+
+    /// The narrowest layer's patterns.
+    pub fn topics(&self) -> &[String] {
+        self.layers.last().map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn matches(&self, notification: &str) -> bool {
+        let Some(method) = notification_method(notification) else {
+            return self.scopes.is_none();
+        };
+        if let Some(scopes) = &self.scopes {
+            if !scope_covers(scopes, &method) {
+                return false;
+            }
+        }
+        self.layers
+            .iter()
+            .all(|layer| layer.is_empty() || layer.iter().any(|t| pattern_matches(t, &method)))
+    }
+}
+
+/// Whether a key with `scopes` may see `topic`. CraftOBJ's own events are unprefixed, and
+/// `events.*` (lag reports) go to everyone.
+fn scope_covers(scopes: &[KeyScope], topic: &str) -> bool {
+    let data = scopes.iter().any(|s| matches!(s, KeyScope::Data | KeyScope::DataRead));
+    match topic.split_once('.') {
+        None => data,
+        Some(("events", _)) => true,
+        Some(("data", _)) => data,
+        Some(("tunnel", _)) => scopes.contains(&KeyScope::Tunnel),
+        Some(("settlement", _)) => scopes.contains(&KeyScope::Settlement),
+        Some(_) => false,
+    }
+}
+
+/// The `method` of a serialized JSON-RPC notification.
+pub fn notification_method(notification: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(notification).ok()?;
+    value.get("method")?.as_str().map(str::to_string)
+}
+
+/// `events.*` namespace: the topics a listener forwards, topic subscriptions, and replay of
+/// what a client missed.
+pub struct EventsHandler {
+    pub topics: TopicFilter,
+    pub bus: Arc<EventBus>,
+    /// `None` where subscriptions can't be opened (on a subscription itself, in-memory calls)
+    pub subscriptions: Option<Arc<Subscriptions>>,
+}
+
+impl IpcHandler for EventsHandler {
+    fn handle(
+        &self,
+        method: &str,
+        params: Option<Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + '_>> {
+        let result = match method {
+            "topics" => Ok(json!({ "topics": self.topics.topics() })),
+            "subscribe" | "unsubscribe" => match &self.subscriptions {
+                None => Err("Subscriptions can't be opened from this connection".to_string()),
+                Some(subscriptions) if method == "subscribe" => params
+                    .as_ref()
+                    .and_then(|p| p.get("topics"))
+                    .and_then(|t| serde_json::from_value::<Vec<String>>(t.clone()).ok())
+                    .ok_or_else(|| "Expected params {\"topics\": [string]}".to_string())
+                    .and_then(|topics| subscriptions.open(topics)),
+                Some(subscriptions) => params
+                    .as_ref()
+                    .and_then(|p| p.get("socket_path"))
+                    .and_then(|s| s.as_str())
+                    .ok_or_else(|| "Expected params {\"socket_path\": string}".to_string())
+                    .and_then(|path| subscriptions.close(path))
+                    .map(|_| json!({ "closed": true })),
+            },
+            "replay" => params
+                .as_ref()
+                .and_then(|p| p.get("since"))
+                .and_then(|s| s.as_u64())
+                .ok_or_else(|| "Expected params {\"since\": seq, \"topics\"?: [string]}".to_string())
+                .map(|since| {
+                    // Optional narrowing for this call only
+                    let narrow = params
+                        .as_ref()
+                        .and_then(|p| p.get("topics"))
+                        .and_then(|t| serde_json::from_value::<Vec<String>>(t.clone()).ok())
+                        .map(TopicFilter::new)
+                        .unwrap_or_default();
+                    let (events, complete) = self.bus.replay_since(since);
+                    let events: Vec<Value> = events
+                        .iter()
+                        .filter(|n| self.topics.matches(n) && narrow.matches(n))
+                        .filter_map(|n| serde_json::from_str(n).ok())
+                        .collect();
+                    json!({ "events": events, "latest": self.bus.latest_seq(), "complete": complete })
+                }),
+            other => Err(format!("Method not found: events.{}", other)),
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(method: &str) -> String {
+        json!({ "jsonrpc": "2.0", "method": method, "params": {} }).to_string()
+    }
+
+    #[test]
+    fn filter_matches_patterns() {
+        let filter = TopicFilter::new(vec!["tunnel.*".to_string(), "peer_connected".to_string()]);
+        assert!(filter.matches(&notification("tunnel.state_changed")));
+        assert!(filter.matches(&notification("peer_connected")));
+        assert!(!filter.matches(&notification("peer_disconnected")));
+        assert!(TopicFilter::default().matches(&notification("anything")));
+    }
+
+    #[test]
+    fn scoped_filters_only_pass_granted_namespaces() {
+        let tunnel = TopicFilter::new(Vec::new()).within_scopes(Some(&[KeyScope::Tunnel]));
+        assert!(tunnel.matches(&notification("tunnel.state_changed")));
+        assert!(tunnel.matches(&notification("events.lagged")));
+        assert!(!tunnel.matches(&notification("peer_connected")));
+
+        let read = TopicFilter::new(vec!["*".to_string()]).within_scopes(Some(&[KeyScope::DataRead]));
+        assert!(read.matches(&notification("peer_connected")));
+        assert!(!read.matches(&notification("tunnel.state_changed")));
+
+        let narrowed = TopicFilter::new(vec!["tunnel.*".to_string()]).narrowed(vec!["tunnel.peers_changed".to_string()]);
+        assert!(narrowed.matches(&notification("tunnel.peers_changed")));
+        assert!(!narrowed.matches(&notification("tunnel.state_changed")));
+        assert_eq!(narrowed.topics(), ["tunnel.peers_changed".to_string()]);
+    }
+
+    #[test]
+    fn replay_reports_evicted_gaps() {
+        let bus = EventBus::new(2);
+        for method in ["a", "b", "c"] {
+            bus.publish(notification(method));
+        }
+        assert_eq!(bus.latest_seq(), 3);
+        let (events, complete) = bus.replay_since(1);
+        assert_eq!(events.len(), 2);
+        assert!(complete);
+        let (events, complete) = bus.replay_since(0);
+        assert_eq!(events.len(), 2);
+        assert!(!complete);
+        // A client-supplied `since` past the end must not overflow
+        let (events, complete) = bus.replay_since(u64::MAX);
+        assert!(events.is_empty());
+        assert!(complete);
+    }
+}
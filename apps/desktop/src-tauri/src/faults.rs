@@ -0,0 +1,584 @@
+//! Fault injection for resilience testing.
+//!
+//! `DaemonManager::inject_fault` breaks a running instance on purpose: it cuts it off from
+//! specific peers, throttles its bandwidth, pauses its event loops, or corrupts or deletes a
+//! fraction of its stored pieces. Every fault carries a revert timer. When the timer fires,
+//! or when `revert_fault` is called, the instance is put back the way it was. Damaged pieces
+//! are backed up under `{data_dir}/.faults/<id>/` first so they can be restored. Injection
+//! and revert both leave a line in the instance's log buffer.
+//!
+//! Stopping an instance (which moving its data dir does too) ends its faults early: damaged
+//! pieces are put back once its tasks have ended, and the rest go away with the swarm.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+
+use crate::api_keys::now_secs;
+use crate::daemon_manager::{push_log, DaemonManager, SharedLogs};
+use crate::peer_store::PeerStore;
+use crate::swarm_ctl::SwarmControl;
+
+/// Directories under the data dir that hold piece data.
+const PIECE_DIRS: &[&str] = &["chunks", "storage"];
+/// Bytes flipped per corrupted piece.
+const CORRUPT_BYTES: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FaultKind {
+    /// Disconnect from and ban these peers
+    Disconnect { peers: Vec<String> },
+    /// Cap swarm bandwidth
+    Throttle { bytes_per_sec: u64 },
+    /// Stop polling the instance's event loops
+    Pause,
+    /// Flip bytes in a fraction (0..=1) of stored pieces
+    CorruptPieces { fraction: f64 },
+    /// Remove a fraction (0..=1) of stored pieces
+    DeletePieces { fraction: f64 },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultSpec {
+    pub kind: FaultKind,
+    pub duration_secs: u64,
+    /// Seed for choosing pieces; random when absent
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultInfo {
+    pub id: u64,
+    pub pid: u32,
+    pub kind: FaultKind,
+    pub started_at: u64,
+    pub revert_at: u64,
+    /// Peers cut off or pieces damaged
+    pub affected: usize,
+}
+
+/// How to undo a fault.
+enum Revert {
+    /// `peers` holds only the bans the fault added; `store` says which the user holds
+    Unban { swarm: SwarmControl, store: Arc<PeerStore>, peers: Vec<String> },
+    Unthrottle { swarm: SwarmControl },
+    Resume { pause: tokio::sync::watch::Sender<bool> },
+    RestorePieces { data_dir: PathBuf, backup: PathBuf, files: Vec<PathBuf> },
+}
+
+impl Revert {
+    async fn run(self) -> Result<(), String> {
+        match self {
+            Revert::Unban { swarm, store, peers } => {
+                for peer in &peers {
+                    // Banned by the user while the fault was active
+                    if store.get(peer).is_some_and(|p| p.banned) {
+                        continue;
+                    }
+                    swarm.unban(peer).await?;
+                }
+                Ok(())
+            }
+            Revert::Unthrottle { swarm } => swarm.set_bandwidth_limit(None).await.map(|_| ()),
+            Revert::Resume { pause } => {
+                pause.send_replace(false);
+                Ok(())
+            }
+            Revert::RestorePieces { data_dir, backup, files } => {
+                tokio::task::spawn_blocking(move || restore_pieces(&data_dir, &backup, &files))
+                    .await
+                    .map_err(|e| format!("Piece restore task failed: {}", e))?
+            }
+        }
+    }
+}
+
+pub(crate) struct ActiveFault {
+    info: FaultInfo,
+    /// `None` while the fault is still being applied; the entry only reserves its slot then
+    revert: Option<Revert>,
+    timer: Option<AbortHandle>,
+}
+
+pub(crate) type ActiveFaults = Arc<Mutex<HashMap<u64, ActiveFault>>>;
+
+impl DaemonManager {
+    /// Apply a fault to a running instance and schedule its revert.
+    pub async fn inject_fault(&self, pid: u32, spec: FaultSpec) -> Result<FaultInfo, String> {
+        let (ipc, _, instance) = self.running_ipc(pid)?;
+        let swarm = SwarmControl::new(ipc.handler);
+        let started_at = now_secs();
+        let mut info = FaultInfo {
+            id: 0,
+            pid,
+            kind: spec.kind.clone(),
+            started_at,
+            revert_at: started_at + spec.duration_secs,
+            affected: 0,
+        };
+
+        // Check for a clashing fault and reserve the slot in one go, so two concurrent
+        // injections can't both pass the check
+        {
+            let mut faults = self.faults.lock().unwrap();
+            let exclusive = |k: &FaultKind| matches!(k, FaultKind::Pause | FaultKind::Throttle { .. });
+            if exclusive(&spec.kind)
+                && faults.values().any(|f| {
+                    f.info.pid == pid && std::mem::discriminant(&f.info.kind) == std::mem::discriminant(&spec.kind)
+                })
+            {
+                return Err(format!("Instance {} already has an active fault of this type", pid));
+            }
+            let mut next = self.next_fault_id.lock().unwrap();
+            *next += 1;
+            info.id = *next;
+            faults.insert(
+                info.id,
+                ActiveFault {
+                    info: info.clone(),
+                    revert: None,
+                    timer: None,
+                },
+            );
+        }
+        let id = info.id;
+
+        let data_dir = PathBuf::from(&instance.data_dir);
+        let (revert, affected) = match self.apply(pid, id, &spec, &data_dir, swarm).await {
+            Ok(applied) => applied,
+            Err(e) => {
+                self.faults.lock().unwrap().remove(&id);
+                return Err(e);
+            }
+        };
+        info.affected = affected;
+
+        let line = format!("Fault #{} injected: {:?} for {}s ({} affected)", id, info.kind, spec.duration_secs, affected);
+        warn!("Instance {}: {}", pid, line);
+        push_log(&self.logs, pid, line, true);
+
+        // Hold the table while the timer is spawned so it can't fire before the fault is recorded
+        let mut faults = self.faults.lock().unwrap();
+        let timer = self.runtime.spawn({
+            let faults = Arc::clone(&self.faults);
+            let logs = Arc::clone(&self.logs);
+            let duration = Duration::from_secs(spec.duration_secs);
+            async move {
+                tokio::time::sleep(duration).await;
+                let fault = faults.lock().unwrap().remove(&id);
+                if let Some(fault) = fault {
+                    finish(fault, &logs, "timer expired").await;
+                }
+            }
+        });
+        faults.insert(
+            id,
+            ActiveFault {
+                info: info.clone(),
+                revert: Some(revert),
+                timer: Some(timer.abort_handle()),
+            },
+        );
+        Ok(info)
+    }
+
+    /// Put the fault in place. A fault that fails partway is rolled back before the error
+    /// is returned, so nothing is left half applied without a revert.
+    async fn apply(
+        &self,
+        pid: u32,
+        id: u64,
+        spec: &FaultSpec,
+        data_dir: &Path,
+        swarm: SwarmControl,
+    ) -> Result<(Revert, usize), String> {
+        match &spec.kind {
+            FaultKind::Disconnect { peers } => {
+                let store = self.with_daemon(pid, |d| Arc::clone(&d.peers))?;
+                let mut banned = Vec::new();
+                for peer in peers {
+                    // A peer the user already banned stays banned after the revert
+                    let step = if store.get(peer).is_some_and(|p| p.banned) {
+                        swarm.disconnect(peer).await.map(|_| ())
+                    } else {
+                        match swarm.ban(peer).await {
+                            Ok(_) => {
+                                banned.push(peer.clone());
+                                swarm.disconnect(peer).await.map(|_| ())
+                            }
+                            Err(e) => Err(e),
+                        }
+                    };
+                    if let Err(e) = step {
+                        return Err(roll_back(Revert::Unban { swarm, store, peers: banned }, e).await);
+                    }
+                }
+                Ok((Revert::Unban { swarm, store, peers: banned }, peers.len()))
+            }
+            FaultKind::Throttle { bytes_per_sec } => {
+                swarm.set_bandwidth_limit(Some(*bytes_per_sec)).await?;
+                Ok((Revert::Unthrottle { swarm }, 0))
+            }
+            FaultKind::Pause => {
+                let pause = self.with_daemon(pid, |d| d.pause.clone())?;
+                pause.send_replace(true);
+                Ok((Revert::Resume { pause }, 0))
+            }
+            FaultKind::CorruptPieces { fraction } | FaultKind::DeletePieces { fraction } => {
+                let delete = matches!(spec.kind, FaultKind::DeletePieces { .. });
+                let backup = data_dir.join(".faults").join(id.to_string());
+                let mut rng = match spec.seed {
+                    Some(seed) => StdRng::seed_from_u64(seed),
+                    None => StdRng::from_entropy(),
+                };
+                let (dir, saved, fraction) = (data_dir.to_path_buf(), backup.clone(), *fraction);
+                let (files, damaged) = tokio::task::spawn_blocking(move || {
+                    let mut files = Vec::new();
+                    let damaged = damage_pieces(&dir, &saved, fraction, delete, &mut rng, &mut files);
+                    (files, damaged)
+                })
+                .await
+                .map_err(|e| format!("Piece damage task failed: {}", e))?;
+                let affected = files.len();
+                let revert = Revert::RestorePieces {
+                    data_dir: data_dir.to_path_buf(),
+                    backup,
+                    files,
+                };
+                match damaged {
+                    Ok(()) => Ok((revert, affected)),
+                    Err(e) => Err(roll_back(revert, e).await),
+                }
+            }
+        }
+    }
+
+    /// Undo a fault before its timer fires.
+    pub async fn revert_fault(&self, pid: u32, id: u64) -> Result<(), String> {
+        let fault = {
+            let mut faults = self.faults.lock().unwrap();
+            match faults.get(&id) {
+                Some(f) if f.info.pid == pid && f.revert.is_some() => faults.remove(&id),
+                _ => None,
+            }
+        }
+        .ok_or_else(|| format!("No active fault #{} on instance {}", id, pid))?;
+        if let Some(timer) = &fault.timer {
+            timer.abort();
+        }
+        finish(fault, &self.logs, "reverted manually").await;
+        Ok(())
+    }
+
+    /// Take a stopping instance's faults out of the table. The returned future puts back
+    /// damaged pieces and should run once the instance's tasks have ended; bans, throttles
+    /// and pauses go away with the swarm. Faults still being applied are left alone.
+    pub(crate) fn clear_faults(&self, pid: u32) -> impl Future<Output = ()> + Send + 'static {
+        let cleared = take_faults(&self.faults, Some(pid));
+        async move {
+            for fault in cleared {
+                let id = fault.info.id;
+                let Some(revert @ Revert::RestorePieces { .. }) = fault.revert else { continue };
+                match revert.run().await {
+                    Ok(()) => info!("Instance {}: fault #{} cleared on stop", pid, id),
+                    Err(e) => warn!("Instance {}: fault #{} could not be reverted on stop: {}", pid, id, e),
+                }
+            }
+        }
+    }
+
+    /// `clear_faults` for every instance, blocking; for shutdown, when nothing awaits.
+    pub(crate) fn clear_all_faults(&self) {
+        for fault in take_faults(&self.faults, None) {
+            if let Some(Revert::RestorePieces { data_dir, backup, files }) = fault.revert {
+                if let Err(e) = restore_pieces(&data_dir, &backup, &files) {
+                    warn!("Instance {}: fault #{} could not be reverted on stop: {}", fault.info.pid, fault.info.id, e);
+                }
+            }
+        }
+    }
+
+    pub fn list_faults(&self, pid: u32) -> Vec<FaultInfo> {
+        let faults = self.faults.lock().unwrap();
+        let mut list: Vec<FaultInfo> = faults
+            .values()
+            .filter(|f| f.info.pid == pid && f.revert.is_some())
+            .map(|f| f.info.clone())
+            .collect();
+        list.sort_by_key(|f| f.id);
+        list
+    }
+}
+
+/// Undo what a failed injection did so far; returns the injection error, plus the rollback's
+/// if that failed too.
+async fn roll_back(revert: Revert, error: String) -> String {
+    match revert.run().await {
+        Ok(()) => error,
+        Err(e) => format!("{} (rolling back also failed: {})", error, e),
+    }
+}
+
+/// Remove the applied faults of `pid` (or of every instance) and cancel their timers.
+fn take_faults(faults: &ActiveFaults, pid: Option<u32>) -> Vec<ActiveFault> {
+    let mut faults = faults.lock().unwrap();
+    let ids: Vec<u64> = faults
+        .values()
+        .filter(|f| pid.is_none_or(|pid| f.info.pid == pid) && f.revert.is_some())
+        .map(|f| f.info.id)
+        .collect();
+    let taken: Vec<ActiveFault> = ids.iter().filter_map(|id| faults.remove(id)).collect();
+    for timer in taken.iter().filter_map(|f| f.timer.as_ref()) {
+        timer.abort();
+    }
+    taken
+}
+
+async fn finish(fault: ActiveFault, logs: &SharedLogs, reason: &str) {
+    let ActiveFault { info, revert, .. } = fault;
+    let Some(revert) = revert else { return };
+    match revert.run().await {
+        Ok(()) => {
+            let line = format!("Fault #{} reverted ({})", info.id, reason);
+            info!("Instance {}: {}", info.pid, line);
+            push_log(logs, info.pid, line, false);
+        }
+        Err(e) => {
+            let line = format!("Fault #{} revert failed ({}): {}", info.id, reason, e);
+            warn!("Instance {}: {}", info.pid, line);
+            push_log(logs, info.pid, line, true);
+        }
+    }
+}
+
+/// Move a random `fraction` of piece files into `backup`, then either leave them deleted or
+/// put back a copy with flipped bytes. Every piece moved is added to `damaged` (relative to
+/// `data_dir`) right away, so a failure partway can still be restored.
+fn damage_pieces(
+    data_dir: &Path,
+    backup: &Path,
+    fraction: f64,
+    delete: bool,
+    rng: &mut StdRng,
+    damaged: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("Fraction must be between 0 and 1, got {}", fraction));
+    }
+    let mut pieces = Vec::new();
+    for dir in PIECE_DIRS {
+        collect_files(&data_dir.join(dir), &mut pieces);
+    }
+    pieces.sort();
+    let count = (pieces.len() as f64 * fraction).ceil() as usize;
+    let chosen = rand::seq::index::sample(rng, pieces.len(), count.min(pieces.len()));
+
+    for i in chosen.iter() {
+        let path = &pieces[i];
+        let rel = path.strip_prefix(data_dir).unwrap_or(path).to_path_buf();
+        let saved = backup.join(&rel);
+        if let Some(parent) = saved.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        if delete {
+            std::fs::rename(path, &saved).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+            damaged.push(rel);
+        } else {
+            let mut bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if bytes.is_empty() {
+                continue;
+            }
+            std::fs::rename(path, &saved).map_err(|e| format!("Failed to back up {}: {}", path.display(), e))?;
+            damaged.push(rel);
+            for _ in 0..CORRUPT_BYTES.min(bytes.len()) {
+                let at = rng.gen_range(0..bytes.len());
+                bytes[at] ^= 0xFF;
+            }
+            std::fs::write(path, &bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move backed-up pieces back into place and drop the backup dir.
+fn restore_pieces(data_dir: &Path, backup: &Path, files: &[PathBuf]) -> Result<(), String> {
+    for rel in files {
+        std::fs::rename(backup.join(rel), data_dir.join(rel))
+            .map_err(|e| format!("Failed to restore {}: {}", rel.display(), e))?;
+    }
+    match std::fs::remove_dir_all(backup) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to remove {}: {}", backup.display(), e)),
+        _ => Ok(()),
+    }
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use craftec_ipc::server::IpcHandler;
+    use serde_json::Value;
+    use std::pin::Pin;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("craftstudio-faults-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("chunks").join("ab")).unwrap();
+        std::fs::create_dir_all(dir.join("storage")).unwrap();
+        for i in 0..4 {
+            std::fs::write(dir.join("chunks").join("ab").join(format!("piece{}", i)), vec![i as u8; 64]).unwrap();
+        }
+        std::fs::write(dir.join("storage").join("piece4"), vec![4u8; 64]).unwrap();
+        dir
+    }
+
+    fn pieces(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = Vec::new();
+        for sub in PIECE_DIRS {
+            collect_files(&dir.join(sub), &mut files);
+        }
+        files.sort();
+        files.into_iter().map(|f| (f.clone(), std::fs::read(&f).unwrap())).collect()
+    }
+
+    #[test]
+    fn deleted_pieces_are_restored() {
+        let dir = temp_dir("delete");
+        let before = pieces(&dir);
+        let backup = dir.join(".faults").join("1");
+        let mut damaged = Vec::new();
+        damage_pieces(&dir, &backup, 0.5, true, &mut StdRng::seed_from_u64(7), &mut damaged).unwrap();
+        assert_eq!(damaged.len(), 3);
+        assert_eq!(pieces(&dir).len(), 2);
+
+        restore_pieces(&dir, &backup, &damaged).unwrap();
+        assert_eq!(pieces(&dir), before);
+        assert!(!backup.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn corrupted_pieces_are_restored() {
+        let dir = temp_dir("corrupt");
+        let before = pieces(&dir);
+        let backup = dir.join(".faults").join("2");
+        let mut damaged = Vec::new();
+        damage_pieces(&dir, &backup, 1.0, false, &mut StdRng::seed_from_u64(7), &mut damaged).unwrap();
+        assert_eq!(damaged.len(), before.len());
+        let after = pieces(&dir);
+        assert_eq!(after.len(), before.len());
+        assert!(after.iter().zip(&before).all(|(a, b)| a.0 == b.0 && a.1 != b.1));
+
+        restore_pieces(&dir, &backup, &damaged).unwrap();
+        assert_eq!(pieces(&dir), before);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn same_seed_damages_same_pieces() {
+        let dir = temp_dir("seed");
+        let backup = dir.join(".faults").join("3");
+        let mut first = Vec::new();
+        damage_pieces(&dir, &backup, 0.4, true, &mut StdRng::seed_from_u64(42), &mut first).unwrap();
+        restore_pieces(&dir, &backup, &first).unwrap();
+        let mut second = Vec::new();
+        damage_pieces(&dir, &backup, 0.4, true, &mut StdRng::seed_from_u64(42), &mut second).unwrap();
+        assert_eq!(first, second);
+
+        let mut none = Vec::new();
+        assert!(damage_pieces(&dir, &backup, 1.5, true, &mut StdRng::seed_from_u64(42), &mut none).is_err());
+        assert!(none.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Records the swarm calls it is sent.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(String, Option<Value>)>>);
+
+    impl IpcHandler for Recorder {
+        fn handle(
+            &self,
+            method: &str,
+            params: Option<Value>,
+        ) -> Pin<Box<dyn std::future::Future<Output = Result<Value, String>> + Send + '_>> {
+            self.0.lock().unwrap().push((method.to_string(), params));
+            Box::pin(async { Ok(Value::Null) })
+        }
+    }
+
+    #[test]
+    fn unban_leaves_user_bans_in_place() {
+        let dir = temp_dir("unban");
+        let store = Arc::new(PeerStore::open(&dir));
+        store.set_banned("peer-b", true).unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let revert = Revert::Unban {
+            swarm: SwarmControl::new(recorder.clone()),
+            store,
+            peers: vec!["peer-a".to_string(), "peer-b".to_string()],
+        };
+        tokio::runtime::Runtime::new().unwrap().block_on(revert.run()).unwrap();
+
+        let calls = recorder.0.lock().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "unban_peer");
+        assert_eq!(calls[0].1.as_ref().unwrap()["peer_id"], "peer-a");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stopping_takes_only_that_instances_applied_faults() {
+        let faults: ActiveFaults = Arc::default();
+        let fault = |id: u64, pid: u32, applied: bool| ActiveFault {
+            info: FaultInfo {
+                id,
+                pid,
+                kind: FaultKind::Pause,
+                started_at: 0,
+                revert_at: 60,
+                affected: 0,
+            },
+            revert: applied.then(|| Revert::Resume {
+                pause: tokio::sync::watch::channel(true).0,
+            }),
+            timer: None,
+        };
+        {
+            let mut table = faults.lock().unwrap();
+            table.insert(1, fault(1, 10, true));
+            table.insert(2, fault(2, 10, false));
+            table.insert(3, fault(3, 20, true));
+        }
+
+        let taken = take_faults(&faults, Some(10));
+        assert_eq!(taken.iter().map(|f| f.info.id).collect::<Vec<_>>(), vec![1]);
+        let mut left: Vec<u64> = faults.lock().unwrap().keys().copied().collect();
+        left.sort();
+        assert_eq!(left, vec![2, 3]);
+
+        assert_eq!(take_faults(&faults, None).len(), 1);
+        assert_eq!(faults.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,412 @@
+//! Local HTTP gateway for content.
+//!
+//! With `gateway` set in its config, an instance serves `GET /content/<cid>` over plain HTTP
+//! on loopback, or on `bind` when given. The gateway has no authentication, so it's meant for
+//! browsers and curl on the same machine. Because any page open in the browser can reach it,
+//! it only serves content in the instance's catalog, and files of directories in it, unless
+//! `fetch_remote` is set. The catalog is re-read at most every few seconds. Requests whose
+//! `Host` isn't the bound address, `localhost`, `127.0.0.1` or `[::1]` are refused, so a page
+//! can't reach the gateway through a DNS name rebound to loopback.
+//!
+//! Content is fetched through the instance into its `ContentCache`, which has a size cap,
+//! and streamed from the reassembled file. A single `Range` is honoured. The content type
+//! comes from the name the content was published under: its catalog entry, or the file's
+//! path inside a directory. Every response carries `nosniff`, and HTML, SVG and XML are
+//! served under a `sandbox` CSP, so user content never runs as the gateway's origin.
+//! Directory manifests are listed as HTML, or as JSON with `Accept: application/json`, and
+//! `GET /content/<dir-cid>/<path>` serves one file of the directory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::content_ctl::ContentCache;
+use crate::directory::{DirectoryManifest, MAX_MANIFEST_BYTES};
+use crate::http::{self, Request, Response};
+
+/// How long a catalog read is reused.
+const CATALOG_TTL: Duration = Duration::from_secs(5);
+
+/// Manifest lookups remembered before the memo starts over.
+const MAX_MEMO_ENTRIES: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayOptions {
+    pub port: u16,
+    /// Address to listen on; loopback when absent
+    #[serde(default)]
+    pub bind: Option<IpAddr>,
+    /// Also fetch content this instance doesn't hold
+    #[serde(default)]
+    pub fetch_remote: bool,
+}
+
+/// Content ID → published name, from the instance's catalog.
+type Catalog = Arc<HashMap<String, Option<String>>>;
+
+struct Gateway {
+    cache: ContentCache,
+    fetch_remote: bool,
+    catalog: Mutex<Option<(Instant, Catalog)>>,
+    /// Content IDs already checked for being a directory manifest
+    manifests: Mutex<HashMap<String, Option<Arc<DirectoryManifest>>>>,
+}
+
+/// Serve the gateway until aborted.
+pub async fn serve(cache: ContentCache, options: GatewayOptions) -> Result<(), String> {
+    let addr = SocketAddr::new(options.bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)), options.port);
+    let gateway = Arc::new(Gateway {
+        cache,
+        fetch_remote: options.fetch_remote,
+        catalog: Mutex::new(None),
+        manifests: Mutex::new(HashMap::new()),
+    });
+    let handler: http::Handler = Arc::new(move |req| {
+        let gateway = Arc::clone(&gateway);
+        Box::pin(async move {
+            let response = if host_allowed(req.headers.get("host").map(String::as_str), addr) {
+                gateway.handle(&req).await
+            } else {
+                Response::text(403, "Unexpected Host header")
+            };
+            response.header("X-Content-Type-Options", "nosniff")
+        })
+    });
+    http::serve(addr, handler).await
+}
+
+impl Gateway {
+    async fn handle(&self, req: &Request) -> Response {
+        let Some(rest) = req.path.strip_prefix("/content/") else {
+            return Response::text(404, "Not found");
+        };
+        let (cid, sub) = match rest.split_once('/') {
+            Some((cid, sub)) => (cid, Some(sub)),
+            None => (rest, None),
+        };
+        let catalog = self.catalog().await;
+        if !self.fetch_remote && !catalog.contains_key(cid) {
+            return Response::text(404, format!("{} is not held by this instance", cid));
+        }
+        let path = match self.cache.get(cid).await {
+            Ok(path) => path,
+            Err(e) if e.starts_with("Invalid content ID") => return Response::text(400, e),
+            Err(e) => return Response::text(502, format!("Fetch of {} failed: {}", cid, e)),
+        };
+        match (self.manifest(cid, &path).await, sub) {
+            (Some(manifest), sub) => serve_directory(&self.cache, req, cid, &manifest, sub.unwrap_or("")).await,
+            (None, Some(_)) => Response::text(404, format!("{} is not a directory", cid)),
+            (None, None) => {
+                let name = catalog.get(cid).cloned().flatten();
+                serve_file(req, path, cid, content_type(name.as_deref().unwrap_or("")))
+            }
+        }
+    }
+
+    /// The instance's catalog, re-read once it is older than `CATALOG_TTL`.
+    async fn catalog(&self) -> Catalog {
+        if let Some((read_at, catalog)) = self.catalog.lock().unwrap().as_ref() {
+            if read_at.elapsed() < CATALOG_TTL {
+                return Arc::clone(catalog);
+            }
+        }
+        let catalog: Catalog = match self.cache.content().list().await {
+            Ok(entries) => Arc::new(entries.into_iter().map(|e| (e.content_id, e.name)).collect()),
+            Err(_) => Arc::default(),
+        };
+        *self.catalog.lock().unwrap() = Some((Instant::now(), Arc::clone(&catalog)));
+        catalog
+    }
+
+    /// The directory manifest `cid` holds, if it is one. Content IDs never change what they
+    /// point to, so the answer is remembered.
+    async fn manifest(&self, cid: &str, path: &Path) -> Option<Arc<DirectoryManifest>> {
+        if let Some(known) = self.manifests.lock().unwrap().get(cid) {
+            return known.clone();
+        }
+        let path = path.to_path_buf();
+        let manifest = tokio::task::spawn_blocking(move || directory_manifest(&path))
+            .await
+            .ok()
+            .flatten()
+            .map(Arc::new);
+        let mut manifests = self.manifests.lock().unwrap();
+        if manifests.len() >= MAX_MEMO_ENTRIES {
+            manifests.clear();
+        }
+        manifests.insert(cid.to_string(), manifest.clone());
+        manifest
+    }
+}
+
+/// Whether `host` names the gateway directly: the bound address or a loopback name, with
+/// the gateway's port if a port is given.
+fn host_allowed(host: Option<&str>, bound: SocketAddr) -> bool {
+    let Some(host) = host else {
+        return false;
+    };
+    let (name, port) = match host.rsplit_once(':') {
+        // A bare IPv6 literal has colons but no port
+        Some((name, port)) if !name.contains(':') || name.ends_with(']') => (name, Some(port)),
+        _ => (host, None),
+    };
+    if port.is_some_and(|p| p.parse::<u16>() != Ok(bound.port())) {
+        return false;
+    }
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    if name.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    match name.parse::<IpAddr>() {
+        Ok(ip) => ip == bound.ip() || ip == IpAddr::V4(Ipv4Addr::LOCALHOST) || ip == IpAddr::V6(Ipv6Addr::LOCALHOST),
+        Err(_) => false,
+    }
+}
+
+/// The content at `path` parsed as a directory manifest, if it is one.
+fn directory_manifest(path: &Path) -> Option<DirectoryManifest> {
+    let len = std::fs::metadata(path).ok()?.len();
+    if len > MAX_MANIFEST_BYTES {
+        return None;
+    }
+    let raw = std::fs::read(path).ok()?;
+    if raw.first() != Some(&b'{') {
+        return None;
+    }
+    DirectoryManifest::parse(&raw).ok()
+}
+
+async fn serve_directory(
+    cache: &ContentCache,
+    req: &Request,
+    cid: &str,
+    manifest: &DirectoryManifest,
+    sub: &str,
+) -> Response {
+    let sub = sub.trim_end_matches('/');
+    if let Some(file) = manifest.files.iter().find(|f| f.path == sub) {
+        return match cache.get(&file.cid).await {
+            Ok(path) => serve_file(req, path, &file.cid, content_type(&file.path)),
+            Err(e) => Response::text(502, format!("Fetch of {} failed: {}", file.path, e)),
+        };
+    }
+
+    // Immediate children of `sub`: files, and directories with the total size below them
+    let prefix = if sub.is_empty() { String::new() } else { format!("{}/", sub) };
+    let mut dirs: BTreeMap<&str, u64> = BTreeMap::new();
+    let mut files = Vec::new();
+    for file in &manifest.files {
+        let Some(rest) = file.path.strip_prefix(&prefix) else { continue };
+        match rest.split_once('/') {
+            Some((dir, _)) => *dirs.entry(dir).or_default() += file.size,
+            None => files.push((rest, file)),
+        }
+    }
+    if !sub.is_empty() && dirs.is_empty() && files.is_empty() {
+        return Response::text(404, format!("No '{}' in directory {}", sub, cid));
+    }
+
+    let accept = req.headers.get("accept").map(String::as_str).unwrap_or("");
+    if accept.contains("application/json") {
+        let mut entries: Vec<_> = dirs
+            .iter()
+            .map(|(name, size)| json!({ "name": name, "kind": "directory", "size": size }))
+            .collect();
+        entries.extend(
+            files
+                .iter()
+                .map(|(name, f)| json!({ "name": name, "kind": "file", "size": f.size, "cid": f.cid })),
+        );
+        let body = json!({ "cid": cid, "name": manifest.name, "path": sub, "entries": entries });
+        return Response::new(200, "application/json", body.to_string().into_bytes());
+    }
+
+    let title = format!("{}/{}", manifest.name, sub);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n<h1>{0}</h1>\n<ul>\n",
+        escape_html(&title)
+    );
+    if !sub.is_empty() {
+        let parent = sub.rsplit_once('/').map_or("", |(parent, _)| parent);
+        html.push_str(&format!("<li><a href=\"{}\">../</a></li>\n", link(cid, parent)));
+    }
+    for (name, size) in &dirs {
+        html.push_str(&format!(
+            "<li><a href=\"{}/\">{}/</a> ({} bytes)</li>\n",
+            link(cid, &format!("{}{}", prefix, name)),
+            escape_html(name),
+            size
+        ));
+    }
+    for (name, file) in &files {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> ({} bytes)</li>\n",
+            link(cid, &file.path),
+            escape_html(name),
+            file.size
+        ));
+    }
+    html.push_str("</ul>\n</body></html>\n");
+    Response::new(200, "text/html; charset=utf-8", html.into_bytes()).header("Content-Security-Policy", "default-src 'none'")
+}
+
+/// Stream a cached file, or the part of it a `Range` header asks for.
+fn serve_file(req: &Request, path: PathBuf, cid: &str, content_type: &str) -> Response {
+    let len = match std::fs::metadata(&path) {
+        Ok(meta) => meta.len(),
+        Err(e) => return Response::text(500, format!("Failed to read {}: {}", cid, e)),
+    };
+    let response = match parse_range(req.headers.get("range").map(String::as_str), len) {
+        None => Response::file(200, content_type, path, 0, len),
+        Some(Ok((start, end))) => Response::file(206, content_type, path, start, end - start + 1)
+            .header("Content-Range", format!("bytes {}-{}/{}", start, end, len)),
+        Some(Err(())) => {
+            return Response::text(416, "Range not satisfiable").header("Content-Range", format!("bytes */{}", len))
+        }
+    };
+    let response = if is_active(content_type) {
+        // A document of its own origin, without scripts
+        response.header("Content-Security-Policy", "sandbox")
+    } else {
+        response
+    };
+    // Content IDs never change what they point to
+    response
+        .header("Accept-Ranges", "bytes")
+        .header("ETag", format!("\"{}\"", cid))
+        .header("Cache-Control", "public, max-age=31536000, immutable")
+}
+
+/// Inclusive byte range of a single-range `Range` header. `None` means serve everything
+/// (no header, another unit, several ranges or a malformed value); `Err` means the range
+/// starts past the end.
+fn parse_range(header: Option<&str>, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header?.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let last = len.saturating_sub(1);
+    let range = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        (Some(s), Some(e)) if s <= e => (s, e.min(last)),
+        (Some(s), None) if end.is_empty() => (s, last),
+        // Suffix range: the last `n` bytes
+        (None, Some(n)) if start.is_empty() && n > 0 => (len.saturating_sub(n), last),
+        _ => return None,
+    };
+    if range.0 >= len {
+        return Some(Err(()));
+    }
+    Some(Ok(range))
+}
+
+fn content_type(name: &str) -> &'static str {
+    let ext = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "wasm" => "application/wasm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Types a browser renders as a document that could run script.
+fn is_active(content_type: &str) -> bool {
+    ["text/html", "image/svg+xml", "application/xml"].iter().any(|t| content_type.starts_with(t))
+}
+
+/// Gateway URL of a path inside a directory.
+fn link(cid: &str, path: &str) -> String {
+    let mut out = format!("/content/{}/", cid);
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        assert_eq!(parse_range(None, 100), None);
+        assert_eq!(parse_range(Some("bytes=0-0"), 100), Some(Ok((0, 0))));
+        assert_eq!(parse_range(Some("bytes=10-"), 100), Some(Ok((10, 99))));
+        assert_eq!(parse_range(Some("bytes=90-200"), 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), Some(Ok((90, 99))));
+        assert_eq!(parse_range(Some("bytes=-500"), 100), Some(Ok((0, 99))));
+        assert_eq!(parse_range(Some("bytes=100-"), 100), Some(Err(())));
+        assert_eq!(parse_range(Some("bytes=0-"), 0), Some(Err(())));
+    }
+
+    #[test]
+    fn serves_whole_content_for_ranges_it_does_not_handle() {
+        for header in ["items=0-1", "bytes=0-1,5-6", "bytes=5-1", "bytes=-0", "bytes=a-b", "bytes="] {
+            assert_eq!(parse_range(Some(header), 100), None, "{}", header);
+        }
+    }
+
+    #[test]
+    fn only_direct_hosts_are_served() {
+        let loopback = SocketAddr::from(([127, 0, 0, 1], 8080));
+        for host in ["127.0.0.1:8080", "localhost:8080", "LOCALHOST", "[::1]:8080", "127.0.0.1"] {
+            assert!(host_allowed(Some(host), loopback), "{}", host);
+        }
+        for host in ["evil.example:8080", "localhost.evil.example:8080", "127.0.0.1:9090", "", "[::2]:8080"] {
+            assert!(!host_allowed(Some(host), loopback), "{}", host);
+        }
+        assert!(!host_allowed(None, loopback));
+
+        let lan = SocketAddr::from(([192, 168, 1, 5], 8080));
+        assert!(host_allowed(Some("192.168.1.5:8080"), lan));
+        assert!(!host_allowed(Some("192.168.1.6:8080"), lan));
+    }
+
+    #[test]
+    fn content_type_follows_the_extension() {
+        assert_eq!(content_type("site/index.HTML"), "text/html; charset=utf-8");
+        assert_eq!(content_type("photo.jpeg"), "image/jpeg");
+        assert_eq!(content_type("archive.tar.gz"), "application/gzip");
+        assert_eq!(content_type("README"), "application/octet-stream");
+        assert_eq!(content_type(""), "application/octet-stream");
+        assert!(is_active(content_type("logo.svg")));
+        assert!(!is_active(content_type("app.js")));
+    }
+}
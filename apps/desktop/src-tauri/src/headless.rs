@@ -0,0 +1,306 @@
+//! Headless subcommands: `craftstudio --headless <subcommand> [--flag value ...]`.
+//!
+//! Without a subcommand `run_headless` runs the primary daemon as before. Subcommands drive
+//! a `DaemonManager` the same way the GUI does and block until Ctrl+C.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::backup;
+use crate::cluster::{ClusterInfo, ClusterProfile, ClusterSpec, Topology};
+use crate::daemon_manager::{DaemonConfig, DaemonInstance, DaemonManager};
+use crate::faults::{FaultKind, FaultSpec};
+use crate::gateway::GatewayOptions;
+use crate::resources::ResourceLimits;
+
+/// Arguments after `--headless`.
+pub struct HeadlessArgs {
+    pub subcommand: Option<String>,
+    flags: HashMap<String, String>,
+}
+
+impl HeadlessArgs {
+    pub fn from_env() -> Self {
+        let mut args = std::env::args().skip_while(|a| a != "--headless").skip(1).peekable();
+        let subcommand = args.next_if(|a| !a.starts_with("--"));
+        let mut flags = HashMap::new();
+        while let Some(arg) = args.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let value = args.next_if(|v| !v.starts_with("--")).unwrap_or_default();
+                flags.insert(name.to_string(), value);
+            }
+        }
+        Self { subcommand, flags }
+    }
+
+    pub(crate) fn flag(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).map(String::as_str)
+    }
+
+    fn parsed<T: std::str::FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.flag(name) {
+            Some(v) => v.parse().map_err(|_| format!("Invalid value for --{}: {}", name, v)),
+            None => Ok(default),
+        }
+    }
+
+    fn optional<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        self.flag(name)
+            .map(|v| v.parse().map_err(|_| format!("Invalid value for --{}: {}", name, v)))
+            .transpose()
+    }
+
+    /// Comma-separated flag value.
+    pub(crate) fn list(&self, name: &str) -> Option<Vec<String>> {
+        self.flag(name).map(|v| {
+            v.split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+    }
+}
+
+/// Run a subcommand to completion. Returns an error message for the caller to print.
+pub async fn run_subcommand(args: &HeadlessArgs) -> Result<(), String> {
+    let manager = DaemonManager::new(
+        Arc::new(Mutex::new(HashMap::new())),
+        tokio::runtime::Handle::current(),
+    );
+    match args.subcommand.as_deref() {
+        Some("node") => node(&manager, args).await,
+        Some("mount") => mount(&manager, args).await,
+        Some("backup") => backup(args),
+        Some("restore") => restore(&manager, args).await,
+        Some("cluster") => cluster(&manager, args).await,
+        Some("fault") => fault(&manager, args).await,
+        Some(other) => Err(format!("Unknown headless subcommand '{}'", other)),
+        None => Ok(()),
+    }
+}
+
+/// `node [--data-dir DIR] [--listen ADDR,...] [--ws-port N] [--capabilities client,storage] [--metrics-port N]
+///       [--worker-threads N] [--max-concurrent-ipc N] [--disk-io-bytes-per-sec N]
+///       [--gateway-port N] [--gateway-bind ADDR] [--gateway-fetch-remote] [--max-storage-bytes N]
+///       [--strict-storage]`
+///
+/// Runs one instance through the manager, so options only the manager offers (metrics
+/// endpoint, several listen addresses, resource limits) are available without the GUI.
+async fn node(manager: &DaemonManager, args: &HeadlessArgs) -> Result<(), String> {
+    let instance = start_node(manager, args)?;
+
+    // Report the bound addresses once the swarm has announced them
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        let bound = manager
+            .list()
+            .into_iter()
+            .find(|d| d.pid == instance.pid)
+            .map(|d| d.bound_addrs)
+            .unwrap_or_default();
+        if !bound.is_empty() {
+            for addr in bound {
+                println!("  Listening on {}", addr);
+            }
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    println!();
+    println!("  Ctrl+C to stop");
+
+    let _ = tokio::signal::ctrl_c().await;
+    manager.stop_and_wait(instance.pid).await
+}
+
+/// `mount --mountpoint DIR [node options]`
+///
+/// Runs an instance like `node` and mounts its catalog and VFS read-only at `--mountpoint`
+/// (Linux). Stopping unmounts.
+async fn mount(manager: &DaemonManager, args: &HeadlessArgs) -> Result<(), String> {
+    let mountpoint = args.flag("mountpoint").ok_or("--mountpoint is required")?;
+    let instance = start_node(manager, args)?;
+
+    // The instance takes a moment before it answers on its handler
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let info = loop {
+        match manager.mount_instance(instance.pid, mountpoint).await {
+            Ok(info) => break info,
+            Err(e) if Instant::now() < deadline && e.contains("still starting") => {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            Err(e) => {
+                manager.stop_and_wait(instance.pid).await?;
+                return Err(e);
+            }
+        }
+    };
+    println!("  Mounted at {}", info.mountpoint);
+    println!();
+    println!("  Ctrl+C to unmount and stop");
+
+    let _ = tokio::signal::ctrl_c().await;
+    manager.stop_and_wait(instance.pid).await
+}
+
+/// Start the instance described by the `node` flags and print it.
+fn start_node(manager: &DaemonManager, args: &HeadlessArgs) -> Result<DaemonInstance, String> {
+    let metrics_port: Option<u16> = args.optional("metrics-port")?;
+    let limits = ResourceLimits {
+        worker_threads: args.optional("worker-threads")?,
+        max_concurrent_ipc: args.optional("max-concurrent-ipc")?,
+        disk_io_bytes_per_sec: args.optional("disk-io-bytes-per-sec")?,
+    };
+    let gateway = match args.optional::<u16>("gateway-port")? {
+        Some(port) => Some(GatewayOptions {
+            port,
+            bind: args.optional("gateway-bind")?,
+            fetch_remote: args.flag("gateway-fetch-remote").is_some(),
+        }),
+        None => None,
+    };
+    let gateway_addr = gateway
+        .as_ref()
+        .map(|g| SocketAddr::new(g.bind.unwrap_or(Ipv4Addr::LOCALHOST.into()), g.port));
+    let instance = manager.start(DaemonConfig {
+        data_dir: args.flag("data-dir").map(str::to_string),
+        ws_port: args.optional("ws-port")?,
+        listen_addr: args.list("listen"),
+        capabilities: args.list("capabilities"),
+        metrics_port,
+        limits: Some(limits),
+        gateway,
+        max_storage_bytes: args.optional("max-storage-bytes")?,
+        strict_storage: args.flag("strict-storage").is_some(),
+        ..Default::default()
+    })?;
+
+    println!("  Instance #{}  {}  ws:{}  {}", instance.pid, instance.peer_id, instance.ws_port, instance.data_dir);
+    if let Some(port) = metrics_port {
+        println!("  Metrics on http://127.0.0.1:{}/metrics", port);
+    }
+    if let Some(addr) = gateway_addr {
+        println!("  Content gateway on http://{}/content/<cid>", addr);
+    }
+    Ok(instance)
+}
+
+/// `backup --data-dir DIR --output FILE [--include-chunks]`
+///
+/// Archives a data dir whose instance isn't running; running instances are backed up
+/// through the GUI, which pauses them first.
+fn backup(args: &HeadlessArgs) -> Result<(), String> {
+    let data_dir = args.flag("data-dir").ok_or("--data-dir is required")?;
+    let output = args.flag("output").ok_or("--output is required")?;
+    let info = backup::create_backup(Path::new(data_dir), Path::new(output), args.flag("include-chunks").is_some())?;
+    println!("  {} files, {} bytes -> {}", info.files, info.bytes, info.archive);
+    println!("  sha256 {}", info.sha256);
+    Ok(())
+}
+
+/// `restore --archive FILE [--data-dir DIR] [--ws-port N]`
+///
+/// Restores a backup (into the data dir it was taken from unless `--data-dir` is given)
+/// and runs the instance until Ctrl+C.
+async fn restore(manager: &DaemonManager, args: &HeadlessArgs) -> Result<(), String> {
+    let archive = args.flag("archive").ok_or("--archive is required")?;
+    let config = DaemonConfig {
+        ws_port: args.optional("ws-port")?,
+        ..Default::default()
+    };
+    let restored = manager
+        .restore_instance(archive, args.flag("data-dir").map(str::to_string), config)
+        .await?;
+    let instance = restored.instance;
+    println!("  Restored {} files, {} bytes", restored.files, restored.bytes);
+    println!("  Instance #{}  {}  ws:{}  {}", instance.pid, instance.peer_id, instance.ws_port, instance.data_dir);
+    println!();
+    println!("  Ctrl+C to stop");
+
+    let _ = tokio::signal::ctrl_c().await;
+    manager.stop_and_wait(instance.pid).await
+}
+
+/// `cluster --nodes 3 --profile storage --topology mesh`
+async fn cluster(manager: &DaemonManager, args: &HeadlessArgs) -> Result<(), String> {
+    let info = launch_cluster(manager, args).await?;
+    println!("  Ctrl+C to stop the cluster and remove its data");
+
+    let _ = tokio::signal::ctrl_c().await;
+    manager.stop_cluster(info.id).await
+}
+
+/// `fault --kind pause --node 1 --duration 30 [--fraction 0.2] [--bytes-per-sec 65536] [--seed 7]`
+///
+/// Starts a cluster like `cluster` does and injects one fault into node `--node`.
+/// `disconnect` cuts the node off from every other node in the cluster.
+async fn fault(manager: &DaemonManager, args: &HeadlessArgs) -> Result<(), String> {
+    let node: usize = args.parsed("node", 0)?;
+    let fraction: f64 = args.parsed("fraction", 0.1)?;
+    let kind_name = args.flag("kind").ok_or("--kind is required (disconnect, throttle, pause, corrupt, delete)")?;
+    let info = launch_cluster(manager, args).await?;
+    let target = info
+        .nodes
+        .get(node)
+        .ok_or_else(|| format!("Cluster has no node {}", node))?;
+
+    let kind = match kind_name {
+        "disconnect" => FaultKind::Disconnect {
+            peers: info.nodes.iter().filter(|n| n.pid != target.pid).map(|n| n.peer_id.clone()).collect(),
+        },
+        "throttle" => FaultKind::Throttle {
+            bytes_per_sec: args.parsed("bytes-per-sec", 64 * 1024)?,
+        },
+        "pause" => FaultKind::Pause,
+        "corrupt" => FaultKind::CorruptPieces { fraction },
+        "delete" => FaultKind::DeletePieces { fraction },
+        other => {
+            manager.stop_cluster(info.id).await?;
+            return Err(format!("Unknown fault kind '{}' (disconnect, throttle, pause, corrupt, delete)", other));
+        }
+    };
+    let spec = FaultSpec {
+        kind,
+        duration_secs: args.parsed("duration", 30)?,
+        seed: args.optional("seed")?,
+    };
+    match manager.inject_fault(target.pid, spec).await {
+        Ok(fault) => println!(
+            "  Fault #{} on node {}: {:?}, {} affected, reverts in {}s",
+            fault.id,
+            node,
+            fault.kind,
+            fault.affected,
+            fault.revert_at - fault.started_at
+        ),
+        Err(e) => {
+            manager.stop_cluster(info.id).await?;
+            return Err(e);
+        }
+    }
+    println!("  Ctrl+C to stop the cluster and remove its data");
+
+    let _ = tokio::signal::ctrl_c().await;
+    manager.stop_cluster(info.id).await
+}
+
+/// Start a cluster from `--nodes`, `--profile`, `--topology` and `--timeout` and print it.
+async fn launch_cluster(manager: &DaemonManager, args: &HeadlessArgs) -> Result<ClusterInfo, String> {
+    let spec = ClusterSpec {
+        nodes: args.parsed("nodes", 3)?,
+        profile: ClusterProfile::parse(args.flag("profile").unwrap_or("storage"))?,
+        topology: Topology::parse(args.flag("topology").unwrap_or("mesh"))?,
+        ready_timeout_secs: Some(args.parsed("timeout", 60)?),
+    };
+    let info = manager.start_cluster(spec).await?;
+
+    println!("  Cluster {} ready ({:?}), data in {}", info.id, info.topology, info.data_root);
+    for node in &info.nodes {
+        println!("    #{}  ws:{}  {}  {}", node.pid, node.ws_port, node.peer_id, node.data_dir);
+    }
+    println!();
+    Ok(info)
+}
@@ -0,0 +1,217 @@
+//! Minimal HTTP/1.1 server for local endpoints (metrics, content gateway).
+//!
+//! Only what loopback tooling needs: one request per connection, `GET`/`HEAD`, headers
+//! parsed into a map, and a response that is either buffered or streamed from a file.
+//! Anything serious belongs behind the IPC API instead.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Largest request head accepted.
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+
+/// How long a client has to send its request head.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Request {
+    pub method: String,
+    /// Path without the query string, percent-decoded
+    pub path: String,
+    /// Header names are lowercased
+    pub headers: HashMap<String, String>,
+}
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// `len` bytes of a file starting at `offset`, streamed as they are read
+    File { path: PathBuf, offset: u64, len: u64 },
+}
+
+impl Body {
+    fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } => *len,
+        }
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: Body::Bytes(body),
+        }
+    }
+
+    pub fn file(status: u16, content_type: &str, path: PathBuf, offset: u64, len: u64) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: Body::File { path, offset, len },
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body.into().into_bytes())
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+}
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Response> + Send>>;
+pub type Handler = Arc<dyn Fn(Request) -> HandlerFuture + Send + Sync>;
+
+/// Accept connections on `addr` until the task is aborted.
+pub async fn serve(addr: SocketAddr, handler: Handler) -> Result<(), String> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+    info!("HTTP endpoint listening on http://{}", addr);
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("HTTP accept failed on {}: {}", addr, e);
+                continue;
+            }
+        };
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            // The head is read through `take`, so a line without an end can't grow past the limit
+            let head = tokio::time::timeout(HEAD_TIMEOUT, read_request(BufReader::new(read.take(MAX_HEAD_BYTES))));
+            let request = match head.await {
+                Ok(Some(request)) => request,
+                Ok(None) => {
+                    let _ = write_response(&mut write, false, Response::text(400, "Bad request")).await;
+                    return;
+                }
+                Err(_) => {
+                    let _ = write_response(&mut write, false, Response::text(408, "Request timeout")).await;
+                    return;
+                }
+            };
+            let head_only = request.method == "HEAD";
+            let response = match request.method.as_str() {
+                "GET" | "HEAD" => handler(request).await,
+                _ => Response::text(405, "Method not allowed").header("Allow", "GET, HEAD"),
+            };
+            let _ = write_response(&mut write, head_only, response).await;
+        });
+    }
+}
+
+async fn read_request<R: tokio::io::AsyncRead + Unpin>(mut reader: BufReader<R>) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    if !line.ends_with('\n') {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    let path = percent_decode(path)?;
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.ok()?;
+        // Cut short by the end of the stream or the head limit
+        if !header.ends_with('\n') {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    Some(Request {
+        method,
+        path,
+        headers,
+    })
+}
+
+async fn write_response<W: tokio::io::AsyncWrite + Unpin>(
+    write: &mut W,
+    head_only: bool,
+    response: Response,
+) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason(response.status));
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !response.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("content-length")) {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    write.write_all(head.as_bytes()).await?;
+    if !head_only {
+        match response.body {
+            Body::Bytes(bytes) => write.write_all(&bytes).await?,
+            Body::File { path, offset, len } => {
+                let mut file = tokio::fs::File::open(&path).await?;
+                file.seek(SeekFrom::Start(offset)).await?;
+                tokio::io::copy(&mut file.take(len), write).await?;
+            }
+        }
+    }
+    write.shutdown().await
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        _ => "",
+    }
+}
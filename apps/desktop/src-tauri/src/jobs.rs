@@ -0,0 +1,672 @@
+//! Background publish/fetch/repair/mirror jobs.
+//!
+//! Each instance has a `JobQueue` persisted in `{data_dir}/jobs.json`. `run` works through
+//! it next to the instance, a few jobs at a time. Every state or progress change is sent
+//! on the manager's job channel, which the GUI forwards as `job-progress` Tauri events.
+//!
+//! The daemon calls behind a job are single requests, so pausing a job aborts its
+//! in-flight call and resuming issues it again. CraftOBJ keeps the segments it already
+//! stored or fetched, so the repeated call skips them. Jobs that were running when the
+//! app quit are queued again on the next start.
+//!
+//! Progress comes from two places: the directory publisher reports per file, and
+//! notifications on the instance bus that name a job's content (`manifest_retrieved`,
+//! `segment_fetched`, `repair_completed`) advance segment counts. A single-file publish
+//! doesn't know its CID up front, so it is matched by the `path` a notification carries,
+//! or, failing that, `content_published` binds it when it is the only publish waiting for
+//! one. From then on `distribution_progress` for that CID advances it piece by piece.
+//! Encoding before `content_published` reports nothing, since the daemon doesn't either.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::api_keys::now_secs;
+use crate::content_ctl::ContentControl;
+use crate::daemon_manager::DaemonManager;
+use crate::directory::{self, PublishDirRequest, PublishProgress};
+use crate::mirror::{self, MirrorStore};
+use crate::resources::IoLimiter;
+use crate::state_file;
+use crate::vfs::Vfs;
+
+/// Jobs of one instance that run at the same time.
+const MAX_RUNNING: usize = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobKind {
+    Publish { path: String },
+    PublishDirectory(PublishDirRequest),
+    /// Writes to `output`, or `{data_dir}/downloads/<cid>` when absent
+    Fetch {
+        cid: String,
+        #[serde(default)]
+        output: Option<String>,
+    },
+    /// Regenerate pieces for content that lost redundancy
+    Repair { cid: String },
+    /// Bring a mirror to version `cid` of its directory
+    Mirror { mirror: u64, cid: String },
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Failed,
+    Done,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    /// Instance ID in this session; not meaningful across restarts
+    #[serde(default)]
+    pub pid: u32,
+    pub kind: JobKind,
+    pub state: JobState,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub attempts: u32,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub segments_done: u64,
+    pub segments_total: u64,
+    /// Content the job is about: given for fetch, repair and mirror jobs, learned from the
+    /// daemon's notifications for a publish
+    #[serde(default)]
+    pub content_id: Option<String>,
+    pub error: Option<String>,
+    /// Daemon response of the last successful run
+    pub result: Option<Value>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct JobBook {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+pub struct JobQueue {
+    pid: u32,
+    path: PathBuf,
+    book: Mutex<JobBook>,
+    running: Mutex<HashMap<u64, JoinHandle<()>>>,
+    wake: Notify,
+    updates: broadcast::Sender<Job>,
+}
+
+impl JobQueue {
+    pub fn open(data_dir: &Path, pid: u32, updates: broadcast::Sender<Job>) -> Self {
+        let path = data_dir.join("jobs.json");
+        let mut book: JobBook = state_file::load(&path);
+        for job in &mut book.jobs {
+            job.pid = pid;
+            if job.state == JobState::Running {
+                job.state = JobState::Queued;
+            }
+        }
+        Self {
+            pid,
+            path,
+            book: Mutex::new(book),
+            running: Mutex::new(HashMap::new()),
+            wake: Notify::new(),
+            updates,
+        }
+    }
+
+    pub fn list(&self) -> Vec<Job> {
+        self.book.lock().unwrap().jobs.clone()
+    }
+
+    pub fn enqueue(&self, kind: JobKind) -> Result<Job, String> {
+        let bytes_total = match &kind {
+            JobKind::Publish { path } => std::fs::metadata(path)
+                .map_err(|e| format!("Cannot publish {}: {}", path, e))?
+                .len(),
+            _ => 0,
+        };
+        let content_id = match &kind {
+            JobKind::Fetch { cid, .. } | JobKind::Repair { cid } | JobKind::Mirror { cid, .. } => Some(cid.clone()),
+            JobKind::Publish { .. } | JobKind::PublishDirectory(_) => None,
+        };
+        let now = now_secs();
+        let job = {
+            let mut book = self.book.lock().unwrap();
+            book.next_id += 1;
+            let job = Job {
+                id: book.next_id,
+                pid: self.pid,
+                kind,
+                state: JobState::Queued,
+                created_at: now,
+                updated_at: now,
+                attempts: 0,
+                bytes_done: 0,
+                bytes_total,
+                segments_done: 0,
+                segments_total: 0,
+                content_id,
+                error: None,
+                result: None,
+            };
+            book.jobs.push(job.clone());
+            job
+        };
+        self.save()?;
+        let _ = self.updates.send(job.clone());
+        self.wake.notify_one();
+        Ok(job)
+    }
+
+    pub fn pause(&self, id: u64) -> Result<Job, String> {
+        self.transition(id, &[JobState::Queued, JobState::Running], JobState::Paused)
+    }
+
+    pub fn resume(&self, id: u64) -> Result<Job, String> {
+        self.transition(id, &[JobState::Paused], JobState::Queued)
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<Job, String> {
+        self.transition(id, &[JobState::Queued, JobState::Running, JobState::Paused], JobState::Cancelled)
+    }
+
+    pub fn retry(&self, id: u64) -> Result<Job, String> {
+        self.transition(id, &[JobState::Failed, JobState::Cancelled], JobState::Queued)
+    }
+
+    /// Abort the running jobs for an instance stop; they are queued again on the next start.
+    /// Returns their tasks, to wait until they have ended.
+    pub fn stop_all(&self) -> Vec<JoinHandle<()>> {
+        let tasks: Vec<JoinHandle<()>> = self.running.lock().unwrap().drain().map(|(_, t)| t).collect();
+        for task in &tasks {
+            task.abort();
+        }
+        tasks
+    }
+
+    /// Drop finished, failed and cancelled jobs from the list.
+    pub fn clear_finished(&self) -> Result<usize, String> {
+        let removed = {
+            let mut book = self.book.lock().unwrap();
+            let before = book.jobs.len();
+            book.jobs
+                .retain(|j| matches!(j.state, JobState::Queued | JobState::Running | JobState::Paused));
+            before - book.jobs.len()
+        };
+        self.save()?;
+        Ok(removed)
+    }
+
+    fn transition(&self, id: u64, from: &[JobState], to: JobState) -> Result<Job, String> {
+        let job = self.update(id, |job| {
+            if !from.contains(&job.state) {
+                return Err(format!("Job {} is {:?}; cannot move it to {:?}", id, job.state, to));
+            }
+            job.state = to;
+            if to == JobState::Queued {
+                job.error = None;
+            }
+            Ok(())
+        })?;
+        if to != JobState::Queued {
+            if let Some(task) = self.running.lock().unwrap().remove(&id) {
+                task.abort();
+            }
+        }
+        self.save()?;
+        self.wake.notify_one();
+        Ok(job)
+    }
+
+    /// Apply `f` to a job, stamp it and announce the change. Not persisted; callers save
+    /// on state changes.
+    fn update(&self, id: u64, f: impl FnOnce(&mut Job) -> Result<(), String>) -> Result<Job, String> {
+        let job = {
+            let mut book = self.book.lock().unwrap();
+            let job = book
+                .jobs
+                .iter_mut()
+                .find(|j| j.id == id)
+                .ok_or_else(|| format!("No job {}", id))?;
+            f(job)?;
+            job.updated_at = now_secs();
+            job.clone()
+        };
+        let _ = self.updates.send(job.clone());
+        Ok(job)
+    }
+
+    /// Mark the next queued job running, if there is room.
+    fn claim(&self) -> Option<Job> {
+        if self.running.lock().unwrap().len() >= MAX_RUNNING {
+            return None;
+        }
+        let id = self
+            .book
+            .lock()
+            .unwrap()
+            .jobs
+            .iter()
+            .find(|j| j.state == JobState::Queued)?
+            .id;
+        let job = self
+            .update(id, |job| {
+                job.state = JobState::Running;
+                job.attempts += 1;
+                Ok(())
+            })
+            .ok()?;
+        let _ = self.save();
+        Some(job)
+    }
+
+    /// Record how a run ended, unless the job was paused or cancelled meanwhile.
+    fn finish(&self, id: u64, result: Result<Value, String>) {
+        self.running.lock().unwrap().remove(&id);
+        let _ = self.update(id, |job| {
+            if job.state != JobState::Running {
+                return Ok(());
+            }
+            match result {
+                Ok(value) => {
+                    job.state = JobState::Done;
+                    job.bytes_done = job.bytes_total.max(job.bytes_done);
+                    job.segments_done = job.segments_total.max(job.segments_done);
+                    job.result = Some(value);
+                }
+                Err(e) => {
+                    job.state = JobState::Failed;
+                    job.error = Some(e);
+                }
+            }
+            Ok(())
+        });
+        if let Err(e) = self.save() {
+            warn!("Failed to save job queue: {}", e);
+        }
+        self.wake.notify_one();
+    }
+
+    /// Advance progress from a bus notification naming a running job's content or path.
+    fn observe(&self, notification: &str) {
+        let Ok(v) = serde_json::from_str::<Value>(notification) else {
+            return;
+        };
+        let method = v.get("method").and_then(|m| m.as_str()).unwrap_or_default();
+        let params = v.get("params").cloned().unwrap_or(Value::Null);
+        let ids: Vec<u64> = {
+            let book = self.book.lock().unwrap();
+            let running: Vec<&Job> = book.jobs.iter().filter(|j| j.state == JobState::Running).collect();
+            let unbound = running.iter().filter(|j| awaits_cid(j)).count();
+            running
+                .iter()
+                .filter(|j| concerns(j, method, &params, unbound))
+                .map(|j| j.id)
+                .collect()
+        };
+        for id in ids {
+            let _ = self.update(id, |job| {
+                advance(job, method, &params);
+                Ok(())
+            });
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&*self.book.lock().unwrap())
+            .map_err(|e| format!("Failed to serialize job queue: {}", e))?;
+        state_file::write(&self.path, json.as_bytes())
+    }
+}
+
+/// A publish that hasn't learned its CID yet.
+fn awaits_cid(job: &Job) -> bool {
+    matches!(job.kind, JobKind::Publish { .. }) && job.content_id.is_none()
+}
+
+/// Whether `method` with `params` is about `job`. `unbound` is how many running publishes
+/// don't know their CID; `content_published` without a path only binds when it's one.
+fn concerns(job: &Job, method: &str, params: &Value, unbound: usize) -> bool {
+    let cid = params.get("content_id").and_then(|c| c.as_str());
+    match &job.kind {
+        JobKind::Publish { path } => {
+            if !matches!(method, "content_published" | "distribution_progress") {
+                return false;
+            }
+            match (params.get("path").and_then(|p| p.as_str()), &job.content_id) {
+                (Some(p), _) => p == path,
+                (None, Some(bound)) => cid == Some(bound.as_str()),
+                (None, None) => method == "content_published" && unbound == 1,
+            }
+        }
+        JobKind::Fetch { cid: own, .. } | JobKind::Repair { cid: own } => {
+            matches!(method, "manifest_retrieved" | "segment_fetched" | "repair_completed") && cid == Some(own.as_str())
+        }
+        JobKind::PublishDirectory(_) | JobKind::Mirror { .. } => false,
+    }
+}
+
+/// Apply a notification `concerns` matched to `job`'s progress.
+fn advance(job: &mut Job, method: &str, params: &Value) {
+    let field = |k: &str| params.get(k).and_then(|n| n.as_u64());
+    match method {
+        "content_published" => {
+            if job.content_id.is_none() {
+                job.content_id = params.get("content_id").and_then(|c| c.as_str()).map(str::to_string);
+            }
+            job.segments_total = field("segment_count").unwrap_or(job.segments_total);
+            job.bytes_total = field("size").or(field("total_size")).unwrap_or(job.bytes_total);
+        }
+        "distribution_progress" => {
+            // Pieces are spread evenly over segments, so scale both counts by the share pushed
+            let (Some(pushed), Some(total)) = (field("pieces_pushed"), field("total_pieces")) else {
+                return;
+            };
+            if total == 0 {
+                return;
+            }
+            let share = |of: u64| (of as u128 * pushed.min(total) as u128 / total as u128) as u64;
+            job.segments_done = job.segments_done.max(share(job.segments_total));
+            job.bytes_done = job.bytes_done.max(share(job.bytes_total));
+        }
+        "manifest_retrieved" => {
+            job.segments_total = field("segment_count").unwrap_or(job.segments_total);
+            job.bytes_total = field("total_size").unwrap_or(job.bytes_total);
+        }
+        _ => {
+            job.segments_done += 1;
+            job.bytes_done += field("bytes").unwrap_or(0);
+        }
+    }
+}
+
+/// What a job needs from its instance.
+#[derive(Clone)]
+pub struct JobContext {
+    pub content: ContentControl,
+    pub io: Option<Arc<IoLimiter>>,
+    pub vfs: Arc<Vfs>,
+    pub mirrors: Arc<MirrorStore>,
+    pub data_dir: PathBuf,
+}
+
+/// Run queued jobs and track their progress until the bus closes.
+pub async fn run(queue: Arc<JobQueue>, ctx: JobContext, mut events: broadcast::Receiver<String>) {
+    loop {
+        while let Some(job) = queue.claim() {
+            info!("Starting job {} ({:?}), attempt {}", job.id, job.kind, job.attempts);
+            let (queue_for_task, ctx) = (Arc::clone(&queue), ctx.clone());
+            // Hold the table lock until the handle is in, so `finish` can't run first
+            let mut running = queue.running.lock().unwrap();
+            let task = tokio::spawn(async move {
+                let result = execute(&queue_for_task, &ctx, &job).await;
+                queue_for_task.finish(job.id, result);
+            });
+            running.insert(job.id, task);
+        }
+        tokio::select! {
+            _ = queue.wake.notified() => {}
+            event = events.recv() => match event {
+                Ok(notification) => queue.observe(&notification),
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+    queue.stop_all();
+}
+
+async fn execute(queue: &Arc<JobQueue>, ctx: &JobContext, job: &Job) -> Result<Value, String> {
+    match &job.kind {
+        JobKind::Publish { path } => {
+            let published = ctx.content.publish(Path::new(path)).await?;
+            let _ = queue.update(job.id, |j| {
+                j.content_id = Some(published.cid.clone());
+                j.segments_total = published.segments;
+                j.bytes_total = published.size.max(j.bytes_total);
+                Ok(())
+            });
+            Ok(json!({ "cid": published.cid, "size": published.size, "segments": published.segments }))
+        }
+        JobKind::PublishDirectory(req) => {
+            let (queue, id) = (Arc::clone(queue), job.id);
+            let progress = Arc::new(move |p: &PublishProgress| {
+                let _ = queue.update(id, |j| {
+                    j.bytes_done = p.bytes_done;
+                    j.bytes_total = p.bytes_total;
+                    Ok(())
+                });
+            });
+            let vfs = req.vfs_path.as_ref().map(|_| Arc::clone(&ctx.vfs));
+            let staging = ctx.data_dir.join("dir-manifests");
+            let published = directory::publish_directory(
+                ctx.content.clone(),
+                ctx.io.clone(),
+                vfs,
+                &staging,
+                req.clone(),
+                progress,
+            )
+            .await?;
+            serde_json::to_value(published).map_err(|e| e.to_string())
+        }
+        JobKind::Fetch { cid, output } => {
+            let output = match output {
+                Some(path) => PathBuf::from(path),
+                None => ctx.data_dir.join("downloads").join(cid),
+            };
+            if let Some(parent) = output.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            let path = ctx.content.fetch(cid, &output).await?;
+            Ok(json!({ "path": path }))
+        }
+        JobKind::Repair { cid } => ctx.content.extend(cid).await,
+        JobKind::Mirror { mirror, cid } => {
+            let (queue, id) = (Arc::clone(queue), job.id);
+            let progress = move |done: u64, total: u64| {
+                let _ = queue.update(id, |j| {
+                    j.bytes_done = done;
+                    j.bytes_total = total;
+                    Ok(())
+                });
+            };
+            let report =
+                mirror::sync_mirror(&ctx.mirrors, &ctx.content, ctx.io.as_deref(), *mirror, cid, progress).await?;
+            serde_json::to_value(report).map_err(|e| e.to_string())
+        }
+    }
+}
+
+impl DaemonManager {
+    pub fn list_jobs(&self, pid: u32) -> Result<Vec<Job>, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.jobs))?.list())
+    }
+
+    pub fn enqueue_job(&self, pid: u32, kind: JobKind) -> Result<Job, String> {
+        let job = self.with_daemon(pid, |d| Arc::clone(&d.jobs))?.enqueue(kind)?;
+        info!("Instance {} queued job {}", pid, job.id);
+        Ok(job)
+    }
+
+    pub fn pause_job(&self, pid: u32, id: u64) -> Result<Job, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.jobs))?.pause(id)
+    }
+
+    pub fn resume_job(&self, pid: u32, id: u64) -> Result<Job, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.jobs))?.resume(id)
+    }
+
+    pub fn cancel_job(&self, pid: u32, id: u64) -> Result<Job, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.jobs))?.cancel(id)
+    }
+
+    pub fn retry_job(&self, pid: u32, id: u64) -> Result<Job, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.jobs))?.retry(id)
+    }
+
+    pub fn clear_finished_jobs(&self, pid: u32) -> Result<usize, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.jobs))?.clear_finished()
+    }
+
+    /// Every job change on every instance.
+    pub fn job_updates(&self) -> broadcast::Receiver<Job> {
+        self.job_updates.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(name: &str) -> (JobQueue, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("craftstudio-jobs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        (JobQueue::open(&dir, 1, broadcast::channel(64).0), dir)
+    }
+
+    fn fetch(cid: &str) -> JobKind {
+        JobKind::Fetch {
+            cid: cid.to_string(),
+            output: None,
+        }
+    }
+
+    fn notification(method: &str, params: Value) -> String {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params }).to_string()
+    }
+
+    #[test]
+    fn transitions_only_from_allowed_states() {
+        let (queue, dir) = queue("transition");
+        let id = queue.enqueue(fetch("a")).unwrap().id;
+
+        assert!(queue.resume(id).is_err());
+        assert!(queue.retry(id).is_err());
+        assert_eq!(queue.pause(id).unwrap().state, JobState::Paused);
+        assert_eq!(queue.resume(id).unwrap().state, JobState::Queued);
+        assert_eq!(queue.cancel(id).unwrap().state, JobState::Cancelled);
+        assert!(queue.pause(id).is_err());
+        assert_eq!(queue.retry(id).unwrap().state, JobState::Queued);
+        assert!(queue.pause(99).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn claim_takes_queued_jobs_in_order_and_counts_attempts() {
+        let (queue, dir) = queue("claim");
+        let first = queue.enqueue(fetch("a")).unwrap().id;
+        let second = queue.enqueue(fetch("b")).unwrap().id;
+        queue.pause(first).unwrap();
+
+        let claimed = queue.claim().unwrap();
+        assert_eq!((claimed.id, claimed.state, claimed.attempts), (second, JobState::Running, 1));
+        assert!(queue.claim().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finish_records_outcome_unless_the_job_moved_on() {
+        let (queue, dir) = queue("finish");
+        let done = queue.enqueue(fetch("a")).unwrap().id;
+        queue.claim().unwrap();
+        queue.finish(done, Ok(json!({ "path": "/tmp/a" })));
+        let job = queue.list().into_iter().find(|j| j.id == done).unwrap();
+        assert_eq!(job.state, JobState::Done);
+        assert!(job.result.is_some());
+
+        let failed = queue.enqueue(fetch("b")).unwrap().id;
+        queue.claim().unwrap();
+        queue.finish(failed, Err("no providers".to_string()));
+        let job = queue.list().into_iter().find(|j| j.id == failed).unwrap();
+        assert_eq!((job.state, job.error.as_deref()), (JobState::Failed, Some("no providers")));
+
+        let paused = queue.enqueue(fetch("c")).unwrap().id;
+        queue.claim().unwrap();
+        queue.pause(paused).unwrap();
+        queue.finish(paused, Ok(Value::Null));
+        assert_eq!(queue.list().into_iter().find(|j| j.id == paused).unwrap().state, JobState::Paused);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn running_jobs_are_queued_again_on_reopen() {
+        let (queue, dir) = queue("reopen");
+        let id = queue.enqueue(fetch("a")).unwrap().id;
+        queue.claim().unwrap();
+        drop(queue);
+
+        let reopened = JobQueue::open(&dir, 2, broadcast::channel(64).0);
+        let job = reopened.list().into_iter().find(|j| j.id == id).unwrap();
+        assert_eq!((job.state, job.pid), (JobState::Queued, 2));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn publish_progress_follows_its_cid() {
+        let (queue, dir) = queue("publish");
+        let input = dir.join("big.bin");
+        std::fs::write(&input, vec![0u8; 4000]).unwrap();
+        let id = queue
+            .enqueue(JobKind::Publish {
+                path: input.to_string_lossy().to_string(),
+            })
+            .unwrap()
+            .id;
+        queue.claim().unwrap();
+
+        let published = json!({ "content_id": "cid-1", "size": 4000, "segment_count": 4 });
+        queue.observe(&notification("content_published", published));
+        queue.observe(&notification(
+            "distribution_progress",
+            json!({ "content_id": "cid-1", "pieces_pushed": 10, "total_pieces": 40 }),
+        ));
+        // Someone else's content doesn't move it
+        queue.observe(&notification(
+            "distribution_progress",
+            json!({ "content_id": "cid-2", "pieces_pushed": 40, "total_pieces": 40 }),
+        ));
+        let job = queue.list().into_iter().find(|j| j.id == id).unwrap();
+        assert_eq!(job.content_id.as_deref(), Some("cid-1"));
+        assert_eq!((job.segments_done, job.segments_total), (1, 4));
+        assert_eq!((job.bytes_done, job.bytes_total), (1000, 4000));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn content_published_binds_by_path_when_publishes_overlap() {
+        let (queue, dir) = queue("overlap");
+        let (a, b) = (dir.join("a.bin"), dir.join("b.bin"));
+        std::fs::write(&a, b"a").unwrap();
+        std::fs::write(&b, b"b").unwrap();
+        let publish = |p: &Path| JobKind::Publish {
+            path: p.to_string_lossy().to_string(),
+        };
+        let (a_id, b_id) = (queue.enqueue(publish(&a)).unwrap().id, queue.enqueue(publish(&b)).unwrap().id);
+        queue.claim().unwrap();
+        queue.claim().unwrap();
+
+        // Without a path it is ambiguous, so neither job takes it
+        queue.observe(&notification("content_published", json!({ "content_id": "x" })));
+        assert!(queue.list().iter().all(|j| j.content_id.is_none()));
+
+        queue.observe(&notification("content_published", json!({ "content_id": "b-cid", "path": b })));
+        let cids: Vec<_> = queue.list().into_iter().map(|j| (j.id, j.content_id)).collect();
+        assert_eq!(cids, [(a_id, None), (b_id, Some("b-cid".to_string()))]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
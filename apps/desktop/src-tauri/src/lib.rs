@@ -1,10 +1,57 @@
+mod api_keys;
+mod audit;
+mod backup;
+mod boot_peers;
+mod cluster;
 mod commands;
+mod compat;
 mod config;
+mod content_ctl;
 mod craftnet_adapter;
 mod daemon_manager;
+mod directory;
+mod events;
+mod faults;
+mod gateway;
+mod headless;
+mod http;
+mod jobs;
+mod listeners;
+mod metrics;
+mod mirror;
+mod mount;
+mod nat;
+mod peer_store;
+mod relocate;
+mod resources;
+mod services;
+#[cfg(feature = "sim")]
+pub mod sim;
+mod state_file;
+mod storage;
+mod swarm_ctl;
+mod sync;
+mod transports;
+mod vfs;
 
+use api_keys::{ApiKeyInfo, IssueKeyRequest, ScopedKey};
+use audit::{AuditRecord, MethodPolicy};
+use backup::{BackedUpInstance, RestoredInstance};
+use boot_peers::{BootPeerTest, BootPeers};
+use cluster::{ClusterInfo, ClusterSpec};
 use daemon_manager::{DaemonConfig, DaemonInstance, DaemonLogLayer, DaemonManager, LogLine, SharedLogs};
-use std::collections::HashMap;
+use directory::{PublishDirRequest, PublishProgress, PublishedDirectory};
+use faults::{FaultInfo, FaultSpec};
+use jobs::{Job, JobKind};
+use metrics::MetricsSnapshot;
+use mirror::Mirror;
+use mount::MountInfo;
+use peer_store::{ConnectionPolicy, PeerRecord};
+use relocate::MovedInstance;
+use storage::StorageReport;
+use sync::{SyncFolderRequest, SyncStatus, SyncedFile};
+use vfs::{VfsEntry, VfsSnapshot, VfsStats};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use tracing_subscriber::layer::SubscriberExt;
@@ -44,6 +91,590 @@ fn get_daemon_logs(
     state.get_logs(pid, since)
 }
 
+// ── Boot Peer Commands ─────────────────────────────────────────
+
+#[tauri::command]
+fn list_boot_peers(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    data_dir: String,
+) -> BootPeers {
+    state.list_boot_peers(&data_dir)
+}
+
+#[tauri::command]
+async fn add_boot_peer(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    data_dir: String,
+    addr: String,
+) -> Result<BootPeers, String> {
+    state.add_boot_peer(&data_dir, &addr).await
+}
+
+#[tauri::command]
+fn remove_boot_peer(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    data_dir: String,
+    addr: String,
+) -> Result<BootPeers, String> {
+    state.remove_boot_peer(&data_dir, &addr)
+}
+
+#[tauri::command]
+async fn test_boot_peer(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    addr: String,
+) -> Result<BootPeerTest, String> {
+    state.test_boot_peer(pid, &addr).await
+}
+
+// ── Peer Commands ──────────────────────────────────────────────
+
+#[tauri::command]
+fn list_peers(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<Vec<PeerRecord>, String> {
+    state.list_peers(pid)
+}
+
+#[tauri::command]
+fn pin_peer(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    peer_id: String,
+    pinned: bool,
+) -> Result<(), String> {
+    state.pin_peer(pid, &peer_id, pinned)
+}
+
+#[tauri::command]
+async fn ban_peer(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    peer_id: String,
+    banned: bool,
+) -> Result<(), String> {
+    state.ban_peer(pid, &peer_id, banned).await
+}
+
+#[tauri::command]
+async fn dial_peer(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    peer_id: String,
+) -> Result<(), String> {
+    state.dial_peer(pid, &peer_id).await
+}
+
+#[tauri::command]
+fn get_connection_policy(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<ConnectionPolicy, String> {
+    state.get_connection_policy(pid)
+}
+
+#[tauri::command]
+fn set_connection_policy(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    policy: ConnectionPolicy,
+) -> Result<(), String> {
+    state.set_connection_policy(pid, policy)
+}
+
+// ── Metrics Commands ───────────────────────────────────────────
+
+#[tauri::command]
+fn get_metrics(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<MetricsSnapshot, String> {
+    state.metrics_snapshot(pid)
+}
+
+// ── API Key Commands ───────────────────────────────────────────
+
+#[tauri::command]
+async fn rotate_api_key(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<String, String> {
+    state.rotate_api_key(pid).await
+}
+
+#[tauri::command]
+async fn issue_api_key(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    request: IssueKeyRequest,
+) -> Result<ScopedKey, String> {
+    state.issue_api_key(pid, request).await
+}
+
+#[tauri::command]
+fn list_api_keys(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<Vec<ApiKeyInfo>, String> {
+    state.list_api_keys(pid)
+}
+
+#[tauri::command]
+async fn revoke_api_key(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    name: String,
+) -> Result<(), String> {
+    state.revoke_api_key(pid, &name).await
+}
+
+// ── IPC Audit & Policy Commands ────────────────────────────────
+
+#[tauri::command]
+fn get_ipc_policy(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<MethodPolicy, String> {
+    state.get_ipc_policy(pid)
+}
+
+#[tauri::command]
+fn set_ipc_policy(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    policy: MethodPolicy,
+) -> Result<(), String> {
+    state.set_ipc_policy(pid, policy)
+}
+
+#[tauri::command]
+fn read_audit_log(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    since: usize,
+    limit: Option<usize>,
+) -> Result<Vec<AuditRecord>, String> {
+    state.read_audit_log(pid, since, limit.unwrap_or(500))
+}
+
+#[tauri::command]
+fn get_legacy_call_stats(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<HashMap<String, u64>, String> {
+    state.legacy_call_stats(pid)
+}
+
+// ── Cluster Commands ───────────────────────────────────────────
+
+#[tauri::command]
+async fn start_cluster(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    spec: ClusterSpec,
+) -> Result<ClusterInfo, String> {
+    state.start_cluster(spec).await
+}
+
+#[tauri::command]
+async fn stop_cluster(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    id: u32,
+) -> Result<(), String> {
+    state.stop_cluster(id).await
+}
+
+#[tauri::command]
+fn list_clusters(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+) -> Vec<ClusterInfo> {
+    state.list_clusters()
+}
+
+// ── Fault Injection Commands ───────────────────────────────────
+
+#[tauri::command]
+async fn inject_fault(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    spec: FaultSpec,
+) -> Result<FaultInfo, String> {
+    state.inject_fault(pid, spec).await
+}
+
+#[tauri::command]
+async fn revert_fault(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<(), String> {
+    state.revert_fault(pid, id).await
+}
+
+#[tauri::command]
+fn list_faults(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Vec<FaultInfo> {
+    state.list_faults(pid)
+}
+
+// ── Directory Publishing Commands ──────────────────────────────
+
+/// Progress is emitted as `directory-publish-progress` events.
+#[tauri::command]
+async fn publish_directory(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    request: PublishDirRequest,
+) -> Result<PublishedDirectory, String> {
+    use tauri::Emitter;
+    let progress = Arc::new(move |p: &PublishProgress| {
+        let _ = app.emit("directory-publish-progress", p);
+    });
+    state.publish_directory(pid, request, progress).await
+}
+
+// ── Job Queue Commands ─────────────────────────────────────────
+
+#[tauri::command]
+fn list_jobs(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<Vec<Job>, String> {
+    state.list_jobs(pid)
+}
+
+#[tauri::command]
+fn enqueue_job(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    kind: JobKind,
+) -> Result<Job, String> {
+    state.enqueue_job(pid, kind)
+}
+
+#[tauri::command]
+fn pause_job(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<Job, String> {
+    state.pause_job(pid, id)
+}
+
+#[tauri::command]
+fn resume_job(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<Job, String> {
+    state.resume_job(pid, id)
+}
+
+#[tauri::command]
+fn cancel_job(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<Job, String> {
+    state.cancel_job(pid, id)
+}
+
+#[tauri::command]
+fn retry_job(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<Job, String> {
+    state.retry_job(pid, id)
+}
+
+#[tauri::command]
+fn clear_finished_jobs(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<usize, String> {
+    state.clear_finished_jobs(pid)
+}
+
+// ── Folder Sync Commands ───────────────────────────────────────
+
+#[tauri::command]
+fn list_sync_folders(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<Vec<SyncStatus>, String> {
+    state.list_sync_folders(pid)
+}
+
+#[tauri::command]
+fn add_sync_folder(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    folder: SyncFolderRequest,
+) -> Result<SyncStatus, String> {
+    state.add_sync_folder(pid, folder)
+}
+
+#[tauri::command]
+fn remove_sync_folder(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<(), String> {
+    state.remove_sync_folder(pid, id)
+}
+
+#[tauri::command]
+fn rescan_sync_folder(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<(), String> {
+    state.rescan_sync_folder(pid, id)
+}
+
+#[tauri::command]
+fn list_synced_files(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<BTreeMap<String, SyncedFile>, String> {
+    state.synced_files(pid, id)
+}
+
+// ── Mirror Commands ────────────────────────────────────────────
+
+#[tauri::command]
+fn list_mirrors(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<Vec<Mirror>, String> {
+    state.list_mirrors(pid)
+}
+
+#[tauri::command]
+fn add_mirror(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    cid: String,
+    local_path: String,
+) -> Result<Job, String> {
+    state.add_mirror(pid, &cid, &local_path)
+}
+
+#[tauri::command]
+async fn update_mirror(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+    cid: String,
+    force: Option<bool>,
+) -> Result<Job, String> {
+    state.update_mirror(pid, id, &cid, force.unwrap_or(false)).await
+}
+
+#[tauri::command]
+fn remove_mirror(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+) -> Result<(), String> {
+    state.remove_mirror(pid, id)
+}
+
+#[tauri::command]
+fn resolve_mirror_conflict(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    id: u64,
+    path: String,
+    keep_local: bool,
+) -> Result<Option<Job>, String> {
+    state.resolve_mirror_conflict(pid, id, &path, keep_local)
+}
+
+// ── Instance Data Commands ─────────────────────────────────────
+
+#[tauri::command]
+async fn move_instance_data(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    new_path: String,
+    keep_old: Option<bool>,
+) -> Result<MovedInstance, String> {
+    state.move_instance_data(pid, &new_path, keep_old.unwrap_or(false)).await
+}
+
+#[tauri::command]
+async fn backup_instance(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    archive: String,
+    include_chunks: Option<bool>,
+) -> Result<BackedUpInstance, String> {
+    state.backup_instance(pid, &archive, include_chunks.unwrap_or(false)).await
+}
+
+#[tauri::command]
+async fn restore_instance(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    archive: String,
+    data_dir: Option<String>,
+    ws_port: Option<u16>,
+) -> Result<RestoredInstance, String> {
+    let config = DaemonConfig {
+        ws_port,
+        ..Default::default()
+    };
+    state.restore_instance(&archive, data_dir, config).await
+}
+
+// ── Storage Commands ───────────────────────────────────────────
+
+#[tauri::command]
+async fn storage_usage(state: tauri::State<'_, Arc<DaemonManager>>) -> Result<StorageReport, String> {
+    // Walking the data dirs can take a while
+    let manager = Arc::clone(state.inner());
+    tokio::task::spawn_blocking(move || manager.storage_usage())
+        .await
+        .map_err(|e| format!("Storage scan failed: {}", e))
+}
+
+// ── Mount Commands ─────────────────────────────────────────────
+
+#[tauri::command]
+async fn mount_instance(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    mountpoint: String,
+) -> Result<MountInfo, String> {
+    state.mount_instance(pid, &mountpoint).await
+}
+
+#[tauri::command]
+fn unmount_instance(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<(), String> {
+    state.unmount_instance(pid)
+}
+
+#[tauri::command]
+fn get_mount(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<Option<MountInfo>, String> {
+    state.mount_info(pid)
+}
+
+// ── Virtual Filesystem Commands ────────────────────────────────
+
+#[tauri::command]
+fn vfs_list(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    path: String,
+) -> Result<Vec<VfsEntry>, String> {
+    state.vfs_list(pid, &path)
+}
+
+#[tauri::command]
+fn vfs_stat(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    path: String,
+) -> Result<VfsEntry, String> {
+    state.vfs_stat(pid, &path)
+}
+
+#[tauri::command]
+fn vfs_mkdir(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    path: String,
+    parents: Option<bool>,
+) -> Result<VfsEntry, String> {
+    state.vfs_mkdir(pid, &path, parents.unwrap_or(false))
+}
+
+#[tauri::command]
+fn vfs_add_file(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    path: String,
+    cid: String,
+    size: u64,
+) -> Result<VfsEntry, String> {
+    state.vfs_add_file(pid, &path, &cid, size)
+}
+
+#[tauri::command]
+fn vfs_move(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    from: String,
+    to: String,
+) -> Result<VfsEntry, String> {
+    state.vfs_move(pid, &from, &to)
+}
+
+#[tauri::command]
+fn vfs_rename(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    path: String,
+    new_name: String,
+) -> Result<VfsEntry, String> {
+    state.vfs_rename(pid, &path, &new_name)
+}
+
+#[tauri::command]
+fn vfs_delete(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    path: String,
+    recursive: Option<bool>,
+) -> Result<usize, String> {
+    state.vfs_delete(pid, &path, recursive.unwrap_or(false))
+}
+
+#[tauri::command]
+fn vfs_stats(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<VfsStats, String> {
+    state.vfs_stats(pid)
+}
+
+#[tauri::command]
+fn vfs_snapshots(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<Vec<VfsSnapshot>, String> {
+    state.vfs_snapshots(pid)
+}
+
+#[tauri::command]
+async fn vfs_snapshot(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+) -> Result<VfsSnapshot, String> {
+    state.vfs_snapshot(pid).await
+}
+
+#[tauri::command]
+async fn vfs_restore(
+    state: tauri::State<'_, Arc<DaemonManager>>,
+    pid: u32,
+    cid: String,
+) -> Result<usize, String> {
+    state.vfs_restore(pid, &cid).await
+}
+
 pub fn run() {
     // Shared log storage for daemon instances
     let logs: SharedLogs = Arc::new(Mutex::new(HashMap::new()));
@@ -88,10 +719,27 @@ pub fn run() {
     let runtime_handle = tokio::runtime::Handle::current();
     let daemon_manager = Arc::new(DaemonManager::new(logs, runtime_handle.clone()));
 
+    let job_updates = daemon_manager.job_updates();
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .manage(daemon_manager)
-        .setup(|_app| {
+        .setup(move |app| {
+            // Forward job changes to the frontend as `job-progress` events
+            use tauri::Emitter;
+            let handle = app.handle().clone();
+            let mut job_updates = job_updates;
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    match job_updates.recv().await {
+                        Ok(job) => {
+                            let _ = handle.emit("job-progress", &job);
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -100,6 +748,7 @@ pub fn run() {
             commands::get_daemon_api_key,
             commands::discover_local_daemons,
             commands::pick_file,
+            commands::pick_folder,
             config::get_config,
             config::save_config,
             config::get_default_config,
@@ -109,6 +758,67 @@ pub fn run() {
             stop_craftobj_daemon,
             list_craftobj_daemons,
             get_daemon_logs,
+            list_boot_peers,
+            add_boot_peer,
+            remove_boot_peer,
+            test_boot_peer,
+            list_peers,
+            pin_peer,
+            ban_peer,
+            dial_peer,
+            get_connection_policy,
+            set_connection_policy,
+            get_metrics,
+            rotate_api_key,
+            issue_api_key,
+            list_api_keys,
+            revoke_api_key,
+            get_ipc_policy,
+            set_ipc_policy,
+            read_audit_log,
+            get_legacy_call_stats,
+            start_cluster,
+            stop_cluster,
+            list_clusters,
+            inject_fault,
+            revert_fault,
+            list_faults,
+            publish_directory,
+            list_sync_folders,
+            add_sync_folder,
+            remove_sync_folder,
+            rescan_sync_folder,
+            list_synced_files,
+            list_mirrors,
+            add_mirror,
+            update_mirror,
+            remove_mirror,
+            resolve_mirror_conflict,
+            move_instance_data,
+            backup_instance,
+            restore_instance,
+            storage_usage,
+            mount_instance,
+            unmount_instance,
+            get_mount,
+            list_jobs,
+            enqueue_job,
+            pause_job,
+            resume_job,
+            cancel_job,
+            retry_job,
+            clear_finished_jobs,
+            vfs_list,
+            vfs_stat,
+            vfs_mkdir,
+            vfs_add_file,
+            vfs_move,
+            vfs_rename,
+            vfs_delete,
+            vfs_stats,
+            vfs_snapshots,
+            vfs_snapshot,
+            vfs_restore,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -134,6 +844,15 @@ pub async fn run_headless() {
     println!("  CraftStudio v0.1.0 — headless daemon mode");
     println!();
 
+    let args = headless::HeadlessArgs::from_env();
+    if args.subcommand.is_some() {
+        if let Err(e) = headless::run_subcommand(&args).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Use same defaults as GUI mode (DaemonManager primary instance)
     let data_dir = dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from("."))
@@ -159,7 +878,7 @@ pub async fn run_headless() {
             "reannounce_interval_secs": 600,
             "reannounce_threshold_secs": 1200,
             "challenger_interval_secs": null,
-            "max_storage_bytes": 10_737_418_240_u64
+            "max_storage_bytes": storage::default_quota(&data_dir, &[])
         });
         if let Err(e) = std::fs::write(&config_path, serde_json::to_string_pretty(&daemon_cfg).unwrap_or_default()) {
             eprintln!("Warning: failed to write default config: {}", e);
@@ -178,8 +897,26 @@ pub async fn run_headless() {
     let keypair = libp2p::identity::Keypair::from(libp2p::identity::ed25519::Keypair::from(ed_libp2p));
     let dalek_key = ed25519_dalek::SigningKey::from_bytes(&node_signing_key.secret_key_bytes());
 
+    // --listen takes a comma-separated list of multiaddrs (TCP or QUIC-v1, IPv4/IPv6). The
+    // swarm has no WebSocket transport, so /ws addresses are rejected here.
+    // The addresses actually bound are logged by the swarm as it starts listening.
+    let listen = args
+        .list("listen")
+        .unwrap_or_else(|| vec![format!("/ip4/0.0.0.0/tcp/{}", listen_port)]);
+    let listen_addrs = match transports::validate_listen_addrs(&listen) {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for addr in &listen_addrs {
+        println!("  Listen address {}", addr);
+    }
+    println!();
+
     let network_config = NetworkConfig {
-        listen_addrs: vec![format!("/ip4/0.0.0.0/tcp/{}", listen_port).parse().expect("Invalid listen addr")],
+        listen_addrs,
         protocol_prefix: "craftobj".to_string(),
         ..Default::default()
     };
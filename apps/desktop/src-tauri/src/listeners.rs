@@ -0,0 +1,465 @@
+//! IPC listeners for an in-process daemon instance.
+//!
+//! Every instance has a `master` listener (the instance's socket + ws_port, authenticated
+//! with `{data_dir}/api_key`) and one listener per named key, since a `ServerBuilder`
+//! authenticates a single key. Every listener registers the same routes; for a named key
+//! each call is checked against the key's scopes before it reaches a handler, and calls
+//! outside them are refused (and audited as denied). Listeners are independent tasks, so a
+//! key can be rotated or revoked without touching the daemon's swarm or event loops. Once a
+//! listener is closed (rotated, revoked or expired), every call arriving through it is
+//! refused, including calls on connections it accepted before. Unprefixed methods on the
+//! master listener go through the compatibility layer in `compat`. Listeners opened with
+//! `events.subscribe` (see `events`) are tracked by the listener they came from and close
+//! with it. A subscription opened from a listener with a WebSocket port gets a WebSocket
+//! port of its own, so WS clients like the GUI can use them too.
+//!
+//! A listener with a WebSocket port serves it from a second server with handlers of its
+//! own, so audit records can say which transport a call used. Both servers share the
+//! listener's key, topics, subscriptions and closed flag. `ServerBuilder` always binds a
+//! Unix socket, so the WebSocket server also listens on `<socket>-ws.sock`, with the same key.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use craftec_ipc::server::IpcHandler;
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::{error, info, Instrument};
+
+use crate::api_keys::{now_secs, KeyScope, ScopedKey};
+use crate::audit::{AuditedHandler, CallGuard, Gate};
+use crate::compat::{LegacyHandler, LegacyStats};
+use crate::events::{lagged_notification, EventBus, EventsHandler, TopicFilter};
+
+/// `data.*` methods a `data_read` key may call. Not `fetch`: it writes to a path the
+/// caller picks.
+const DATA_READ_METHODS: &[&str] = &["list", "status", "providers"];
+/// Subscription listeners one listener may have open at a time.
+const MAX_SUBSCRIPTIONS: usize = 16;
+
+/// Handlers and event bus of a running instance, available once `init_daemon` completes.
+#[derive(Clone)]
+pub struct InstanceIpc {
+    /// CraftOBJ handler (`data` namespace and legacy unprefixed methods)
+    pub handler: Arc<dyn IpcHandler>,
+    /// Node service handlers keyed by namespace (`tunnel`, ...)
+    pub services: Vec<(&'static str, Arc<dyn IpcHandler>)>,
+    /// JSON-RPC notifications fanned out to every listener
+    pub events: Arc<EventBus>,
+    /// Audit log and method policy applied to every listener
+    pub guard: CallGuard,
+    /// Counts of deprecated unprefixed calls
+    pub legacy: Arc<LegacyStats>,
+}
+
+/// What a single listener binds and serves.
+#[derive(Clone)]
+pub struct ListenerSpec {
+    pub name: String,
+    pub socket_path: String,
+    pub ws_port: Option<u16>,
+    pub api_key: String,
+    /// `None` serves every namespace plus the legacy default handler.
+    pub scopes: Option<Vec<KeyScope>>,
+    pub expires_at: Option<u64>,
+    /// Event topic filter; empty forwards everything the scopes cover
+    pub topics: Vec<String>,
+    /// Set on a listener opened by `events.subscribe`: the filter it forwards through
+    pub subscription: Option<TopicFilter>,
+    /// Whether clients may open subscriptions from this listener
+    pub subscribable: bool,
+}
+
+impl ListenerSpec {
+    pub fn master(socket_path: &str, ws_port: u16, api_key: String) -> Self {
+        Self {
+            name: "master".to_string(),
+            socket_path: socket_path.to_string(),
+            ws_port: Some(ws_port),
+            api_key,
+            scopes: None,
+            expires_at: None,
+            topics: Vec::new(),
+            subscription: None,
+            subscribable: true,
+        }
+    }
+
+    pub fn scoped(key: &ScopedKey) -> Self {
+        Self {
+            name: key.name.clone(),
+            socket_path: key.socket_path.clone(),
+            ws_port: key.ws_port,
+            api_key: key.key.clone(),
+            scopes: Some(key.scopes.clone()),
+            expires_at: key.expires_at,
+            topics: key.topics.clone(),
+            subscription: None,
+            subscribable: true,
+        }
+    }
+}
+
+/// Namespace table a listener serves. Dispatch mirrors `ServerBuilder`: `ns.method` goes to
+/// the `ns` handler as `method`, anything else to the default handler unchanged.
+pub struct Routes {
+    pub topics: TopicFilter,
+    /// Set once the listener is gone; its handlers refuse calls from then on
+    pub closed: Arc<AtomicBool>,
+    pub subscriptions: Option<Arc<Subscriptions>>,
+    namespaces: Vec<(&'static str, Arc<dyn IpcHandler>)>,
+    default: Option<Arc<dyn IpcHandler>>,
+}
+
+impl Routes {
+    /// Routes of a new listener whose calls arrive over `transport`.
+    pub fn build(spec: &ListenerSpec, ipc: &InstanceIpc, transport: &'static str) -> Self {
+        let topics = match &spec.subscription {
+            Some(filter) => filter.clone(),
+            None => TopicFilter::new(spec.topics.clone()).within_scopes(spec.scopes.as_deref()),
+        };
+        let subscriptions = (spec.subscribable && spec.subscription.is_none())
+            .then(|| Arc::new(Subscriptions::new(spec.clone(), topics.clone(), ipc.clone())));
+        Self::assemble(spec, ipc, transport, topics, Arc::new(AtomicBool::new(false)), subscriptions)
+    }
+
+    /// Routes for another transport of the same listener, sharing its state.
+    fn sibling(&self, spec: &ListenerSpec, ipc: &InstanceIpc, transport: &'static str) -> Self {
+        Self::assemble(
+            spec,
+            ipc,
+            transport,
+            self.topics.clone(),
+            Arc::clone(&self.closed),
+            self.subscriptions.clone(),
+        )
+    }
+
+    fn assemble(
+        spec: &ListenerSpec,
+        ipc: &InstanceIpc,
+        transport: &'static str,
+        topics: TopicFilter,
+        closed: Arc<AtomicBool>,
+        subscriptions: Option<Arc<Subscriptions>>,
+    ) -> Self {
+        let audited = |namespace: &'static str, handler: Arc<dyn IpcHandler>, gate: Option<Gate>| {
+            AuditedHandler::wrap(namespace, &spec.name, transport, handler, &ipc.guard, gate, &closed)
+        };
+        let events = EventsHandler {
+            topics: topics.clone(),
+            bus: Arc::clone(&ipc.events),
+            subscriptions: subscriptions.clone(),
+        };
+        let mut namespaces = vec![("events", audited("events", Arc::new(events), None))];
+
+        // Every listener registers the same routes; a scoped key's calls are checked per call
+        let scoped = |namespace: &'static str| spec.scopes.clone().map(|scopes| scope_gate(scopes, namespace));
+        namespaces.push(("data", audited("data", ipc.handler.clone(), scoped("data"))));
+        for (namespace, handler) in &ipc.services {
+            namespaces.push((*namespace, audited(namespace, handler.clone(), scoped(namespace))));
+        }
+        let legacy = LegacyHandler::new(ipc.handler.clone(), ipc.guard.clone(), ipc.legacy.clone());
+        let default = Some(audited("", Arc::new(legacy), scoped("")));
+
+        Self {
+            topics,
+            closed,
+            subscriptions,
+            namespaces,
+            default,
+        }
+    }
+
+    /// Call a method without going through a socket (used by the in-memory IPC mode).
+    pub async fn dispatch(&self, method: &str, params: Option<Value>) -> Result<Value, String> {
+        if let Some((ns, rest)) = method.split_once('.') {
+            if let Some((_, handler)) = self.namespaces.iter().find(|(n, _)| *n == ns) {
+                return handler.handle(rest, params).await;
+            }
+        }
+        match &self.default {
+            Some(handler) => handler.handle(method, params).await,
+            None => Err(format!("Method not found: {}", method)),
+        }
+    }
+}
+
+/// Marks a listener closed, and closes its subscriptions, when its task ends, including by
+/// abort.
+struct CloseOnDrop(Arc<AtomicBool>, Option<Arc<Subscriptions>>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+        if let Some(subscriptions) = &self.1 {
+            subscriptions.close_all();
+        }
+    }
+}
+
+/// Subscription listeners opened from one listener through `events.subscribe`.
+pub struct Subscriptions {
+    parent: ListenerSpec,
+    topics: TopicFilter,
+    ipc: InstanceIpc,
+    next: AtomicU64,
+    open: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl Subscriptions {
+    fn new(parent: ListenerSpec, topics: TopicFilter, ipc: InstanceIpc) -> Self {
+        Self {
+            parent,
+            topics,
+            ipc,
+            next: AtomicU64::new(1),
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start a listener that serves what the parent does but only forwards `topics`
+    /// (within the parent's own filter). Returns the socket to connect to, and a WebSocket
+    /// port if the parent has one.
+    pub fn open(&self, topics: Vec<String>) -> Result<Value, String> {
+        if topics.is_empty() {
+            return Err("A subscription needs at least one topic".to_string());
+        }
+        let mut open = self.open.lock().unwrap();
+        open.retain(|_, listener| !listener.is_finished());
+        if open.len() >= MAX_SUBSCRIPTIONS {
+            return Err(format!("At most {} subscriptions can be open per listener", MAX_SUBSCRIPTIONS));
+        }
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        let stem = self.parent.socket_path.strip_suffix(".sock").unwrap_or(&self.parent.socket_path);
+        let socket_path = format!("{}-events-{}.sock", stem, n);
+        let ws_port = self.parent.ws_port.map(|_| free_port()).transpose()?;
+        let spec = ListenerSpec {
+            socket_path: socket_path.clone(),
+            ws_port,
+            subscription: Some(self.topics.narrowed(topics.clone())),
+            subscribable: false,
+            ..self.parent.clone()
+        };
+        open.insert(socket_path.clone(), spawn_listener(spec, &self.ipc));
+        info!("Listener '{}' opened event subscription {} for {:?}", self.parent.name, socket_path, topics);
+        Ok(json!({ "socket_path": socket_path, "ws_port": ws_port, "topics": topics }))
+    }
+
+    pub fn close(&self, socket_path: &str) -> Result<(), String> {
+        let listener = self
+            .open
+            .lock()
+            .unwrap()
+            .remove(socket_path)
+            .ok_or_else(|| format!("No subscription at {}", socket_path))?;
+        listener.abort();
+        Ok(())
+    }
+
+    fn close_all(&self) {
+        for (_, listener) in self.open.lock().unwrap().drain() {
+            listener.abort();
+        }
+    }
+}
+
+/// Build and run an IPC server for `spec` until it fails, expires or is aborted.
+/// A listener that used the same socket path must have finished before this is called.
+pub fn spawn_listener(spec: ListenerSpec, ipc: &InstanceIpc) -> JoinHandle<()> {
+    // A listener that crashed or an earlier run may have left its socket behind
+    let _ = std::fs::remove_file(&spec.socket_path);
+
+    let routes = Routes::build(&spec, ipc, "socket");
+    let ws = spec.ws_port.map(|port| {
+        let socket_path = ws_socket_path(&spec.socket_path);
+        let _ = std::fs::remove_file(&socket_path);
+        server(&socket_path, &spec.api_key, routes.sibling(&spec, ipc, "ws")).with_websocket(port)
+    });
+    let topics = routes.topics.clone();
+    let close_on_drop = CloseOnDrop(Arc::clone(&routes.closed), routes.subscriptions.clone());
+    let builder = server(&spec.socket_path, &spec.api_key, routes);
+
+    let mut server_event_txs = vec![builder.event_sender()];
+    server_event_txs.extend(ws.as_ref().map(|ws| ws.event_sender()));
+    let mut events_rx = ipc.events.subscribe();
+    let bus = Arc::clone(&ipc.events);
+    let metrics = Arc::clone(&ipc.guard.metrics);
+    let name = spec.name;
+    let expires_in = spec
+        .expires_at
+        .map(|t| Duration::from_secs(t.saturating_sub(now_secs())));
+
+    tokio::spawn(
+        async move {
+            let _close_on_drop = close_on_drop;
+            let forward = tokio::spawn(async move {
+                loop {
+                    match events_rx.recv().await {
+                        Ok(notification) => {
+                            if topics.matches(&notification) {
+                                for tx in &server_event_txs {
+                                    let _ = tx.send(notification.clone());
+                                }
+                            }
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                            // Tell this listener's clients to resync (or replay) rather than drift
+                            metrics.observe_lag(missed);
+                            let lagged = lagged_notification(missed, bus.latest_seq());
+                            for tx in &server_event_txs {
+                                let _ = tx.send(lagged.clone());
+                            }
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            });
+
+            let expiry = async {
+                match expires_in {
+                    Some(d) => tokio::time::sleep(d).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let ws_run = async {
+                match ws {
+                    Some(ws) => ws.run().await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                result = builder.run() => {
+                    if let Err(e) = result {
+                        error!("IPC listener '{}' error: {}", name, e);
+                    }
+                }
+                result = ws_run => {
+                    if let Err(e) = result {
+                        error!("IPC listener '{}' WebSocket error: {}", name, e);
+                    }
+                }
+                _ = expiry => {
+                    info!("API key '{}' expired, listener closed", name);
+                }
+            }
+            forward.abort();
+        }
+        .instrument(tracing::Span::current()),
+    )
+}
+
+/// Server for one transport of a listener.
+fn server(socket_path: &str, api_key: &str, routes: Routes) -> craftec_ipc::ServerBuilder {
+    let mut builder = craftec_ipc::ServerBuilder::new(socket_path).with_api_key(api_key.to_string());
+    for (namespace, handler) in routes.namespaces {
+        builder = builder.namespace(namespace, handler);
+    }
+    if let Some(handler) = routes.default {
+        builder = builder.default_handler(handler);
+    }
+    builder
+}
+
+/// Unix socket the WebSocket server of a listener binds next to `socket_path`.
+fn ws_socket_path(socket_path: &str) -> String {
+    format!("{}-ws.sock", socket_path.strip_suffix(".sock").unwrap_or(socket_path))
+}
+
+/// A loopback port nothing is listening on. `ServerBuilder` takes a port rather than a
+/// bound socket, so another process could still take it before the server binds.
+fn free_port() -> Result<u16, String> {
+    std::net::TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .map_err(|e| format!("Failed to find a free port for the subscription: {}", e))
+}
+
+/// Whether `scopes` cover `method` in `namespace` (`""` for the default handler, which
+/// serves `settlement.*` to scoped keys).
+fn permits(scopes: &[KeyScope], namespace: &str, method: &str) -> bool {
+    match namespace {
+        "data" => {
+            scopes.contains(&KeyScope::Data)
+                || (scopes.contains(&KeyScope::DataRead) && DATA_READ_METHODS.contains(&method))
+        }
+        "tunnel" => scopes.contains(&KeyScope::Tunnel),
+        "" => scopes.contains(&KeyScope::Settlement) && method.starts_with("settlement."),
+        _ => false,
+    }
+}
+
+/// Refuses calls outside a named key's scopes.
+fn scope_gate(scopes: Vec<KeyScope>, namespace: &'static str) -> Gate {
+    Arc::new(move |method: &str| {
+        if permits(&scopes, namespace, method) {
+            Ok(())
+        } else if namespace.is_empty() {
+            Err(format!("{} is outside this key's scopes", method))
+        } else {
+            Err(format!("{}.{} is outside this key's scopes", namespace, method))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_are_checked_per_method() {
+        let read = [KeyScope::DataRead];
+        assert!(permits(&read, "data", "list"));
+        assert!(!permits(&read, "data", "publish"));
+        assert!(!permits(&read, "data", "fetch"));
+        assert!(!permits(&read, "tunnel", "status"));
+        assert!(!permits(&read, "", "list"));
+
+        let both = [KeyScope::Data, KeyScope::Tunnel];
+        assert!(permits(&both, "data", "publish"));
+        assert!(permits(&both, "tunnel", "connect"));
+        assert!(!permits(&both, "", "settlement.balance"));
+
+        let settlement = [KeyScope::Settlement];
+        assert!(permits(&settlement, "", "settlement.balance"));
+        assert!(!permits(&settlement, "", "node.stats"));
+        assert!(!permits(&settlement, "relay", "status"));
+    }
+
+    struct Echo;
+
+    impl IpcHandler for Echo {
+        fn handle(
+            &self,
+            _method: &str,
+            _params: Option<Value>,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Value, String>> + Send + '_>> {
+            Box::pin(async { Ok(Value::Null) })
+        }
+    }
+
+    #[test]
+    fn calls_outside_scopes_are_audited_as_denied() {
+        let dir = std::env::temp_dir().join(format!("craftstudio-scope-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let metrics = Arc::new(crate::metrics::InstanceMetrics::new(0));
+        let guard = CallGuard::open(&dir, Arc::clone(&metrics), None);
+        let gate = scope_gate(vec![KeyScope::DataRead], "data");
+        let handler = AuditedHandler::wrap("data", "reader", "memory", Arc::new(Echo), &guard, Some(gate), &Arc::default());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        assert!(rt.block_on(handler.handle("list", None)).is_ok());
+        let err = rt.block_on(handler.handle("publish", None)).unwrap_err();
+        assert_eq!(err, "data.publish is outside this key's scopes");
+
+        let stats = &metrics.snapshot().ipc["data"];
+        assert_eq!((stats.ok, stats.error, stats.denied), (1, 0, 1));
+        drop(handler);
+        drop(guard);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+//! Per-instance metrics.
+//!
+//! Each instance has an `InstanceMetrics` registry. It is fed from several places:
+//! - the IPC audit wrapper: request counts and latency per namespace, and audit records
+//!   dropped because the writer fell behind
+//! - the event bridge and listeners: notifications lost to lag
+//! - the log buffer: lines dropped by the 500-line cap
+//! - `sample`, which polls the swarm for connected peers and traffic totals and reads DHT
+//!   query timings off the event bus
+//!
+//! The GUI reads a `MetricsSnapshot` through a Tauri command. With `metrics_port` set the
+//! same data is served as OpenMetrics text on `http://127.0.0.1:<port>/metrics`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::daemon_manager::DaemonManager;
+use crate::http::{self, Response};
+use crate::swarm_ctl::SwarmControl;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Log lines dropped per instance. Lives outside the registry because the tracing layer
+/// only knows the instance ID.
+static DROPPED_LOG_LINES: LazyLock<Mutex<HashMap<u32, u64>>> = LazyLock::new(Default::default);
+
+pub(crate) fn count_dropped_log_lines(pid: u32, n: usize) {
+    *DROPPED_LOG_LINES.lock().unwrap().entry(pid).or_default() += n as u64;
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Timing {
+    pub count: u64,
+    pub sum_secs: f64,
+}
+
+impl Timing {
+    fn observe(&mut self, d: Duration) {
+        self.count += 1;
+        self.sum_secs += d.as_secs_f64();
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IpcNamespaceStats {
+    pub ok: u64,
+    pub error: u64,
+    pub denied: u64,
+    pub latency: Timing,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MetricsSnapshot {
+    pub connected_peers: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub dht_queries: Timing,
+    /// Keyed by namespace; `default` for unprefixed methods
+    pub ipc: BTreeMap<String, IpcNamespaceStats>,
+    pub events_lagged: u64,
+    pub log_lines_dropped: u64,
+    pub audit_records_dropped: u64,
+}
+
+pub struct InstanceMetrics {
+    pid: u32,
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl InstanceMetrics {
+    pub fn new(pid: u32) -> Self {
+        DROPPED_LOG_LINES.lock().unwrap().remove(&pid);
+        Self {
+            pid,
+            inner: Mutex::new(MetricsSnapshot::default()),
+        }
+    }
+
+    pub fn observe_ipc(&self, namespace: &str, status: &str, elapsed: Duration) {
+        let namespace = if namespace.is_empty() { "default" } else { namespace };
+        let mut inner = self.inner.lock().unwrap();
+        let stats = inner.ipc.entry(namespace.to_string()).or_default();
+        match status {
+            "ok" => stats.ok += 1,
+            "denied" => stats.denied += 1,
+            _ => stats.error += 1,
+        }
+        stats.latency.observe(elapsed);
+    }
+
+    pub fn observe_lag(&self, missed: u64) {
+        self.inner.lock().unwrap().events_lagged += missed;
+    }
+
+    pub fn observe_audit_drop(&self) {
+        self.inner.lock().unwrap().audit_records_dropped += 1;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut snapshot = self.inner.lock().unwrap().clone();
+        snapshot.log_lines_dropped = DROPPED_LOG_LINES.lock().unwrap().get(&self.pid).copied().unwrap_or(0);
+        snapshot
+    }
+
+    /// OpenMetrics text exposition.
+    pub fn render(&self) -> String {
+        let s = self.snapshot();
+        let mut out = String::new();
+        let gauge = |out: &mut String, name: &str, help: &str, v: u64| {
+            let _ = write!(out, "# TYPE {name} gauge\n# HELP {name} {help}\n{name} {v}\n");
+        };
+        let counter = |out: &mut String, name: &str, help: &str, v: u64| {
+            let _ = write!(out, "# TYPE {name} counter\n# HELP {name} {help}\n{name}_total {v}\n");
+        };
+        gauge(&mut out, "craftobj_connected_peers", "Currently connected peers.", s.connected_peers);
+        counter(&mut out, "craftobj_received_bytes", "Bytes received by the swarm.", s.bytes_in);
+        counter(&mut out, "craftobj_sent_bytes", "Bytes sent by the swarm.", s.bytes_out);
+        counter(&mut out, "craftobj_events_lagged", "Notifications skipped by lagging consumers.", s.events_lagged);
+        counter(&mut out, "craftobj_log_lines_dropped", "Log lines dropped from the instance buffer.", s.log_lines_dropped);
+        counter(&mut out, "craftobj_audit_records_dropped", "Audit records dropped by a full writer queue.", s.audit_records_dropped);
+
+        let _ = write!(
+            out,
+            "# TYPE craftobj_dht_query_seconds summary\n# HELP craftobj_dht_query_seconds DHT query duration.\n\
+             craftobj_dht_query_seconds_count {}\ncraftobj_dht_query_seconds_sum {}\n",
+            s.dht_queries.count, s.dht_queries.sum_secs
+        );
+
+        out.push_str("# TYPE craftobj_ipc_requests counter\n# HELP craftobj_ipc_requests IPC requests by namespace and status.\n");
+        for (ns, stats) in &s.ipc {
+            for (status, v) in [("ok", stats.ok), ("error", stats.error), ("denied", stats.denied)] {
+                let _ = writeln!(out, "craftobj_ipc_requests_total{{namespace=\"{ns}\",status=\"{status}\"}} {v}");
+            }
+        }
+        out.push_str("# TYPE craftobj_ipc_request_seconds summary\n# HELP craftobj_ipc_request_seconds IPC request duration by namespace.\n");
+        for (ns, stats) in &s.ipc {
+            let _ = writeln!(out, "craftobj_ipc_request_seconds_count{{namespace=\"{ns}\"}} {}", stats.latency.count);
+            let _ = writeln!(out, "craftobj_ipc_request_seconds_sum{{namespace=\"{ns}\"}} {}", stats.latency.sum_secs);
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+/// Poll the swarm and watch the bus for DHT timings until the bus closes.
+pub async fn sample(
+    metrics: Arc<InstanceMetrics>,
+    mut events: tokio::sync::broadcast::Receiver<String>,
+    swarm: SwarmControl,
+) {
+    let mut tick = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(notification) => {
+                    let Ok(v) = serde_json::from_str::<Value>(&notification) else { continue };
+                    let is_dht = v.get("method").and_then(|m| m.as_str()).is_some_and(|m| m.starts_with("dht_"));
+                    if let (true, Some(ms)) = (is_dht, v.pointer("/params/duration_ms").and_then(|d| d.as_u64())) {
+                        metrics.inner.lock().unwrap().dht_queries.observe(Duration::from_millis(ms));
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+            _ = tick.tick() => {
+                let peers = swarm.connected_peers().await.ok().map(|p| p.len() as u64);
+                let traffic = swarm.traffic().await.ok();
+                let mut inner = metrics.inner.lock().unwrap();
+                if let Some(peers) = peers {
+                    inner.connected_peers = peers;
+                }
+                if let Some((bytes_in, bytes_out)) = traffic {
+                    inner.bytes_in = bytes_in;
+                    inner.bytes_out = bytes_out;
+                }
+            }
+        }
+    }
+}
+
+/// Serve `/metrics` on loopback until aborted.
+pub async fn serve(metrics: Arc<InstanceMetrics>, port: u16) -> Result<(), String> {
+    let handler: http::Handler = Arc::new(move |req| {
+        let metrics = Arc::clone(&metrics);
+        Box::pin(async move {
+            match req.path.as_str() {
+                "/metrics" => Response::new(
+                    200,
+                    "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                    metrics.render().into_bytes(),
+                ),
+                _ => Response::text(404, "Not found"),
+            }
+        })
+    });
+    http::serve(([127, 0, 0, 1], port).into(), handler).await
+}
+
+impl DaemonManager {
+    pub fn metrics_snapshot(&self, pid: u32) -> Result<MetricsSnapshot, String> {
+        self.with_daemon(pid, |d| d.metrics.snapshot())
+    }
+}
@@ -0,0 +1,642 @@
+//! Mirroring published directories into local folders.
+//!
+//! A mirror materializes a directory manifest (see `directory`) into a local path and
+//! remembers what it wrote in `{data_dir}/mirrors.json`. A mirror moves to a newer
+//! manifest CID when one is given by hand, or by itself when content is published,
+//! announced or retrieved on the instance's event bus and turns out to be a manifest
+//! descending from the mirror's target. The new manifest must descend from the current one
+//! through its `previous` links unless the update is forced. Each sync runs as a job in
+//! the instance's queue. Only content up to `MAX_MANIFEST_BYTES` is read as a manifest.
+//!
+//! A sync only fetches files whose hash differs from the local copy. Each fetch goes to a
+//! temporary file next to its target, is checked against the manifest's size and SHA-256,
+//! and is then renamed into place. A local file that was edited since the mirror last
+//! wrote it is never overwritten or deleted. Instead the mirror records a conflict, which
+//! is resolved by keeping the local copy or taking the remote one.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::api_keys::now_secs;
+use crate::content_ctl::ContentControl;
+use crate::daemon_manager::DaemonManager;
+use crate::directory::{self, DirectoryManifest, ManifestFile, MAX_MANIFEST_BYTES};
+use crate::jobs::{Job, JobKind, JobQueue};
+use crate::resources::IoLimiter;
+use crate::state_file;
+
+/// `previous` links followed when checking that an update descends from the current version.
+const MAX_LINEAGE: usize = 32;
+const PART_SUFFIX: &str = ".craftmirror-part";
+/// Bus notifications naming content that may be a new version of a mirrored directory.
+const ANNOUNCEMENTS: &[&str] = &["content_published", "provider_announced", "manifest_retrieved"];
+/// Content IDs remembered as not being a new version, before the memo starts over.
+const MAX_CHECKED: usize = 4096;
+
+/// What the mirror last wrote to a path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirroredFile {
+    pub sha256: String,
+    pub size: u64,
+    /// Local mtime right after the write, to skip rehashing untouched files
+    pub modified_at: u64,
+    /// The user chose to keep a local edit over this remote version
+    #[serde(default)]
+    pub keep_local: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorConflict {
+    pub path: String,
+    /// `None` when the file was deleted locally
+    pub local_sha256: Option<String>,
+    /// `None` when the file was removed from the remote directory
+    pub remote_cid: Option<String>,
+    pub remote_sha256: Option<String>,
+    pub detected_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mirror {
+    pub id: u64,
+    pub local_path: String,
+    /// Version currently materialized; `None` until the first sync finishes
+    pub manifest_cid: Option<String>,
+    /// Version the next sync moves to
+    pub target_cid: String,
+    #[serde(default)]
+    pub files: BTreeMap<String, MirroredFile>,
+    #[serde(default)]
+    pub conflicts: Vec<MirrorConflict>,
+    pub last_sync_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+impl Mirror {
+    /// Drop the conflict on `path` and record the choice in `files` for the next sync.
+    fn settle_conflict(&mut self, path: &str, keep_local: bool) -> Result<(), String> {
+        let pos = self
+            .conflicts
+            .iter()
+            .position(|c| c.path == path)
+            .ok_or_else(|| format!("No conflict on '{}'", path))?;
+        let conflict = self.conflicts.remove(pos);
+        let local = PathBuf::from(&self.local_path).join(path);
+        let meta = std::fs::metadata(&local).ok();
+        match (keep_local, conflict.remote_sha256, conflict.local_sha256) {
+            (true, Some(remote_sha), _) => {
+                self.files.insert(
+                    path.to_string(),
+                    MirroredFile {
+                        sha256: remote_sha,
+                        size: meta.as_ref().map_or(0, |m| m.len()),
+                        modified_at: meta.as_ref().map_or(0, mtime),
+                        keep_local: true,
+                    },
+                );
+            }
+            // Removed remotely and kept locally: the mirror lets go of the file
+            (true, None, _) => {
+                self.files.remove(path);
+            }
+            // Deleted locally: forget it, so the sync fetches it as a new file
+            (false, _, None) => {
+                self.files.remove(path);
+            }
+            // Treat the local copy as unedited so the sync replaces or deletes it
+            (false, _, Some(local_sha)) => {
+                self.files.insert(
+                    path.to_string(),
+                    MirroredFile {
+                        sha256: local_sha,
+                        size: meta.as_ref().map_or(0, |m| m.len()),
+                        modified_at: meta.as_ref().map_or(0, mtime),
+                        keep_local: false,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MirrorReport {
+    pub fetched: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+    pub conflicts: usize,
+    pub bytes_fetched: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct MirrorBook {
+    next_id: u64,
+    mirrors: Vec<Mirror>,
+}
+
+pub struct MirrorStore {
+    path: PathBuf,
+    /// Where fetched manifests are kept
+    manifest_dir: PathBuf,
+    book: Mutex<MirrorBook>,
+}
+
+impl MirrorStore {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join("mirrors.json");
+        let book = state_file::load(&path);
+        Self {
+            path,
+            manifest_dir: data_dir.join("mirror-manifests"),
+            book: Mutex::new(book),
+        }
+    }
+
+    pub fn list(&self) -> Vec<Mirror> {
+        self.book.lock().unwrap().mirrors.clone()
+    }
+
+    pub fn get(&self, id: u64) -> Result<Mirror, String> {
+        self.book
+            .lock()
+            .unwrap()
+            .mirrors
+            .iter()
+            .find(|m| m.id == id)
+            .cloned()
+            .ok_or_else(|| format!("No mirror {}", id))
+    }
+
+    pub fn add(&self, cid: &str, local_path: &str) -> Result<Mirror, String> {
+        let local = PathBuf::from(local_path);
+        if !local.is_absolute() {
+            return Err(format!("Mirror path must be absolute: '{}'", local_path));
+        }
+        std::fs::create_dir_all(&local).map_err(|e| format!("Failed to create {}: {}", local.display(), e))?;
+        let mirror = {
+            let mut book = self.book.lock().unwrap();
+            if let Some(existing) = book.mirrors.iter().find(|m| Path::new(&m.local_path) == local) {
+                return Err(format!("{} is already mirror {}", local_path, existing.id));
+            }
+            book.next_id += 1;
+            let mirror = Mirror {
+                id: book.next_id,
+                local_path: local_path.to_string(),
+                manifest_cid: None,
+                target_cid: cid.to_string(),
+                files: BTreeMap::new(),
+                conflicts: Vec::new(),
+                last_sync_at: None,
+                last_error: None,
+            };
+            book.mirrors.push(mirror.clone());
+            mirror
+        };
+        self.save()?;
+        Ok(mirror)
+    }
+
+    /// Stop tracking a mirror. The local files stay.
+    pub fn remove(&self, id: u64) -> Result<(), String> {
+        {
+            let mut book = self.book.lock().unwrap();
+            let before = book.mirrors.len();
+            book.mirrors.retain(|m| m.id != id);
+            if book.mirrors.len() == before {
+                return Err(format!("No mirror {}", id));
+            }
+        }
+        self.save()
+    }
+
+    fn update<T>(&self, id: u64, f: impl FnOnce(&mut Mirror) -> T) -> Result<T, String> {
+        let out = {
+            let mut book = self.book.lock().unwrap();
+            let mirror = book.mirrors.iter_mut().find(|m| m.id == id).ok_or_else(|| format!("No mirror {}", id))?;
+            f(mirror)
+        };
+        self.save()?;
+        Ok(out)
+    }
+
+    /// Fetch and parse a manifest, reusing one fetched before. Content that is too large or
+    /// not a manifest isn't kept.
+    async fn manifest(&self, content: &ContentControl, cid: &str) -> Result<DirectoryManifest, String> {
+        let path = self.manifest_dir.join(format!("{}.json", cid));
+        if !path.exists() {
+            std::fs::create_dir_all(&self.manifest_dir)
+                .map_err(|e| format!("Failed to create {}: {}", self.manifest_dir.display(), e))?;
+            let written = content.fetch(cid, &path).await?;
+            if written != path {
+                std::fs::rename(&written, &path).map_err(|e| format!("Failed to stage manifest {}: {}", cid, e))?;
+            }
+        }
+        let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if size > MAX_MANIFEST_BYTES {
+            let _ = std::fs::remove_file(&path);
+            return Err(format!("{} is {} bytes, too large for a directory manifest", cid, size));
+        }
+        let raw = std::fs::read(&path).map_err(|e| format!("Failed to read manifest {}: {}", cid, e))?;
+        DirectoryManifest::parse(&raw).map_err(|e| {
+            let _ = std::fs::remove_file(&path);
+            format!("{}: {}", cid, e)
+        })
+    }
+
+    /// Check that `cid` is `current` or descends from it.
+    async fn check_lineage(&self, content: &ContentControl, current: &str, cid: &str) -> Result<(), String> {
+        let mut next = Some(cid.to_string());
+        for _ in 0..MAX_LINEAGE {
+            let Some(at) = next else { break };
+            if at == current {
+                return Ok(());
+            }
+            next = self.manifest(content, &at).await?.previous;
+        }
+        Err(format!(
+            "{} does not descend from the mirrored version {}; force the update to switch anyway",
+            cid, current
+        ))
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&*self.book.lock().unwrap())
+            .map_err(|e| format!("Failed to serialize mirrors: {}", e))?;
+        state_file::write(&self.path, json.as_bytes())
+    }
+}
+
+/// Local state of a mirrored path: `None` if missing, else its hash. Files the mirror wrote
+/// and nobody touched since reuse the recorded hash.
+async fn local_sha(path: &Path, known: Option<&MirroredFile>, io: Option<&IoLimiter>) -> Result<Option<String>, String> {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return Ok(None);
+    };
+    if let Some(k) = known {
+        if k.size == meta.len() && k.modified_at == mtime(&meta) {
+            return Ok(Some(k.sha256.clone()));
+        }
+    }
+    directory::hash_file(path, io).await.map(Some)
+}
+
+fn mtime(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Fetch one file next to its target, verify it and move it into place.
+async fn fetch_verified(
+    content: &ContentControl,
+    io: Option<&IoLimiter>,
+    target: &Path,
+    remote: &ManifestFile,
+) -> Result<MirroredFile, String> {
+    let parent = target.parent().ok_or_else(|| format!("Invalid target {}", target.display()))?;
+    std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    let part = PathBuf::from(format!("{}{}", target.display(), PART_SUFFIX));
+    let written = content.fetch(&remote.cid, &part).await?;
+    if written != part {
+        std::fs::rename(&written, &part).map_err(|e| format!("Failed to stage {}: {}", written.display(), e))?;
+    }
+
+    // A file of the wrong size isn't worth hashing
+    let size = std::fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    if size != remote.size {
+        let _ = std::fs::remove_file(&part);
+        return Err(format!(
+            "{} failed verification (expected {} bytes, got {})",
+            remote.path, remote.size, size
+        ));
+    }
+    let sha256 = directory::hash_file(&part, io).await?;
+    if sha256 != remote.sha256 {
+        let _ = std::fs::remove_file(&part);
+        return Err(format!(
+            "{} failed verification (expected sha256 {}, got {})",
+            remote.path, remote.sha256, sha256
+        ));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(&part, std::fs::Permissions::from_mode(remote.mode));
+    }
+    std::fs::rename(&part, target).map_err(|e| format!("Failed to move {} into place: {}", target.display(), e))?;
+    let modified_at = std::fs::metadata(target).map(|m| mtime(&m)).unwrap_or(0);
+    Ok(MirroredFile {
+        sha256,
+        size,
+        modified_at,
+        keep_local: false,
+    })
+}
+
+/// Bring a mirror to `cid`. `progress` gets bytes fetched so far and bytes to fetch.
+pub async fn sync_mirror(
+    store: &MirrorStore,
+    content: &ContentControl,
+    io: Option<&IoLimiter>,
+    id: u64,
+    cid: &str,
+    progress: impl Fn(u64, u64),
+) -> Result<MirrorReport, String> {
+    let mirror = store.get(id)?;
+    let manifest = store.manifest(content, cid).await?;
+    let root = PathBuf::from(&mirror.local_path);
+    let now = now_secs();
+    let mut report = MirrorReport::default();
+    let mut files = mirror.files.clone();
+    let mut conflicts: Vec<MirrorConflict> = Vec::new();
+    let conflict = |path: &str, local: Option<String>, remote: Option<&ManifestFile>| MirrorConflict {
+        path: path.to_string(),
+        local_sha256: local,
+        remote_cid: remote.map(|r| r.cid.clone()),
+        remote_sha256: remote.map(|r| r.sha256.clone()),
+        detected_at: now,
+    };
+
+    // Decide per remote file before fetching anything, so progress has a total
+    let mut to_fetch = Vec::new();
+    for remote in &manifest.files {
+        let target = root.join(&remote.path);
+        let known = files.get(&remote.path);
+        let local = local_sha(&target, known, io).await?;
+        match (local, known) {
+            (Some(local), _) if local == remote.sha256 => {
+                report.unchanged += 1;
+                files.insert(
+                    remote.path.clone(),
+                    MirroredFile {
+                        sha256: local,
+                        size: remote.size,
+                        modified_at: std::fs::metadata(&target).map(|m| mtime(&m)).unwrap_or(0),
+                        keep_local: false,
+                    },
+                );
+            }
+            // Local edit the user already chose to keep over this remote version
+            (Some(_), Some(k)) if k.keep_local && k.sha256 == remote.sha256 => report.unchanged += 1,
+            (None, None) => to_fetch.push(remote),
+            (Some(local), Some(k)) if local == k.sha256 && !k.keep_local => to_fetch.push(remote),
+            // Deleted locally and unchanged remotely: leave it deleted
+            (None, Some(k)) if k.sha256 == remote.sha256 => report.unchanged += 1,
+            (local, _) => conflicts.push(conflict(&remote.path, local, Some(remote))),
+        }
+    }
+
+    let bytes_total: u64 = to_fetch.iter().map(|f| f.size).sum();
+    progress(0, bytes_total);
+    let mut errors = Vec::new();
+    for remote in to_fetch {
+        match fetch_verified(content, io, &root.join(&remote.path), remote).await {
+            Ok(written) => {
+                files.insert(remote.path.clone(), written);
+                report.fetched += 1;
+                report.bytes_fetched += remote.size;
+                progress(report.bytes_fetched, bytes_total);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // Files the new version no longer has
+    let gone: Vec<String> = files
+        .keys()
+        .filter(|p| !manifest.files.iter().any(|f| &f.path == *p))
+        .cloned()
+        .collect();
+    for path in gone {
+        let target = root.join(&path);
+        let known = files.get(&path).cloned();
+        match local_sha(&target, known.as_ref(), io).await? {
+            Some(local) if known.as_ref().is_some_and(|k| k.sha256 == local && !k.keep_local) => {
+                std::fs::remove_file(&target).map_err(|e| format!("Failed to delete {}: {}", target.display(), e))?;
+                files.remove(&path);
+                report.deleted += 1;
+            }
+            None => {
+                files.remove(&path);
+            }
+            Some(local) => conflicts.push(conflict(&path, Some(local), None)),
+        }
+    }
+
+    report.conflicts = conflicts.len();
+    let complete = errors.is_empty();
+    store.update(id, |m| {
+        m.files = files;
+        m.conflicts = conflicts;
+        m.last_sync_at = Some(now);
+        m.last_error = errors.first().cloned();
+        if complete {
+            m.manifest_cid = Some(cid.to_string());
+        }
+    })?;
+    if !complete {
+        return Err(format!("{} files failed to sync; first: {}", errors.len(), errors[0]));
+    }
+    Ok(report)
+}
+
+/// Move mirrors to new versions of their directory as content is announced on the bus.
+/// Runs until the bus closes.
+pub async fn follow(
+    store: Arc<MirrorStore>,
+    content: ContentControl,
+    queue: Arc<JobQueue>,
+    mut events: broadcast::Receiver<String>,
+) {
+    let mut checked: HashSet<String> = HashSet::new();
+    loop {
+        let notification = match events.recv().await {
+            Ok(notification) => notification,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let Ok(v) = serde_json::from_str::<Value>(&notification) else { continue };
+        let method = v.get("method").and_then(|m| m.as_str()).unwrap_or_default();
+        if !ANNOUNCEMENTS.contains(&method) {
+            continue;
+        }
+        let Some(cid) = v.pointer("/params/content_id").and_then(|c| c.as_str()) else { continue };
+        if checked.contains(cid) || store.list().is_empty() {
+            continue;
+        }
+        if checked.len() >= MAX_CHECKED {
+            checked.clear();
+        }
+        checked.insert(cid.to_string());
+
+        // Only small content can be a manifest; don't fetch anything larger to find out
+        let size = match ["total_size", "size"].iter().find_map(|k| v.pointer(&format!("/params/{}", k))) {
+            Some(size) => size.as_u64(),
+            None => content
+                .list()
+                .await
+                .ok()
+                .and_then(|catalog| catalog.into_iter().find(|e| e.content_id == cid))
+                .map(|e| e.total_size),
+        };
+        match size {
+            Some(size) if size <= MAX_MANIFEST_BYTES => {}
+            _ => continue,
+        }
+        if store.manifest(&content, cid).await.is_err() {
+            continue;
+        }
+        for mirror in store.list() {
+            if mirror.target_cid == cid || store.check_lineage(&content, &mirror.target_cid, cid).await.is_err() {
+                continue;
+            }
+            let queued = store
+                .update(mirror.id, |m| m.target_cid = cid.to_string())
+                .and_then(|_| queue.enqueue(JobKind::Mirror { mirror: mirror.id, cid: cid.to_string() }));
+            match queued {
+                Ok(_) => info!("Mirror {} following new version {}", mirror.id, cid),
+                Err(e) => warn!("Mirror {} could not follow {}: {}", mirror.id, cid, e),
+            }
+        }
+    }
+}
+
+impl DaemonManager {
+    pub fn list_mirrors(&self, pid: u32) -> Result<Vec<Mirror>, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.mirrors))?.list())
+    }
+
+    /// Start mirroring a directory manifest into `local_path`; the first sync is queued.
+    pub fn add_mirror(&self, pid: u32, cid: &str, local_path: &str) -> Result<Job, String> {
+        let mirror = self.with_daemon(pid, |d| Arc::clone(&d.mirrors))?.add(cid, local_path)?;
+        info!("Instance {} mirroring {} into {}", pid, cid, local_path);
+        self.enqueue_job(
+            pid,
+            JobKind::Mirror {
+                mirror: mirror.id,
+                cid: cid.to_string(),
+            },
+        )
+    }
+
+    /// Move a mirror to a newer version of its directory and queue the sync.
+    pub async fn update_mirror(&self, pid: u32, id: u64, cid: &str, force: bool) -> Result<Job, String> {
+        let store = self.with_daemon(pid, |d| Arc::clone(&d.mirrors))?;
+        let mirror = store.get(id)?;
+        if let (Some(current), false) = (&mirror.manifest_cid, force) {
+            let (ipc, _, _) = self.running_ipc(pid)?;
+            store.check_lineage(&ContentControl::new(ipc.handler), current, cid).await?;
+        }
+        store.update(id, |m| m.target_cid = cid.to_string())?;
+        self.enqueue_job(
+            pid,
+            JobKind::Mirror {
+                mirror: id,
+                cid: cid.to_string(),
+            },
+        )
+    }
+
+    pub fn remove_mirror(&self, pid: u32, id: u64) -> Result<(), String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.mirrors))?.remove(id)
+    }
+
+    /// Settle a conflict. Keeping the local copy holds it until the remote file changes
+    /// again; taking the remote one overwrites the local copy on the queued sync.
+    pub fn resolve_mirror_conflict(&self, pid: u32, id: u64, path: &str, keep_local: bool) -> Result<Option<Job>, String> {
+        let store = self.with_daemon(pid, |d| Arc::clone(&d.mirrors))?;
+        let target_cid = store.update(id, |m| {
+            m.settle_conflict(path, keep_local)?;
+            Ok::<_, String>(m.target_cid.clone())
+        })??;
+        if keep_local {
+            return Ok(None);
+        }
+        self.enqueue_job(pid, JobKind::Mirror { mirror: id, cid: target_cid }).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror(local_path: &Path) -> Mirror {
+        Mirror {
+            id: 1,
+            local_path: local_path.to_string_lossy().to_string(),
+            manifest_cid: Some("v1".to_string()),
+            target_cid: "v2".to_string(),
+            files: BTreeMap::new(),
+            conflicts: Vec::new(),
+            last_sync_at: None,
+            last_error: None,
+        }
+    }
+
+    fn conflict(path: &str, local: Option<&str>, remote: Option<&str>) -> MirrorConflict {
+        MirrorConflict {
+            path: path.to_string(),
+            local_sha256: local.map(str::to_string),
+            remote_cid: remote.map(|_| "remote-cid".to_string()),
+            remote_sha256: remote.map(str::to_string),
+            detected_at: 0,
+        }
+    }
+
+    fn recorded(sha256: &str) -> MirroredFile {
+        MirroredFile {
+            sha256: sha256.to_string(),
+            size: 1,
+            modified_at: 0,
+            keep_local: false,
+        }
+    }
+
+    #[test]
+    fn taking_remote_after_a_local_delete_fetches_it_again() {
+        let root = std::env::temp_dir().join(format!("craftstudio-mirror-deleted-{}", std::process::id()));
+        let mut m = mirror(&root);
+        m.files.insert("a.txt".to_string(), recorded("old"));
+        m.conflicts.push(conflict("a.txt", None, Some("new")));
+
+        m.settle_conflict("a.txt", false).unwrap();
+        // No record and no local file is the (None, None) case the sync fetches
+        assert!(m.conflicts.is_empty());
+        assert!(!m.files.contains_key("a.txt"));
+    }
+
+    #[test]
+    fn taking_remote_over_a_local_edit_marks_the_edit_as_replaceable() {
+        let root = std::env::temp_dir().join(format!("craftstudio-mirror-edited-{}", std::process::id()));
+        let mut m = mirror(&root);
+        m.conflicts.push(conflict("a.txt", Some("edited"), Some("new")));
+
+        m.settle_conflict("a.txt", false).unwrap();
+        let file = &m.files["a.txt"];
+        assert_eq!((file.sha256.as_str(), file.keep_local), ("edited", false));
+    }
+
+    #[test]
+    fn keeping_local_holds_until_the_remote_changes() {
+        let root = std::env::temp_dir().join(format!("craftstudio-mirror-kept-{}", std::process::id()));
+        let mut m = mirror(&root);
+        m.conflicts.push(conflict("a.txt", Some("edited"), Some("new")));
+        m.conflicts.push(conflict("b.txt", Some("edited"), None));
+        m.files.insert("b.txt".to_string(), recorded("old"));
+
+        m.settle_conflict("a.txt", true).unwrap();
+        m.settle_conflict("b.txt", true).unwrap();
+        let file = &m.files["a.txt"];
+        assert_eq!((file.sha256.as_str(), file.keep_local), ("new", true));
+        assert!(!m.files.contains_key("b.txt"));
+        assert!(m.settle_conflict("a.txt", true).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Read-only FUSE mount of an instance's content (Linux).
+//!
+//! The mount has two top-level directories:
+//! - `/catalog`: one file per entry of the content catalog (`list`), named after the content
+//!   when its name is unique and by content ID otherwise.
+//! - `/vfs`: the instance's virtual filesystem.
+//!
+//! Nothing is fetched up front. A file's content is fetched through the instance's handler
+//! when it is first opened and kept in the instance's `ContentCache`. Later reads, and
+//! other paths with the same content, are served from the cache. CraftOBJ only fetches
+//! whole objects, so opens and reads are answered from tasks on the instance runtime
+//! rather than the FUSE session thread, which keeps serving other operations on the mount
+//! while a large object is fetched. Listing a directory
+//! rebuilds the tree once it is a few seconds old. The mount is released, which unmounts
+//! it, when the instance stops.
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tracing::info;
+
+use crate::api_keys::now_secs;
+use crate::content_ctl::{ContentCache, ContentControl};
+use crate::daemon_manager::DaemonManager;
+use crate::vfs::{EntryKind, Vfs};
+
+/// Age after which listing a directory rebuilds the tree.
+const REFRESH_AFTER: Duration = Duration::from_secs(5);
+const ROOT_INO: u64 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MountInfo {
+    pub mountpoint: String,
+    pub mounted_at: u64,
+}
+
+/// A live mount; dropping it unmounts.
+pub struct Mount {
+    pub info: MountInfo,
+    #[cfg(target_os = "linux")]
+    _session: fuser::BackgroundSession,
+}
+
+/// Where the mounted files come from.
+struct Source {
+    cache: ContentCache,
+    vfs: Arc<Vfs>,
+    rt: Handle,
+}
+
+enum NodeKind {
+    Dir(Vec<u64>),
+    File { cid: String, mode: u32 },
+}
+
+struct Node {
+    name: String,
+    path: String,
+    size: u64,
+    modified_at: u64,
+    kind: NodeKind,
+}
+
+/// Snapshot of the mounted tree. Inode numbers are keyed by path so they stay the same
+/// across rebuilds.
+struct Tree {
+    nodes: HashMap<u64, Node>,
+    inodes: HashMap<String, u64>,
+    next_ino: u64,
+    built: Option<Instant>,
+}
+
+impl Tree {
+    fn new() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            inodes: HashMap::from([("/".to_string(), ROOT_INO)]),
+            next_ino: ROOT_INO + 1,
+            built: None,
+        }
+    }
+
+    fn stale(&self) -> bool {
+        self.built.map_or(true, |at| at.elapsed() >= REFRESH_AFTER)
+    }
+
+    fn build(&mut self, src: &Source) -> Result<(), String> {
+        let catalog = src.rt.block_on(src.cache.content().list())?;
+        let now = now_secs();
+        self.nodes.clear();
+        self.nodes.insert(ROOT_INO, dir_node("", "/", now));
+
+        let catalog_ino = self.add(ROOT_INO, "catalog", 0, now, NodeKind::Dir(Vec::new()));
+        let mut name_counts: HashMap<String, usize> = HashMap::new();
+        for entry in &catalog {
+            if let Some(name) = entry.name.as_deref().map(file_name) {
+                *name_counts.entry(name).or_default() += 1;
+            }
+        }
+        for entry in catalog {
+            let name = entry
+                .name
+                .as_deref()
+                .map(file_name)
+                .filter(|n| !n.is_empty() && name_counts.get(n) == Some(&1))
+                .unwrap_or_else(|| entry.content_id.clone());
+            let kind = NodeKind::File {
+                cid: entry.content_id,
+                mode: 0o444,
+            };
+            self.add(catalog_ino, &name, entry.total_size, now, kind);
+        }
+
+        let vfs_ino = self.add(ROOT_INO, "vfs", 0, now, NodeKind::Dir(Vec::new()));
+        self.add_vfs_dir(src, vfs_ino, "/")?;
+        self.built = Some(Instant::now());
+        Ok(())
+    }
+
+    fn add_vfs_dir(&mut self, src: &Source, ino: u64, dir: &str) -> Result<(), String> {
+        for entry in src.vfs.list(dir)? {
+            match (entry.kind, entry.cid) {
+                (EntryKind::Directory, _) => {
+                    let child = self.add(ino, &entry.name, entry.size, entry.modified_at, NodeKind::Dir(Vec::new()));
+                    self.add_vfs_dir(src, child, &entry.path)?;
+                }
+                (EntryKind::File, Some(cid)) => {
+                    let kind = NodeKind::File {
+                        cid,
+                        mode: entry.mode & 0o555,
+                    };
+                    self.add(ino, &entry.name, entry.size, entry.modified_at, kind);
+                }
+                (EntryKind::File, None) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, parent: u64, name: &str, size: u64, modified_at: u64, kind: NodeKind) -> u64 {
+        let parent_path = self.nodes[&parent].path.clone();
+        let path = if parent_path == "/" {
+            format!("/{}", name)
+        } else {
+            format!("{}/{}", parent_path, name)
+        };
+        let ino = match self.inodes.get(&path) {
+            Some(&ino) => ino,
+            None => {
+                let ino = self.next_ino;
+                self.next_ino += 1;
+                self.inodes.insert(path.clone(), ino);
+                ino
+            }
+        };
+        self.nodes.insert(
+            ino,
+            Node {
+                name: name.to_string(),
+                path,
+                size,
+                modified_at,
+                kind,
+            },
+        );
+        if let Some(Node { kind: NodeKind::Dir(children), .. }) = self.nodes.get_mut(&parent) {
+            children.push(ino);
+        }
+        ino
+    }
+
+    fn child(&self, parent: u64, name: &str) -> Option<u64> {
+        match &self.nodes.get(&parent)?.kind {
+            NodeKind::Dir(children) => children.iter().copied().find(|c| self.nodes[c].name == name),
+            NodeKind::File { .. } => None,
+        }
+    }
+}
+
+fn dir_node(name: &str, path: &str, modified_at: u64) -> Node {
+    Node {
+        name: name.to_string(),
+        path: path.to_string(),
+        size: 0,
+        modified_at,
+        kind: NodeKind::Dir(Vec::new()),
+    }
+}
+
+/// Content names may contain anything; keep them to one path component.
+fn file_name(name: &str) -> String {
+    name.replace(['/', '\0'], "_")
+}
+
+#[cfg(target_os = "linux")]
+mod fuse {
+    use std::ffi::OsStr;
+    use std::os::unix::fs::FileExt;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use fuser::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen, Request};
+    use tracing::warn;
+
+    use super::{Node, NodeKind, Source, Tree, ROOT_INO};
+
+    const TTL: Duration = Duration::from_secs(1);
+
+    pub(super) struct ContentFs {
+        pub tree: Tree,
+        pub src: Source,
+        pub uid: u32,
+        pub gid: u32,
+    }
+
+    impl ContentFs {
+        fn refresh(&mut self) {
+            if self.tree.stale() {
+                if let Err(e) = self.tree.build(&self.src) {
+                    warn!("Mount refresh failed: {}", e);
+                }
+            }
+        }
+
+        fn attr(&self, ino: u64, node: &Node) -> FileAttr {
+            let time = UNIX_EPOCH + Duration::from_secs(node.modified_at);
+            let (kind, perm, nlink) = match &node.kind {
+                NodeKind::Dir(_) => (FileType::Directory, 0o555, 2),
+                NodeKind::File { mode, .. } => (FileType::RegularFile, *mode as u16, 1),
+            };
+            FileAttr {
+                ino,
+                size: node.size,
+                blocks: node.size.div_ceil(512),
+                atime: time,
+                mtime: time,
+                ctime: time,
+                crtime: time,
+                kind,
+                perm,
+                nlink,
+                uid: self.uid,
+                gid: self.gid,
+                rdev: 0,
+                blksize: 4096,
+                flags: 0,
+            }
+        }
+    }
+
+    impl Filesystem for ContentFs {
+        fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+            let found = name.to_str().and_then(|name| self.tree.child(parent, name));
+            match found {
+                Some(ino) => reply.entry(&TTL, &self.attr(ino, &self.tree.nodes[&ino]), 0),
+                None => reply.error(libc::ENOENT),
+            }
+        }
+
+        fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+            match self.tree.nodes.get(&ino) {
+                Some(node) => reply.attr(&TTL, &self.attr(ino, node)),
+                None => reply.error(libc::ENOENT),
+            }
+        }
+
+        fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+            if flags & libc::O_ACCMODE != libc::O_RDONLY {
+                return reply.error(libc::EROFS);
+            }
+            let Some(Node { kind: NodeKind::File { cid, .. }, .. }) = self.tree.nodes.get(&ino) else {
+                return reply.error(libc::EISDIR);
+            };
+            let (cache, cid) = (self.src.cache.clone(), cid.clone());
+            self.src.rt.spawn(async move {
+                match cache.get(&cid).await {
+                    Ok(_) => reply.opened(0, 0),
+                    Err(e) => {
+                        warn!("Mount failed to fetch {}: {}", cid, e);
+                        reply.error(libc::EIO)
+                    }
+                }
+            });
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn read(
+            &mut self,
+            _req: &Request<'_>,
+            ino: u64,
+            _fh: u64,
+            offset: i64,
+            size: u32,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            reply: ReplyData,
+        ) {
+            let Some(Node { kind: NodeKind::File { cid, .. }, .. }) = self.tree.nodes.get(&ino) else {
+                return reply.error(libc::EISDIR);
+            };
+            // The cached copy may have been evicted since `open`, so this can fetch too
+            let (cache, cid) = (self.src.cache.clone(), cid.clone());
+            self.src.rt.spawn(async move {
+                let read = match cache.get(&cid).await {
+                    Ok(path) => tokio::task::spawn_blocking(move || -> Result<Vec<u8>, String> {
+                        let file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
+                        let mut buf = vec![0u8; size as usize];
+                        let n = file.read_at(&mut buf, offset.max(0) as u64).map_err(|e| e.to_string())?;
+                        buf.truncate(n);
+                        Ok(buf)
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string())),
+                    Err(e) => Err(e),
+                };
+                match read {
+                    Ok(buf) => reply.data(&buf),
+                    Err(e) => {
+                        warn!("Mount read of {} failed: {}", cid, e);
+                        reply.error(libc::EIO)
+                    }
+                }
+            });
+        }
+
+        fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+            if offset == 0 {
+                self.refresh();
+            }
+            let children = match self.tree.nodes.get(&ino) {
+                Some(Node { kind: NodeKind::Dir(children), .. }) => children,
+                Some(_) => return reply.error(libc::ENOTDIR),
+                None => return reply.error(libc::ENOENT),
+            };
+            let parent = self.tree.inodes.get(parent_path(&self.tree.nodes[&ino].path)).copied().unwrap_or(ROOT_INO);
+            let mut entries = vec![(ino, FileType::Directory, "."), (parent, FileType::Directory, "..")];
+            for child in children {
+                let node = &self.tree.nodes[child];
+                let kind = match node.kind {
+                    NodeKind::Dir(_) => FileType::Directory,
+                    NodeKind::File { .. } => FileType::RegularFile,
+                };
+                entries.push((*child, kind, node.name.as_str()));
+            }
+            for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(offset.max(0) as usize) {
+                if reply.add(ino, (i + 1) as i64, kind, name) {
+                    break;
+                }
+            }
+            reply.ok();
+        }
+    }
+
+    fn parent_path(path: &str) -> &str {
+        match path.rfind('/') {
+            Some(0) | None => "/",
+            Some(i) => &path[..i],
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn mount(src: Source, mountpoint: &Path) -> Result<Mount, String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = std::fs::metadata(mountpoint).map_err(|e| format!("Cannot mount on {}: {}", mountpoint.display(), e))?;
+    if !meta.is_dir() {
+        return Err(format!("Mount point {} is not a directory", mountpoint.display()));
+    }
+    let mut tree = Tree::new();
+    tree.build(&src)?;
+    let fs = fuse::ContentFs {
+        tree,
+        src,
+        uid: meta.uid(),
+        gid: meta.gid(),
+    };
+    let options = [
+        fuser::MountOption::RO,
+        fuser::MountOption::FSName("craftobj".to_string()),
+        fuser::MountOption::Subtype("craftstudio".to_string()),
+    ];
+    let session = fuser::spawn_mount2(fs, mountpoint, &options)
+        .map_err(|e| format!("Failed to mount {}: {}", mountpoint.display(), e))?;
+    Ok(Mount {
+        info: MountInfo {
+            mountpoint: mountpoint.display().to_string(),
+            mounted_at: now_secs(),
+        },
+        _session: session,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount(_src: Source, _mountpoint: &Path) -> Result<Mount, String> {
+    Err("Mounting instance content is only supported on Linux".to_string())
+}
+
+impl DaemonManager {
+    /// Mount an instance's catalog and VFS read-only at `mountpoint`.
+    pub async fn mount_instance(&self, pid: u32, mountpoint: &str) -> Result<MountInfo, String> {
+        let slot = self.with_daemon(pid, |d| Arc::clone(&d.mount))?;
+        if let Some(existing) = slot.lock().unwrap().as_ref() {
+            return Err(format!("Instance {} is already mounted at {}", pid, existing.info.mountpoint));
+        }
+        let (ipc, _, instance) = self.running_ipc(pid)?;
+        let (vfs, rt) = self.with_daemon(pid, |d| (Arc::clone(&d.vfs), d.rt.clone()))?;
+        let src = Source {
+            cache: ContentCache::new(ContentControl::new(ipc.handler), Path::new(&instance.data_dir)),
+            vfs,
+            rt,
+        };
+        // Building the tree blocks on the instance runtime
+        let target = PathBuf::from(mountpoint);
+        let mounted = tokio::task::spawn_blocking(move || mount(src, &target))
+            .await
+            .map_err(|e| format!("Mount task failed: {}", e))??;
+        let info = mounted.info.clone();
+        *slot.lock().unwrap() = Some(mounted);
+        info!("Instance {} mounted at {}", pid, mountpoint);
+        Ok(info)
+    }
+
+    pub fn unmount_instance(&self, pid: u32) -> Result<(), String> {
+        let mounted = self
+            .with_daemon(pid, |d| Arc::clone(&d.mount))?
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| format!("Instance {} is not mounted", pid))?;
+        info!("Instance {} unmounted from {}", pid, mounted.info.mountpoint);
+        Ok(())
+    }
+
+    pub fn mount_info(&self, pid: u32) -> Result<Option<MountInfo>, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.mount))?.lock().unwrap().as_ref().map(|m| m.info.clone()))
+    }
+}
@@ -0,0 +1,78 @@
+//! NAT traversal options and reachability reporting.
+//!
+//! Each instance can turn on AutoNAT reachability detection, the circuit-relay client
+//! and/or server, DCUtR hole punching and UPnP/NAT-PMP port mapping. The options map onto
+//! the matching `craftec_network::NetworkConfig` switches. What the swarm detects, meaning
+//! reachability and confirmed external addresses, is sampled through `SwarmControl` and
+//! reported in `DaemonInstance`.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use craftec_network::NetworkConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::swarm_ctl::SwarmControl;
+
+const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NatOptions {
+    /// Probe reachability through other peers
+    #[serde(default)]
+    pub autonat: bool,
+    /// Reserve slots on relays so peers can reach us through them
+    #[serde(default)]
+    pub relay_client: bool,
+    /// Offer relay slots to other peers (needs public reachability to be useful)
+    #[serde(default)]
+    pub relay_server: bool,
+    /// Upgrade relayed connections to direct ones (DCUtR)
+    #[serde(default)]
+    pub hole_punching: bool,
+    /// Ask the router for a port mapping (UPnP / NAT-PMP)
+    #[serde(default)]
+    pub port_mapping: bool,
+}
+
+impl NatOptions {
+    pub fn apply(&self, config: &mut NetworkConfig) -> Result<(), String> {
+        if self.hole_punching && !self.relay_client {
+            return Err("Hole punching needs relay_client: DCUtR upgrades relayed connections".to_string());
+        }
+        config.enable_autonat = self.autonat;
+        config.enable_relay_client = self.relay_client;
+        config.enable_relay_server = self.relay_server;
+        config.enable_dcutr = self.hole_punching;
+        config.enable_upnp = self.port_mapping;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    #[default]
+    Unknown,
+    Public,
+    Private,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NatStatus {
+    pub reachability: Reachability,
+    pub external_addrs: Vec<String>,
+}
+
+pub type SharedNatStatus = Arc<Mutex<NatStatus>>;
+
+/// Sample the swarm's NAT status until aborted.
+pub async fn watch(swarm: SwarmControl, status: SharedNatStatus) {
+    let mut interval = tokio::time::interval(STATUS_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Ok(current) = swarm.nat_status().await {
+            *status.lock().unwrap() = current;
+        }
+    }
+}
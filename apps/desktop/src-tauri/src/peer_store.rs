@@ -0,0 +1,400 @@
+//! Per-instance peer address book and connection manager.
+//!
+//! `PeerStore` keeps what an instance has learned about other peers in
+//! `{data_dir}/peers.json`: addresses, when they were last seen, dial latency, failure
+//! counts, advertised protocols, and whether the user pinned or banned them. It is fed from
+//! the instance event bus (`peer_discovered`, `peer_connected`, `peer_disconnected`,
+//! `peer_heartbeat_timeout`) and from dials made through it.
+//!
+//! `maintain` runs next to each instance. It enforces the `ConnectionPolicy`: above
+//! `max_connections` it closes the least useful unpinned connections, and below
+//! `min_connections` it dials known peers, pinned ones first. It also keeps pinned peers
+//! connected, re-applies bans after a restart and flushes the book to disk.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::api_keys::now_secs;
+use crate::daemon_manager::DaemonManager;
+use crate::state_file;
+use crate::swarm_ctl::SwarmControl;
+
+const MAINTAIN_INTERVAL: Duration = Duration::from_secs(15);
+/// Addresses remembered per peer, most recent first.
+const MAX_ADDRS_PER_PEER: usize = 8;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub peer_id: String,
+    /// Multiaddrs without the trailing `/p2p/<peer_id>`
+    #[serde(default)]
+    pub addrs: Vec<String>,
+    pub last_seen: Option<u64>,
+    /// Time to establish the last connection we dialed
+    pub latency_ms: Option<u64>,
+    #[serde(default)]
+    pub successes: u32,
+    #[serde(default)]
+    pub failures: u32,
+    #[serde(default)]
+    pub protocols: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub banned: bool,
+    /// Live state; not meaningful across restarts
+    #[serde(skip_deserializing)]
+    pub connected: bool,
+}
+
+impl PeerRecord {
+    /// Ordering key for which peers to keep or dial first; higher is better.
+    fn score(&self) -> i64 {
+        let pinned = if self.pinned { 1_000_000 } else { 0 };
+        pinned + self.successes as i64 * 10 - self.failures as i64 * 20 - self.latency_ms.unwrap_or(1_000) as i64 / 100
+    }
+
+    fn dial_addrs(&self) -> Vec<String> {
+        self.addrs.iter().map(|a| format!("{}/p2p/{}", a, self.peer_id)).collect()
+    }
+
+    fn remember_addr(&mut self, addr: &str) {
+        let addr = strip_p2p(addr);
+        if addr.is_empty() {
+            return;
+        }
+        self.addrs.retain(|a| a != addr);
+        self.addrs.insert(0, addr.to_string());
+        self.addrs.truncate(MAX_ADDRS_PER_PEER);
+    }
+}
+
+/// Connection count bounds; `None` leaves that side to the swarm.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectionPolicy {
+    pub max_connections: Option<u32>,
+    pub min_connections: Option<u32>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PeerBook {
+    #[serde(default)]
+    peers: HashMap<String, PeerRecord>,
+    #[serde(default)]
+    policy: ConnectionPolicy,
+}
+
+pub struct PeerStore {
+    path: PathBuf,
+    book: Mutex<PeerBook>,
+    dirty: Mutex<bool>,
+}
+
+impl PeerStore {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join("peers.json");
+        let book = state_file::load(&path);
+        Self {
+            path,
+            book: Mutex::new(book),
+            dirty: Mutex::new(false),
+        }
+    }
+
+    pub fn list(&self) -> Vec<PeerRecord> {
+        let book = self.book.lock().unwrap();
+        let mut peers: Vec<PeerRecord> = book.peers.values().cloned().collect();
+        peers.sort_by_key(|p| std::cmp::Reverse(p.score()));
+        peers
+    }
+
+    /// Rough in-memory size of the book, for per-instance accounting.
+    pub fn approx_bytes(&self) -> u64 {
+        let book = self.book.lock().unwrap();
+        book.peers
+            .values()
+            .map(|p| {
+                let strings: usize = p.addrs.iter().chain(&p.protocols).map(String::len).sum();
+                (std::mem::size_of::<PeerRecord>() + p.peer_id.len() * 2 + strings) as u64
+            })
+            .sum()
+    }
+
+    pub fn get(&self, peer_id: &str) -> Option<PeerRecord> {
+        self.book.lock().unwrap().peers.get(peer_id).cloned()
+    }
+
+    pub fn policy(&self) -> ConnectionPolicy {
+        self.book.lock().unwrap().policy.clone()
+    }
+
+    pub fn set_policy(&self, policy: ConnectionPolicy) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (policy.min_connections, policy.max_connections) {
+            if min > max {
+                return Err(format!("min_connections ({}) is above max_connections ({})", min, max));
+            }
+        }
+        self.update(|book| book.policy = policy);
+        self.flush()
+    }
+
+    pub fn set_pinned(&self, peer_id: &str, pinned: bool) -> Result<(), String> {
+        self.update(|book| entry(book, peer_id).pinned = pinned);
+        self.flush()
+    }
+
+    pub fn set_banned(&self, peer_id: &str, banned: bool) -> Result<(), String> {
+        self.update(|book| entry(book, peer_id).banned = banned);
+        self.flush()
+    }
+
+    /// Record the outcome of a dial we made.
+    pub fn record_dial(&self, peer_id: &str, addr: Option<&str>, result: Result<Duration, ()>) {
+        self.update(|book| {
+            let peer = entry(book, peer_id);
+            match result {
+                Ok(latency) => {
+                    peer.successes += 1;
+                    peer.latency_ms = Some(latency.as_millis() as u64);
+                    peer.last_seen = Some(now_secs());
+                    if let Some(addr) = addr {
+                        peer.remember_addr(addr);
+                    }
+                }
+                Err(()) => peer.failures += 1,
+            }
+        });
+    }
+
+    /// Update the book from one bus notification.
+    pub fn observe(&self, notification: &str) {
+        let Ok(v) = serde_json::from_str::<Value>(notification) else {
+            return;
+        };
+        let Some(method) = v.get("method").and_then(|m| m.as_str()) else {
+            return;
+        };
+        if !matches!(method, "peer_discovered" | "peer_connected" | "peer_disconnected" | "peer_heartbeat_timeout") {
+            return;
+        }
+        let params = v.get("params").cloned().unwrap_or(Value::Null);
+        let Some(peer_id) = params.get("peer_id").and_then(|p| p.as_str()) else {
+            return;
+        };
+        let addr = params.get("address").and_then(|a| a.as_str());
+        let protocols: Option<Vec<String>> = params
+            .get("protocols")
+            .and_then(|p| p.as_array())
+            .map(|p| p.iter().filter_map(|s| s.as_str().map(str::to_string)).collect());
+
+        self.update(|book| {
+            let peer = entry(book, peer_id);
+            match method {
+                "peer_connected" => {
+                    peer.connected = true;
+                    peer.successes += 1;
+                }
+                "peer_disconnected" => peer.connected = false,
+                "peer_heartbeat_timeout" => {
+                    peer.connected = false;
+                    peer.failures += 1;
+                }
+                _ => {}
+            }
+            peer.last_seen = Some(now_secs());
+            if let Some(addr) = addr {
+                peer.remember_addr(addr);
+            }
+            if let Some(protocols) = protocols {
+                peer.protocols = protocols;
+            }
+        });
+    }
+
+    /// Write the book if anything changed since the last flush.
+    pub fn flush(&self) -> Result<(), String> {
+        let mut dirty = self.dirty.lock().unwrap();
+        if !*dirty {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(&*self.book.lock().unwrap())
+            .map_err(|e| format!("Failed to serialize peer store: {}", e))?;
+        state_file::write(&self.path, json.as_bytes())?;
+        *dirty = false;
+        Ok(())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut PeerBook)) {
+        f(&mut self.book.lock().unwrap());
+        *self.dirty.lock().unwrap() = true;
+    }
+}
+
+fn entry<'a>(book: &'a mut PeerBook, peer_id: &str) -> &'a mut PeerRecord {
+    book.peers.entry(peer_id.to_string()).or_insert_with(|| PeerRecord {
+        peer_id: peer_id.to_string(),
+        ..Default::default()
+    })
+}
+
+fn strip_p2p(addr: &str) -> &str {
+    match addr.find("/p2p/") {
+        Some(i) => &addr[..i],
+        None => addr,
+    }
+}
+
+impl DaemonManager {
+    pub fn list_peers(&self, pid: u32) -> Result<Vec<PeerRecord>, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.peers))?.list())
+    }
+
+    /// Pinned peers are redialed whenever they drop and never closed by the connection limit.
+    pub fn pin_peer(&self, pid: u32, peer_id: &str, pinned: bool) -> Result<(), String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.peers))?.set_pinned(peer_id, pinned)
+    }
+
+    /// Ban or unban a peer on the live swarm and remember it across restarts.
+    pub async fn ban_peer(&self, pid: u32, peer_id: &str, banned: bool) -> Result<(), String> {
+        let store = self.with_daemon(pid, |d| Arc::clone(&d.peers))?;
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        let swarm = SwarmControl::new(ipc.handler);
+        if banned {
+            swarm.ban(peer_id).await?;
+            swarm.disconnect(peer_id).await?;
+        } else {
+            swarm.unban(peer_id).await?;
+        }
+        store.set_banned(peer_id, banned)?;
+        info!("Instance {} {} peer {}", pid, if banned { "banned" } else { "unbanned" }, peer_id);
+        Ok(())
+    }
+
+    pub async fn dial_peer(&self, pid: u32, peer_id: &str) -> Result<(), String> {
+        let store = self.with_daemon(pid, |d| Arc::clone(&d.peers))?;
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        dial(&store, &SwarmControl::new(ipc.handler), peer_id).await
+    }
+
+    pub fn get_connection_policy(&self, pid: u32) -> Result<ConnectionPolicy, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.peers))?.policy())
+    }
+
+    /// Takes effect on the next maintenance pass.
+    pub fn set_connection_policy(&self, pid: u32, policy: ConnectionPolicy) -> Result<(), String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.peers))?.set_policy(policy)
+    }
+}
+
+/// Dial a known peer through its remembered addresses and record the outcome.
+pub async fn dial(store: &PeerStore, swarm: &SwarmControl, peer_id: &str) -> Result<(), String> {
+    let peer = store
+        .get(peer_id)
+        .ok_or_else(|| format!("Peer {} is not in the address book", peer_id))?;
+    if peer.banned {
+        return Err(format!("Peer {} is banned", peer_id));
+    }
+    if peer.addrs.is_empty() {
+        return Err(format!("No known addresses for peer {}", peer_id));
+    }
+    let mut last_err = String::new();
+    for addr in peer.dial_addrs() {
+        let started = Instant::now();
+        match swarm.dial(&addr).await {
+            Ok(_) => {
+                store.record_dial(peer_id, Some(&addr), Ok(started.elapsed()));
+                return Ok(());
+            }
+            Err(e) => last_err = e,
+        }
+    }
+    store.record_dial(peer_id, None, Err(()));
+    Err(format!("Failed to dial {}: {}", peer_id, last_err))
+}
+
+/// Feed the store from the bus and enforce its policy until the bus closes.
+pub async fn maintain(
+    store: Arc<PeerStore>,
+    mut events: tokio::sync::broadcast::Receiver<String>,
+    swarm: SwarmControl,
+) {
+    for peer in store.list().iter().filter(|p| p.banned) {
+        if let Err(e) = swarm.ban(&peer.peer_id).await {
+            warn!("Failed to re-apply ban on {}: {}", peer.peer_id, e);
+        }
+    }
+
+    let mut tick = tokio::time::interval(MAINTAIN_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(notification) => store.observe(&notification),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+            _ = tick.tick() => {
+                enforce_policy(&store, &swarm).await;
+                if let Err(e) = store.flush() {
+                    warn!("{}", e);
+                }
+            }
+        }
+    }
+    let _ = store.flush();
+}
+
+async fn enforce_policy(store: &PeerStore, swarm: &SwarmControl) {
+    let Ok(connected) = swarm.connected_peers().await else {
+        return;
+    };
+    let policy = store.policy();
+    let peers = store.list();
+
+    if let Some(max) = policy.max_connections {
+        let excess = connected.len().saturating_sub(max as usize);
+        // `peers` is sorted best-first, so trim from the back; unknown peers go first
+        let mut victims: Vec<&str> = connected
+            .iter()
+            .filter(|id| !peers.iter().any(|p| &p.peer_id == *id))
+            .map(String::as_str)
+            .collect();
+        victims.extend(
+            peers
+                .iter()
+                .rev()
+                .filter(|p| !p.pinned && connected.contains(&p.peer_id))
+                .map(|p| p.peer_id.as_str()),
+        );
+        for peer_id in victims.into_iter().take(excess) {
+            info!("Connection limit {}: disconnecting {}", max, peer_id);
+            let _ = swarm.disconnect(peer_id).await;
+        }
+    }
+
+    let mut wanted: HashSet<&str> = peers
+        .iter()
+        .filter(|p| p.pinned && !p.banned && !connected.contains(&p.peer_id))
+        .map(|p| p.peer_id.as_str())
+        .collect();
+    if let Some(min) = policy.min_connections {
+        let missing = (min as usize).saturating_sub(connected.len() + wanted.len());
+        wanted.extend(
+            peers
+                .iter()
+                .filter(|p| !p.pinned && !p.banned && !p.addrs.is_empty() && !connected.contains(&p.peer_id))
+                .take(missing)
+                .map(|p| p.peer_id.as_str()),
+        );
+    }
+    for peer_id in wanted {
+        if let Err(e) = dial(store, swarm, peer_id).await {
+            warn!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Moving an instance's data dir.
+//!
+//! `move_instance_data` stops the instance, moves its data dir and starts it again from the
+//! new place with the config it was started with. The instance comes back under a new
+//! instance ID. A rename is used when the target is on the same filesystem. Otherwise every
+//! file is copied and hashed on both sides, and the old dir is removed only once all of
+//! them match. Paths in config.json that point into the old dir (`storage_path`,
+//! `keypair_path`, `socket_path`) are rewritten, and instance references in the app config
+//! follow the move. The instance is stopped and its tasks have ended before anything is
+//! moved. If the move fails, the instance is started again where it was; once the data has
+//! moved, it is started from the new place whatever fails afterwards.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::config;
+use crate::daemon_manager::{DaemonConfig, DaemonInstance, DaemonManager};
+use crate::storage;
+
+/// Keys of config.json that hold paths which may point into the data dir.
+const CONFIG_PATH_KEYS: &[&str] = &["storage_path", "keypair_path", "socket_path"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MovedInstance {
+    /// The instance as restarted from the new location
+    pub instance: DaemonInstance,
+    pub old_path: String,
+    pub new_path: String,
+    /// `false` when the data was copied and verified instead
+    pub renamed: bool,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// `path` with the `old` prefix replaced by `new`, if it is under `old`.
+fn rebase(path: &str, old: &Path, new: &Path) -> Option<String> {
+    let rest = Path::new(path).strip_prefix(old).ok()?;
+    Some(new.join(rest).display().to_string())
+}
+
+/// Config to start the instance again with its data dir at `data_dir`.
+pub(crate) fn restart_config(launch: &DaemonConfig, instance: &DaemonInstance, old: &Path, data_dir: &Path) -> DaemonConfig {
+    DaemonConfig {
+        data_dir: Some(data_dir.display().to_string()),
+        ws_port: Some(instance.ws_port),
+        socket_path: Some(rebase(&instance.socket_path, old, data_dir).unwrap_or_else(|| instance.socket_path.clone())),
+        listen_addr: Some(instance.listen_addrs.clone()),
+        ..launch.clone()
+    }
+}
+
+/// Copy `old` into `new` file by file and check each copy against its source hash.
+/// Sockets and other special files are left behind.
+fn copy_verified(old: &Path, new: &Path) -> Result<(usize, u64), String> {
+    let (mut files, mut bytes) = (0, 0);
+    std::fs::create_dir_all(new).map_err(|e| format!("Failed to create {}: {}", new.display(), e))?;
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(old.join(&dir)).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let rel = dir.join(entry.file_name());
+            let (src, dst) = (old.join(&rel), new.join(&rel));
+            let kind = entry.file_type().map_err(|e| e.to_string())?;
+            if kind.is_dir() {
+                std::fs::create_dir(&dst).map_err(|e| format!("Failed to create {}: {}", dst.display(), e))?;
+                dirs.push(rel);
+            } else if kind.is_symlink() {
+                #[cfg(unix)]
+                {
+                    let target = std::fs::read_link(&src).map_err(|e| e.to_string())?;
+                    std::os::unix::fs::symlink(target, &dst)
+                        .map_err(|e| format!("Failed to link {}: {}", dst.display(), e))?;
+                }
+                #[cfg(not(unix))]
+                warn!("Not moving symlink {}", src.display());
+            } else if kind.is_file() {
+                let size = std::fs::copy(&src, &dst).map_err(|e| format!("Failed to copy {}: {}", rel.display(), e))?;
+                if sha256_file(&dst)? != sha256_file(&src)? {
+                    return Err(format!("Copy of {} does not match the original", rel.display()));
+                }
+                files += 1;
+                bytes += size;
+            }
+        }
+    }
+    Ok((files, bytes))
+}
+
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Move the data dir; returns whether it was renamed, and the files and bytes it holds.
+/// Blocks until every file is moved, so call it off the async threads.
+fn relocate(old: &Path, new: &Path, keep_old: bool) -> Result<(bool, usize, u64), String> {
+    if let Some(parent) = new.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    if !keep_old {
+        // An empty target dir is in the way of a rename
+        let _ = std::fs::remove_dir(new);
+        if std::fs::rename(old, new).is_ok() {
+            return Ok((true, count_files(new), storage::dir_size(new)));
+        }
+    }
+    let (files, bytes) = match copy_verified(old, new) {
+        Ok(copied) => copied,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(new);
+            return Err(e);
+        }
+    };
+    if !keep_old {
+        if let Err(e) = std::fs::remove_dir_all(old) {
+            warn!("Moved data dir verified, but removing {} failed: {}", old.display(), e);
+        }
+    }
+    Ok((false, files, bytes))
+}
+
+fn count_files(dir: &Path) -> usize {
+    std::fs::read_dir(dir).map_or(0, |entries| {
+        entries
+            .flatten()
+            .map(|entry| match entry.file_type() {
+                Ok(kind) if kind.is_dir() => count_files(&entry.path()),
+                Ok(kind) if kind.is_file() => 1,
+                _ => 0,
+            })
+            .sum()
+    })
+}
+
+/// Rewrite the paths in `{data_dir}/config.json` that point into `old`.
+pub(crate) fn rewrite_config_paths(data_dir: &Path, old: &Path) -> Result<(), String> {
+    let path = data_dir.join("config.json");
+    let Ok(raw) = std::fs::read_to_string(&path) else {
+        return Ok(());
+    };
+    let mut config: serde_json::Value =
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+    for key in CONFIG_PATH_KEYS {
+        let rebased = config.get(*key).and_then(|v| v.as_str()).and_then(|p| rebase(p, old, data_dir));
+        if let Some(rebased) = rebased {
+            config[*key] = rebased.into();
+        }
+    }
+    let json = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    std::fs::rename(&tmp, &path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+impl DaemonManager {
+    /// Move an instance's data dir to `new_path` and restart it from there. With `keep_old`
+    /// the old dir is copied and left in place.
+    pub async fn move_instance_data(&self, pid: u32, new_path: &str, keep_old: bool) -> Result<MovedInstance, String> {
+        let launch = self.with_daemon(pid, |d| d.launch.clone())?;
+        let instance = self
+            .list()
+            .into_iter()
+            .find(|d| d.pid == pid)
+            .ok_or_else(|| format!("No daemon with instance ID {}", pid))?;
+        let old = PathBuf::from(&instance.data_dir);
+        let new = PathBuf::from(new_path);
+        if !new.is_absolute() {
+            return Err(format!("New data dir must be an absolute path: '{}'", new_path));
+        }
+        if new.starts_with(&old) || old.starts_with(&new) {
+            return Err(format!("Cannot move {} to {}", old.display(), new.display()));
+        }
+        if std::fs::read_dir(&new).is_ok_and(|mut entries| entries.next().is_some()) {
+            return Err(format!("{} is not empty", new.display()));
+        }
+
+        // Nothing may write to the data dir while it is copied and verified; this also frees
+        // the instance's ports for the restart
+        self.stop_and_wait(pid).await?;
+
+        let (from, to) = (old.clone(), new.clone());
+        let moved = tokio::task::spawn_blocking(move || relocate(&from, &to, keep_old))
+            .await
+            .map_err(|e| format!("Move task failed: {}", e))
+            .and_then(|r| r);
+        let (renamed, files, bytes) = match moved {
+            Ok(moved) => moved,
+            Err(e) => {
+                if let Err(restart) = self.start(restart_config(&launch, &instance, &old, &old)) {
+                    warn!("Instance {} could not be restarted at {}: {}", pid, old.display(), restart);
+                }
+                return Err(e);
+            }
+        };
+        // The data lives at `new` from here on, so every path below starts it from there
+        let rewritten = rewrite_config_paths(&new, &old);
+        if let Err(e) = config::relink_instance_dir(&instance.data_dir, new_path) {
+            warn!("Failed to update instance references for {}: {}", new_path, e);
+        }
+        let restarted = self
+            .start(restart_config(&launch, &instance, &old, &new))
+            .map_err(|e| format!("Data moved to {}, but the instance failed to start there: {}", new.display(), e))?;
+        if let Err(e) = rewritten {
+            warn!("Instance {} restarted at {} without its config paths rewritten: {}", restarted.pid, new.display(), e);
+            return Err(format!(
+                "Data moved to {} and restarted as instance {}, but rewriting its config failed: {}",
+                new.display(),
+                restarted.pid,
+                e
+            ));
+        }
+        info!(
+            "Moved instance {} from {} to {} ({} files, {} bytes); now instance {}",
+            pid,
+            old.display(),
+            new.display(),
+            files,
+            bytes,
+            restarted.pid
+        );
+        Ok(MovedInstance {
+            instance: restarted,
+            old_path: instance.data_dir,
+            new_path: new_path.to_string(),
+            renamed,
+            files,
+            bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("craftstudio-relocate-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A data dir with nested files, returned with its (relative path, contents) listing.
+    fn populate(dir: &Path) -> Vec<(String, Vec<u8>)> {
+        let files = vec![
+            ("config.json".to_string(), b"{}".to_vec()),
+            ("chunks/ab/piece".to_string(), vec![7u8; 100_000]),
+            ("storage/empty".to_string(), Vec::new()),
+        ];
+        for (rel, contents) in &files {
+            let path = dir.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        files
+    }
+
+    fn assert_holds(dir: &Path, files: &[(String, Vec<u8>)]) {
+        for (rel, contents) in files {
+            assert_eq!(&std::fs::read(dir.join(rel)).unwrap(), contents, "{}", rel);
+        }
+        assert_eq!(count_files(dir), files.len());
+    }
+
+    #[test]
+    fn rebases_only_paths_under_the_old_dir() {
+        let (old, new) = (Path::new("/data/node"), Path::new("/mnt/big/node"));
+        assert_eq!(rebase("/data/node/storage", old, new).as_deref(), Some("/mnt/big/node/storage"));
+        assert_eq!(rebase("/data/node", old, new).as_deref(), Some("/mnt/big/node"));
+        assert_eq!(rebase("/data/node2/storage", old, new), None);
+        assert_eq!(rebase("/elsewhere/storage", old, new), None);
+    }
+
+    #[test]
+    fn rewrites_config_paths_into_the_new_dir() {
+        let root = temp_dir("config");
+        let (old, new) = (root.join("old"), root.join("new"));
+        std::fs::create_dir_all(&new).unwrap();
+        let config = serde_json::json!({
+            "storage_path": old.join("storage").display().to_string(),
+            "keypair_path": "/keys/node.key",
+            "socket_path": old.join("craftobj.sock").display().to_string(),
+            "max_storage_bytes": 42,
+        });
+        std::fs::write(new.join("config.json"), config.to_string()).unwrap();
+
+        rewrite_config_paths(&new, &old).unwrap();
+        let raw = std::fs::read_to_string(new.join("config.json")).unwrap();
+        let rewritten: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(rewritten["storage_path"], new.join("storage").display().to_string());
+        assert_eq!(rewritten["socket_path"], new.join("craftobj.sock").display().to_string());
+        assert_eq!(rewritten["keypair_path"], "/keys/node.key");
+        assert_eq!(rewritten["max_storage_bytes"], 42);
+
+        // No config.json is nothing to rewrite
+        rewrite_config_paths(&old, &new).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn renames_on_the_same_filesystem() {
+        let root = temp_dir("rename");
+        let (old, new) = (root.join("old"), root.join("moved").join("node"));
+        let files = populate(&old);
+
+        let (renamed, count, bytes) = relocate(&old, &new, false).unwrap();
+        assert!(renamed);
+        assert_eq!(count, files.len());
+        assert_eq!(bytes, files.iter().map(|(_, c)| c.len() as u64).sum::<u64>());
+        assert!(!old.exists());
+        assert_holds(&new, &files);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn keeping_the_old_dir_copies_and_verifies() {
+        let root = temp_dir("copy");
+        let (old, new) = (root.join("old"), root.join("new"));
+        let files = populate(&old);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("config.json", old.join("link")).unwrap();
+
+        let (renamed, count, bytes) = relocate(&old, &new, true).unwrap();
+        assert!(!renamed);
+        assert_eq!(count, files.len());
+        assert_eq!(bytes, files.iter().map(|(_, c)| c.len() as u64).sum::<u64>());
+        assert_holds(&old, &files);
+        assert_holds(&new, &files);
+        #[cfg(unix)]
+        assert_eq!(std::fs::read_link(new.join("link")).unwrap(), Path::new("config.json"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn failed_copy_leaves_no_partial_target() {
+        let root = temp_dir("fail");
+        let (old, new) = (root.join("missing"), root.join("new"));
+        assert!(relocate(&old, &new, true).is_err());
+        assert!(!new.exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
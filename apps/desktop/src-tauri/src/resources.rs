@@ -0,0 +1,109 @@
+//! Per-instance resource controls.
+//!
+//! By default every instance shares the app's tokio runtime. `ResourceLimits` can instead
+//! give an instance its own worker pool, cap how many IPC requests it serves at once, and
+//! cap disk I/O for chunk storage. The disk cap is passed to CraftOBJ and also applies to
+//! file work the desktop crate does for the instance through `IoLimiter`.
+//!
+//! `MemoryUsage` accounts for the buffers the desktop crate keeps per instance. Swarm and
+//! store internals allocate from the shared heap and can't be attributed, so they are not
+//! included.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Run the instance on its own multi-threaded runtime with this many workers
+    #[serde(default)]
+    pub worker_threads: Option<usize>,
+    /// IPC requests served concurrently; further requests wait for a slot
+    #[serde(default)]
+    pub max_concurrent_ipc: Option<usize>,
+    /// Chunk storage read+write bandwidth
+    #[serde(default)]
+    pub disk_io_bytes_per_sec: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.worker_threads == Some(0) {
+            return Err("worker_threads must be at least 1".to_string());
+        }
+        if self.max_concurrent_ipc == Some(0) {
+            return Err("max_concurrent_ipc must be at least 1".to_string());
+        }
+        if self.disk_io_bytes_per_sec == Some(0) {
+            return Err("disk_io_bytes_per_sec must be above 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Dedicated runtime for an instance whose limits ask for one.
+pub fn build_runtime(instance_id: u32, worker_threads: usize) -> Result<tokio::runtime::Runtime, String> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .thread_name(format!("craftobj-{}", instance_id))
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to start runtime for instance {}: {}", instance_id, e))
+}
+
+/// Token bucket for disk bytes. One second of burst.
+pub struct IoLimiter {
+    bytes_per_sec: u64,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl IoLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            bucket: Mutex::new((bytes_per_sec as f64, Instant::now())),
+        }
+    }
+
+    /// Wait until `bytes` may be read or written.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let (tokens, last) = &mut *bucket;
+            let now = Instant::now();
+            let rate = self.bytes_per_sec as f64;
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+            *last = now;
+            *tokens -= bytes as f64;
+            if *tokens < 0.0 {
+                Duration::from_secs_f64(-*tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Bytes held in per-instance buffers owned by the desktop crate.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct MemoryUsage {
+    pub total_bytes: u64,
+    pub log_buffer_bytes: u64,
+    pub event_replay_bytes: u64,
+    pub peer_store_bytes: u64,
+}
+
+impl MemoryUsage {
+    pub fn new(log_buffer_bytes: u64, event_replay_bytes: u64, peer_store_bytes: u64) -> Self {
+        Self {
+            total_bytes: log_buffer_bytes + event_replay_bytes + peer_store_bytes,
+            log_buffer_bytes,
+            event_replay_bytes,
+            peer_store_bytes,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Pluggable node services.
+//!
+//! CraftOBJ is the core of every instance and always owns the `data` namespace. Everything
+//! else that rides on the same swarm (CraftNet tunnels today; settlement, compute or
+//! aggregator later) implements `NodeService` and is registered in the `ServiceRegistry`,
+//! either built in or through `DaemonManager::register_service`. `DaemonManager::start`
+//! builds the services whose capability the instance has, registers each handler under its
+//! namespace and initializes them once the swarm is up.
+
+use std::any::Any;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use craftec_ipc::server::IpcHandler;
+use libp2p::PeerId;
+
+use crate::events::{EventBus, EventSink};
+
+pub type ServiceFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// A service hosted alongside CraftOBJ in an in-process instance.
+pub trait NodeService: Send + Sync {
+    /// IPC namespace the service's handler is registered under (e.g. `tunnel`).
+    fn namespace(&self) -> &'static str;
+
+    /// Handler for `<namespace>.*` calls.
+    fn handler(&self) -> Arc<dyn IpcHandler>;
+
+    /// Called once the swarm is running.
+    fn init<'a>(&'a self, ctx: &'a ServiceContext) -> ServiceFuture<'a>;
+
+    /// Called when the instance stops.
+    fn shutdown(&self) -> ServiceFuture<'_>;
+}
+
+/// Inputs available to a service factory before the daemon starts.
+pub struct ServiceSeed {
+    pub secret_key: [u8; 32],
+    pub data_dir: PathBuf,
+}
+
+/// What a service gets at `init` time.
+pub struct ServiceContext {
+    pub local_peer_id: PeerId,
+    pub data_dir: PathBuf,
+    /// CraftOBJ handler, for services that store or fetch content
+    pub data: Arc<dyn IpcHandler>,
+    /// Instance event bus; use `sink` to publish under the service's namespace
+    events: Arc<EventBus>,
+    /// Raw swarm command/event channels. There is only one set per swarm, so the first
+    /// service that needs direct swarm access takes it.
+    swarm: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl ServiceContext {
+    pub fn new(
+        local_peer_id: PeerId,
+        data_dir: PathBuf,
+        data: Arc<dyn IpcHandler>,
+        events: Arc<EventBus>,
+        swarm: Box<dyn Any + Send>,
+    ) -> Self {
+        Self {
+            local_peer_id,
+            data_dir,
+            data,
+            events,
+            swarm: Mutex::new(Some(swarm)),
+        }
+    }
+
+    /// Event sink that publishes `<namespace>.<event>` notifications.
+    pub fn sink(&self, namespace: &'static str) -> EventSink {
+        EventSink::new(namespace, self.events.clone())
+    }
+
+    /// Every notification published on the instance bus, CraftOBJ's own events included.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<String> {
+        self.events.subscribe()
+    }
+
+    /// Take the swarm handles, if nobody has yet and they are of type `T`.
+    pub fn take_swarm<T: 'static>(&self) -> Option<T> {
+        let mut swarm = self.swarm.lock().unwrap();
+        match swarm.take()?.downcast::<T>() {
+            Ok(handles) => Some(*handles),
+            Err(other) => {
+                *swarm = Some(other);
+                None
+            }
+        }
+    }
+}
+
+pub type BuildFn = fn(&ServiceSeed) -> Result<Arc<dyn NodeService>, String>;
+
+struct ServiceFactory {
+    namespace: &'static str,
+    /// Instance capability that enables the service; `None` means always on.
+    capability: Option<&'static str>,
+    build: BuildFn,
+}
+
+/// Known services and the capability that enables each one.
+pub struct ServiceRegistry {
+    factories: Vec<ServiceFactory>,
+}
+
+/// Namespaces a service can't take: CraftOBJ's own and the ones every listener serves.
+const RESERVED_NAMESPACES: &[&str] = &["data", "events"];
+
+impl Default for ServiceRegistry {
+    fn default() -> Self {
+        Self {
+            factories: vec![ServiceFactory {
+                namespace: "tunnel",
+                capability: None,
+                build: crate::craftnet_adapter::build_service,
+            }],
+        }
+    }
+}
+
+impl ServiceRegistry {
+    /// Add a service. Namespaces must be unique, and `data` and `events` are reserved.
+    pub fn register(
+        &mut self,
+        namespace: &'static str,
+        capability: Option<&'static str>,
+        build: BuildFn,
+    ) -> Result<(), String> {
+        if RESERVED_NAMESPACES.contains(&namespace) {
+            return Err(format!("The {} namespace is reserved", namespace));
+        }
+        if self.factories.iter().any(|f| f.namespace == namespace) {
+            return Err(format!("A service is already registered under {}", namespace));
+        }
+        self.factories.push(ServiceFactory {
+            namespace,
+            capability,
+            build,
+        });
+        Ok(())
+    }
+
+    /// Build every service enabled by `capabilities`.
+    pub fn build_for(
+        &self,
+        capabilities: &[String],
+        seed: &ServiceSeed,
+    ) -> Result<Vec<Arc<dyn NodeService>>, String> {
+        self.factories
+            .iter()
+            .filter(|f| f.capability.is_none_or(|c| capabilities.iter().any(|have| have == c)))
+            .map(|f| (f.build)(seed))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refuse(_: &ServiceSeed) -> Result<Arc<dyn NodeService>, String> {
+        Err("built".to_string())
+    }
+
+    #[test]
+    fn register_rejects_reserved_and_duplicate_namespaces() {
+        let mut registry = ServiceRegistry::default();
+        assert!(registry.register("data", None, refuse).is_err());
+        assert!(registry.register("events", None, refuse).is_err());
+        assert!(registry.register("tunnel", None, refuse).is_err());
+        assert!(registry.register("compute", Some("compute"), refuse).is_ok());
+    }
+
+    #[test]
+    fn builds_only_services_the_capabilities_enable() {
+        let mut registry = ServiceRegistry { factories: Vec::new() };
+        registry.register("compute", Some("compute"), refuse).unwrap();
+        let seed = ServiceSeed {
+            secret_key: [0; 32],
+            data_dir: PathBuf::new(),
+        };
+        assert!(registry.build_for(&["storage".to_string()], &seed).unwrap().is_empty());
+        assert_eq!(registry.build_for(&["compute".to_string()], &seed).err(), Some("built".to_string()));
+    }
+}
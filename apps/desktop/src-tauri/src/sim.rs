@@ -0,0 +1,510 @@
+//! Multi-node harness for integration tests (`--features sim`).
+//!
+//! Nodes run in-process on a `DaemonManager` in in-memory IPC mode: nothing binds a socket
+//! or WebSocket port, and tests reach a node with `SimHarness::call`, which goes through
+//! the same routing, audit and compatibility layers as a real listener. Swarms are
+//! `isolated` nodes listening on loopback QUIC with ports the OS picks, so no outside
+//! network is needed. Partitions ban and disconnect, through `SwarmControl`, every peer
+//! pair that straddles a boundary, and `heal` lifts the bans and redials.
+//!
+//! Nodes dial each other through a UDP relay per link instead of at each other's own port.
+//! `set_link` gives a link latency and a datagram loss rate, which the relay applies to
+//! QUIC packets in both directions; QUIC's own recovery then deals with the loss. Drops come
+//! from an RNG per link seeded from the harness `id`. A node that learns another's own
+//! address (through identify, say) and dials it directly bypasses the relay, so shaping
+//! covers the connections the harness dials: boot peers and `heal`.
+//!
+//! The request's in-memory libp2p transport is not provided: the swarm is built inside
+//! `craftec_network` with its own TCP/QUIC transports and has no hook for another one.
+//! Loopback is what stands in for it, which also works on a CI box without a network.
+//!
+//! End a test with `shutdown`, which waits for every node to stop before removing the
+//! scratch dir. A harness that is only dropped stops its nodes but leaves the dir behind,
+//! since their tasks may still be writing to it.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::Value;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use crate::daemon_manager::{DaemonConfig, DaemonManager};
+use crate::swarm_ctl::SwarmControl;
+
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest datagram a relay forwards; QUIC keeps well under it
+const MAX_DATAGRAM: usize = 65_535;
+
+struct SimNode {
+    pid: u32,
+    peer_id: String,
+    /// The node's own QUIC listen address
+    quic: SocketAddr,
+}
+
+/// Conditions on a link between two nodes, applied in both directions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkShape {
+    /// Added to every datagram
+    pub latency: Duration,
+    /// Chance in `[0, 1]` that a datagram is dropped
+    pub loss: f64,
+}
+
+pub struct SimHarness {
+    manager: DaemonManager,
+    root: PathBuf,
+    id: u64,
+    nodes: Mutex<BTreeMap<String, SimNode>>,
+    /// Shape per node pair, shared with the relays of that pair
+    shapes: Mutex<HashMap<(String, String), Arc<Mutex<LinkShape>>>>,
+    /// Relays keyed by (dialer, listener), with the address the dialer is given
+    relays: Mutex<HashMap<(String, String), (SocketAddr, JoinHandle<()>)>>,
+    /// Node pairs currently banned from each other by a partition
+    cut: Mutex<HashSet<(String, String)>>,
+    /// Set by `shutdown`, after which dropping the harness has nothing left to do
+    stopped: bool,
+}
+
+impl SimHarness {
+    /// Create an empty network. Must be called inside a tokio runtime. `id` keeps the
+    /// scratch dirs of harnesses running side by side apart and seeds link loss.
+    pub fn new(id: u64) -> Self {
+        let root = std::env::temp_dir().join(format!("craftstudio-sim-{}-{}", std::process::id(), id));
+        let _ = std::fs::remove_dir_all(&root);
+        Self {
+            manager: DaemonManager::new_in_memory(
+                Arc::new(Mutex::new(HashMap::new())),
+                tokio::runtime::Handle::current(),
+            ),
+            root,
+            id,
+            nodes: Mutex::new(BTreeMap::new()),
+            shapes: Mutex::new(HashMap::new()),
+            relays: Mutex::new(HashMap::new()),
+            cut: Mutex::new(HashSet::new()),
+            stopped: false,
+        }
+    }
+
+    /// Scratch directory for test files; removed with the harness.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Start a node that boots from every node added before it, and wait until its
+    /// handlers are up and its swarm is listening.
+    pub async fn add_node(&self, name: &str, capabilities: &[&str]) -> Result<(), String> {
+        let data_dir = self.root.join(name);
+        let earlier: Vec<String> = self.nodes.lock().unwrap().keys().cloned().collect();
+        let mut boot_peers = Vec::with_capacity(earlier.len());
+        for other in &earlier {
+            boot_peers.push(self.link_addr(name, other).await?);
+        }
+
+        let instance = self.manager.start(DaemonConfig {
+            data_dir: Some(data_dir.to_string_lossy().to_string()),
+            listen_addr: Some(vec!["/ip4/127.0.0.1/udp/0/quic-v1".to_string()]),
+            socket_path: Some(self.root.join(format!("{}.sock", name)).to_string_lossy().to_string()),
+            capabilities: Some(capabilities.iter().map(|c| c.to_string()).collect()),
+            boot_peers: Some(boot_peers),
+            isolated: true,
+            ..Default::default()
+        })?;
+
+        let deadline = Instant::now() + READY_TIMEOUT;
+        let (instance, quic) = loop {
+            let listening = self
+                .manager
+                .list()
+                .into_iter()
+                .find(|d| d.pid == instance.pid)
+                .and_then(|d| {
+                    let quic = d.bound_addrs.iter().find_map(|a| quic_socket_addr(a))?;
+                    Some((d, quic))
+                });
+            match listening {
+                Some((node, quic)) if self.manager.running_ipc(node.pid).is_ok() => break (node, quic),
+                _ if Instant::now() >= deadline => {
+                    return Err(format!("Node '{}' did not start within {}s", name, READY_TIMEOUT.as_secs()))
+                }
+                _ => tokio::time::sleep(READY_POLL_INTERVAL).await,
+            }
+        };
+
+        self.nodes.lock().unwrap().insert(
+            name.to_string(),
+            SimNode {
+                pid: instance.pid,
+                peer_id: instance.peer_id,
+                quic,
+            },
+        );
+        Ok(())
+    }
+
+    /// JSON-RPC call into a node.
+    pub async fn call(&self, node: &str, method: &str, params: Value) -> Result<Value, String> {
+        let pid = self.node(node)?.0;
+        let params = if params.is_null() { None } else { Some(params) };
+        self.manager.call(pid, method, params).await
+    }
+
+    /// Notifications published by a node.
+    pub fn subscribe(&self, node: &str) -> Result<tokio::sync::broadcast::Receiver<String>, String> {
+        self.manager.subscribe(self.node(node)?.0)
+    }
+
+    /// Set latency and loss on the link between `a` and `b`. Takes effect on the next datagram.
+    pub fn set_link(&self, a: &str, b: &str, shape: LinkShape) {
+        let shape = LinkShape {
+            loss: shape.loss.clamp(0.0, 1.0),
+            ..shape
+        };
+        *self.shape(a, b).lock().unwrap() = shape;
+    }
+
+    /// Split the network into `groups`; nodes in different groups can't reach each other.
+    /// Nodes not listed form one more group together.
+    pub async fn partition(&self, groups: &[&[&str]]) -> Result<(), String> {
+        let names: Vec<String> = self.nodes.lock().unwrap().keys().cloned().collect();
+        let group_of = |n: &str| groups.iter().position(|g| g.contains(&n)).unwrap_or(groups.len());
+
+        for (i, a) in names.iter().enumerate() {
+            for b in &names[i + 1..] {
+                if group_of(a) != group_of(b) && self.cut.lock().unwrap().insert(ordered(a, b)) {
+                    self.sever(a, b).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo all partitions and redial the affected pairs.
+    pub async fn heal(&self) -> Result<(), String> {
+        let cut: Vec<(String, String)> = self.cut.lock().unwrap().drain().collect();
+        for (a, b) in cut {
+            let (a_pid, a_peer) = self.node(&a)?;
+            let (b_pid, b_peer) = self.node(&b)?;
+            self.control(a_pid)?.unban(&b_peer).await?;
+            self.control(b_pid)?.unban(&a_peer).await?;
+            let addr = self.link_addr(&b, &a).await?;
+            self.control(b_pid)?.dial(&addr).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait until `a` reports a connection to `b`.
+    pub async fn wait_connected(&self, a: &str, b: &str, timeout: Duration) -> Result<(), String> {
+        self.wait_for(a, b, true, timeout).await
+    }
+
+    /// Wait until `a` no longer reports a connection to `b`. Disconnects are only seen
+    /// once the swarm has processed the connection closing, so poll rather than check once.
+    pub async fn wait_disconnected(&self, a: &str, b: &str, timeout: Duration) -> Result<(), String> {
+        self.wait_for(a, b, false, timeout).await
+    }
+
+    /// Whether `a` currently reports a connection to `b`.
+    pub async fn is_connected(&self, a: &str, b: &str) -> Result<bool, String> {
+        let (a_pid, _) = self.node(a)?;
+        let (_, b_peer) = self.node(b)?;
+        Ok(self.control(a_pid)?.connected_peers().await?.contains(&b_peer))
+    }
+
+    async fn wait_for(&self, a: &str, b: &str, connected: bool, timeout: Duration) -> Result<(), String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.is_connected(a, b).await? == connected {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                let state = if connected {
+                    "not connected to"
+                } else {
+                    "still connected to"
+                };
+                return Err(format!("'{}' {} '{}' after {}s", a, state, b, timeout.as_secs()));
+            }
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Ban `a` and `b` from each other and disconnect them.
+    async fn sever(&self, a: &str, b: &str) -> Result<(), String> {
+        let (a_pid, a_peer) = self.node(a)?;
+        let (b_pid, b_peer) = self.node(b)?;
+        let (a_ctl, b_ctl) = (self.control(a_pid)?, self.control(b_pid)?);
+        a_ctl.ban(&b_peer).await?;
+        b_ctl.ban(&a_peer).await?;
+        a_ctl.disconnect(&b_peer).await?;
+        b_ctl.disconnect(&a_peer).await?;
+        Ok(())
+    }
+
+    fn node(&self, name: &str) -> Result<(u32, String), String> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|n| (n.pid, n.peer_id.clone()))
+            .ok_or_else(|| format!("No sim node named '{}'", name))
+    }
+
+    fn shape(&self, a: &str, b: &str) -> Arc<Mutex<LinkShape>> {
+        Arc::clone(self.shapes.lock().unwrap().entry(ordered(a, b)).or_default())
+    }
+
+    /// Address `dialer` reaches `listener` at, through the link's relay.
+    async fn link_addr(&self, dialer: &str, listener: &str) -> Result<String, String> {
+        let (target, peer_id) = {
+            let nodes = self.nodes.lock().unwrap();
+            let node = nodes
+                .get(listener)
+                .ok_or_else(|| format!("No sim node named '{}'", listener))?;
+            (node.quic, node.peer_id.clone())
+        };
+        let key = (dialer.to_string(), listener.to_string());
+        let existing = self.relays.lock().unwrap().get(&key).map(|(addr, _)| *addr);
+        let addr = match existing {
+            Some(addr) => addr,
+            None => {
+                let seed = self.id ^ link_seed(dialer, listener);
+                let (addr, task) = spawn_relay(target, self.shape(dialer, listener), seed).await?;
+                self.relays.lock().unwrap().insert(key, (addr, task));
+                addr
+            }
+        };
+        Ok(format!("/ip4/127.0.0.1/udp/{}/quic-v1/p2p/{}", addr.port(), peer_id))
+    }
+
+    fn control(&self, pid: u32) -> Result<SwarmControl, String> {
+        let (ipc, _, _) = self.manager.running_ipc(pid)?;
+        Ok(SwarmControl::new(ipc.handler))
+    }
+}
+
+impl SimHarness {
+    /// Stop every node, wait until their tasks have ended, and remove the scratch dir.
+    pub async fn shutdown(mut self) {
+        let pids: Vec<u32> = self.nodes.lock().unwrap().values().map(|n| n.pid).collect();
+        for pid in pids {
+            let _ = self.manager.stop_and_wait(pid).await;
+        }
+        for (_, (_, relay)) in self.relays.lock().unwrap().drain() {
+            relay.abort();
+        }
+        let _ = std::fs::remove_dir_all(&self.root);
+        self.stopped = true;
+    }
+}
+
+impl Drop for SimHarness {
+    fn drop(&mut self) {
+        if !self.stopped {
+            self.manager.stop_all();
+            for (_, (_, relay)) in self.relays.lock().unwrap().drain() {
+                relay.abort();
+            }
+        }
+    }
+}
+
+fn ordered(a: &str, b: &str) -> (String, String) {
+    if a <= b {
+        (a.to_string(), b.to_string())
+    } else {
+        (b.to_string(), a.to_string())
+    }
+}
+
+/// Stable per-link seed, so the same harness `id` drops the same datagrams on a link.
+fn link_seed(dialer: &str, listener: &str) -> u64 {
+    // FNV-1a; `DefaultHasher` isn't guaranteed to be stable across releases
+    format!("{}>{}", dialer, listener)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Loopback socket address of a `/ip4/127.0.0.1/udp/<port>/quic-v1` listen address.
+fn quic_socket_addr(addr: &str) -> Option<SocketAddr> {
+    let addr: Multiaddr = addr.parse().ok()?;
+    let mut port = None;
+    let mut quic = false;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::Udp(p) => port = Some(p),
+            Protocol::QuicV1 => quic = true,
+            _ => {}
+        }
+    }
+    quic.then(|| SocketAddr::from(([127, 0, 0, 1], port?)))
+}
+
+/// Start a relay that forwards datagrams between whoever sends to the returned address and
+/// `target`, shaped by `shape`. Runs until aborted.
+async fn spawn_relay(
+    target: SocketAddr,
+    shape: Arc<Mutex<LinkShape>>,
+    seed: u64,
+) -> Result<(SocketAddr, JoinHandle<()>), String> {
+    let bind = |what: &'static str| async move {
+        UdpSocket::bind("127.0.0.1:0")
+            .await
+            .map(Arc::new)
+            .map_err(|e| format!("Failed to bind sim relay {} socket: {}", what, e))
+    };
+    // The dialer talks to `front`; the listener sees traffic coming from `back`
+    let (front, back) = (bind("front").await?, bind("back").await?);
+    let addr = front
+        .local_addr()
+        .map_err(|e| format!("Failed to read sim relay address: {}", e))?;
+
+    let task = tokio::spawn(async move {
+        let (to_dialer, to_dialer_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (to_listener, to_listener_rx) = tokio::sync::mpsc::unbounded_channel();
+        let senders = [
+            tokio::spawn(deliver(Arc::clone(&front), to_dialer_rx)),
+            tokio::spawn(deliver(Arc::clone(&back), to_listener_rx)),
+        ];
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut dialer: Option<SocketAddr> = None;
+        let (mut front_buf, mut back_buf) = (vec![0u8; MAX_DATAGRAM], vec![0u8; MAX_DATAGRAM]);
+        loop {
+            let (datagram, dest, queue) = tokio::select! {
+                received = front.recv_from(&mut front_buf) => {
+                    let Ok((n, from)) = received else { break };
+                    dialer = Some(from);
+                    (front_buf[..n].to_vec(), target, &to_listener)
+                }
+                received = back.recv_from(&mut back_buf) => {
+                    let Ok((n, from)) = received else { break };
+                    match dialer {
+                        Some(dialer) if from == target => (back_buf[..n].to_vec(), dialer, &to_dialer),
+                        _ => continue,
+                    }
+                }
+            };
+            let LinkShape { latency, loss } = *shape.lock().unwrap();
+            if loss > 0.0 && rng.gen_bool(loss) {
+                continue;
+            }
+            let _ = queue.send((tokio::time::Instant::now() + latency, datagram, dest));
+        }
+        for sender in senders {
+            sender.abort();
+        }
+    });
+    Ok((addr, task))
+}
+
+/// Send queued datagrams once they are due. Latency is the same for every datagram queued
+/// under one shape, so order is kept.
+async fn deliver(
+    socket: Arc<UdpSocket>,
+    mut queue: tokio::sync::mpsc::UnboundedReceiver<(tokio::time::Instant, Vec<u8>, SocketAddr)>,
+) {
+    while let Some((due, datagram, dest)) = queue.recv().await {
+        tokio::time::sleep_until(due).await;
+        let _ = socket.send_to(&datagram, dest).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Echo server plus a relay in front of it, on loopback.
+    async fn echo_through_relay(
+        shape: LinkShape,
+        seed: u64,
+    ) -> (UdpSocket, SocketAddr, JoinHandle<()>, JoinHandle<()>) {
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = echo.local_addr().unwrap();
+        let echo_task = tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, from)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..n], from).await;
+            }
+        });
+        let (relay, relay_task) = spawn_relay(target, Arc::new(Mutex::new(shape)), seed).await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        (client, relay, relay_task, echo_task)
+    }
+
+    #[test]
+    fn relay_adds_latency_each_way() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let shape = LinkShape {
+                latency: Duration::from_millis(100),
+                loss: 0.0,
+            };
+            let (client, relay, relay_task, echo_task) = echo_through_relay(shape, 1).await;
+            let started = Instant::now();
+            client.send_to(b"ping", relay).await.unwrap();
+            let mut buf = [0u8; 16];
+            let (n, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buf[..n], b"ping");
+            assert!(
+                started.elapsed() >= Duration::from_millis(200),
+                "took {:?}",
+                started.elapsed()
+            );
+            relay_task.abort();
+            echo_task.abort();
+        });
+    }
+
+    #[test]
+    fn relay_drops_with_a_seeded_rate() {
+        let delivered = |seed: u64| {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let shape = LinkShape {
+                    latency: Duration::ZERO,
+                    loss: 0.5,
+                };
+                let (client, relay, relay_task, echo_task) = echo_through_relay(shape, seed).await;
+                let mut got = Vec::new();
+                for i in 0..40u8 {
+                    client.send_to(&[i], relay).await.unwrap();
+                    let mut buf = [0u8; 1];
+                    if let Ok(Ok(_)) =
+                        tokio::time::timeout(Duration::from_millis(200), client.recv_from(&mut buf)).await
+                    {
+                        got.push(buf[0]);
+                    }
+                }
+                relay_task.abort();
+                echo_task.abort();
+                got
+            })
+        };
+        let first = delivered(7);
+        assert!(!first.is_empty() && first.len() < 40, "delivered {} of 40", first.len());
+        assert_eq!(first, delivered(7));
+    }
+
+    #[test]
+    fn parses_bound_quic_addresses() {
+        assert_eq!(
+            quic_socket_addr("/ip4/127.0.0.1/udp/4001/quic-v1"),
+            Some(SocketAddr::from(([127, 0, 0, 1], 4001)))
+        );
+        assert_eq!(quic_socket_addr("/ip4/127.0.0.1/tcp/4001"), None);
+    }
+}
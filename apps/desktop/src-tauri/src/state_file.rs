@@ -0,0 +1,69 @@
+//! JSON state files in an instance's data dir (jobs, sync folders, mirrors, peers, VFS).
+//!
+//! A file is replaced by writing a `.tmp` sibling and renaming it over the old one, so a
+//! crash leaves either the old or the new contents. A file that fails to parse is moved
+//! aside to `<name>.corrupt-<unix secs>` instead of being treated as empty, so the next
+//! save can't overwrite what may still be recovered by hand.
+
+use std::io::Write;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::api_keys::now_secs;
+
+/// Read `path`, or the default when it doesn't exist or can't be used.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return T::default(),
+        Err(e) => {
+            warn!("Failed to read {}, starting empty: {}", path.display(), e);
+            return T::default();
+        }
+    };
+    match serde_json::from_str(&raw) {
+        Ok(value) => value,
+        Err(e) => {
+            let aside = path.with_extension(format!("json.corrupt-{}", now_secs()));
+            match std::fs::rename(path, &aside) {
+                Ok(()) => warn!("{} is corrupt ({}), moved it to {}", path.display(), e, aside.display()),
+                Err(move_err) => warn!("{} is corrupt ({}) and could not be moved aside: {}", path.display(), e, move_err),
+            }
+            T::default()
+        }
+    }
+}
+
+/// Replace `path` with `contents`.
+pub fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    replace(path, contents, None)
+}
+
+/// Replace `path` with `contents` readable by the owner only (0600 on Unix), for secrets.
+/// The mode is set when the temporary file is created, so the secret is never readable by
+/// others, even briefly.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    replace(path, contents, Some(0o600))
+}
+
+fn replace(path: &Path, contents: &[u8], mode: Option<u32>) -> Result<(), String> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let _ = std::fs::remove_file(&tmp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    let mut file = options.open(&tmp).map_err(|e| format!("Failed to create {}: {}", tmp.display(), e))?;
+    file.write_all(contents)
+        .map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
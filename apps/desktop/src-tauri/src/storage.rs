@@ -0,0 +1,313 @@
+//! Disk usage and storage quotas across instances.
+//!
+//! Each instance stores pieces under `storage` (or the `storage_path` in its config.json),
+//! `chunks` and `manifests`. Everything else in the data dir (caches, staged manifests,
+//! state files) is counted as `other`. The quota is `max_storage_bytes` in config.json,
+//! where 0 means unlimited.
+//!
+//! Instances whose pieces are stored on the same filesystem compete for its free space.
+//! That is the filesystem of the storage dir, which an external `storage_path` may put
+//! elsewhere than the data dir. Before an instance starts, the quota room every instance on
+//! that filesystem still has (quota minus what it already uses) is added up. If that total
+//! exceeds the free space, the start is logged as a warning, or refused with
+//! `strict_storage`. A new instance's default quota is `DEFAULT_QUOTA`, capped at the free
+//! space the others haven't claimed yet.
+//!
+//! Measuring walks an instance's whole store, so it isn't done on the start path. Each
+//! running instance is measured off-thread into a `UsageCache` right after it starts and
+//! every `USAGE_REFRESH` after that, and starts plan against the last measurement. An
+//! instance not measured yet, including the one starting, counts its whole quota as room.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::daemon_manager::DaemonManager;
+use crate::state_file;
+
+/// Quota given to new instances when the filesystem has room for it.
+pub const DEFAULT_QUOTA: u64 = 10 * 1024 * 1024 * 1024;
+/// Smallest quota a new instance is given by default.
+const MIN_QUOTA: u64 = 256 * 1024 * 1024;
+/// How often a running instance's usage is measured.
+const USAGE_REFRESH: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceStorage {
+    /// `None` for the instance being started
+    pub pid: Option<u32>,
+    pub data_dir: String,
+    pub storage: u64,
+    pub chunks: u64,
+    pub manifests: u64,
+    pub other: u64,
+    /// `None` when unlimited
+    pub quota: Option<u64>,
+    /// Where the instance stores pieces
+    pub storage_path: String,
+    /// Filesystem `storage_path` is on
+    pub device: u64,
+}
+
+impl InstanceStorage {
+    pub fn used(&self) -> u64 {
+        self.storage + self.chunks + self.manifests
+    }
+
+    /// Space the instance may still claim under its quota.
+    fn outstanding(&self) -> u64 {
+        self.quota.map_or(0, |q| q.saturating_sub(self.used()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FilesystemUsage {
+    pub device: u64,
+    /// Storage path of the first instance on the filesystem, to identify it
+    pub example_path: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    /// Quota room of all instances on the filesystem
+    pub committed_bytes: u64,
+    /// Instances on the filesystem without a quota
+    pub unlimited: usize,
+    pub overcommitted: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageReport {
+    pub instances: Vec<InstanceStorage>,
+    pub filesystems: Vec<FilesystemUsage>,
+}
+
+/// Size and free space of the filesystem holding `path`.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn filesystem_space(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+#[cfg(not(unix))]
+pub fn filesystem_space(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).map_or(0, |m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_path: &Path) -> u64 {
+    0
+}
+
+/// Bytes of all files below `path`; symlinks aren't followed.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) if meta.is_file() => meta.len(),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Quota and storage path of a data dir from its config.json, with no usage measured.
+fn unmeasured(pid: Option<u32>, data_dir: &Path) -> InstanceStorage {
+    let config: Option<serde_json::Value> = std::fs::read_to_string(data_dir.join("config.json"))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok());
+    let quota = config
+        .as_ref()
+        .and_then(|c| c.get("max_storage_bytes"))
+        .and_then(|v| v.as_u64())
+        .filter(|q| *q > 0);
+    let storage_dir = config
+        .as_ref()
+        .and_then(|c| c.get("storage_path"))
+        .and_then(|v| v.as_str())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| data_dir.join("storage"));
+    // A storage dir that doesn't exist yet is created in the data dir's filesystem
+    let dev = if storage_dir.exists() { device(&storage_dir) } else { device(data_dir) };
+    InstanceStorage {
+        pid,
+        data_dir: data_dir.display().to_string(),
+        storage: 0,
+        chunks: 0,
+        manifests: 0,
+        other: 0,
+        quota,
+        storage_path: storage_dir.display().to_string(),
+        device: dev,
+    }
+}
+
+/// Usage of one data dir, with the quota and storage path from its config.json.
+pub fn measure(pid: Option<u32>, data_dir: &Path) -> InstanceStorage {
+    let mut usage = unmeasured(pid, data_dir);
+    let storage_dir = PathBuf::from(&usage.storage_path);
+    usage.storage = dir_size(&storage_dir);
+    usage.chunks = dir_size(&data_dir.join("chunks"));
+    usage.manifests = dir_size(&data_dir.join("manifests"));
+    usage.other = dir_size(data_dir).saturating_sub(usage.chunks + usage.manifests);
+    if storage_dir.starts_with(data_dir) {
+        usage.other = usage.other.saturating_sub(usage.storage);
+    }
+    usage
+}
+
+/// Last measured usage per data dir.
+#[derive(Default)]
+pub struct UsageCache(Mutex<HashMap<PathBuf, InstanceStorage>>);
+
+impl UsageCache {
+    /// Current quota and storage path of `data_dir`, with the usage measured last.
+    fn get(&self, pid: Option<u32>, data_dir: &Path) -> InstanceStorage {
+        let mut usage = unmeasured(pid, data_dir);
+        if let Some(measured) = self.0.lock().unwrap().get(data_dir) {
+            usage.storage = measured.storage;
+            usage.chunks = measured.chunks;
+            usage.manifests = measured.manifests;
+            usage.other = measured.other;
+        }
+        usage
+    }
+
+    fn insert(&self, usage: &InstanceStorage) {
+        self.0.lock().unwrap().insert(PathBuf::from(&usage.data_dir), usage.clone());
+    }
+}
+
+/// Measure an instance's usage into `cache` every `USAGE_REFRESH`, off the async threads.
+pub async fn refresh_usage(cache: std::sync::Arc<UsageCache>, pid: u32, data_dir: PathBuf) {
+    let mut tick = tokio::time::interval(USAGE_REFRESH);
+    loop {
+        tick.tick().await;
+        let dir = data_dir.clone();
+        match tokio::task::spawn_blocking(move || measure(Some(pid), &dir)).await {
+            Ok(usage) => cache.insert(&usage),
+            Err(e) => warn!("Measuring storage of {} failed: {}", data_dir.display(), e),
+        }
+    }
+}
+
+/// Group instances by filesystem and compare their quota room with the free space.
+fn filesystems(instances: &[InstanceStorage]) -> Vec<FilesystemUsage> {
+    let mut by_device: BTreeMap<u64, Vec<&InstanceStorage>> = BTreeMap::new();
+    for instance in instances {
+        by_device.entry(instance.device).or_default().push(instance);
+    }
+    by_device
+        .into_iter()
+        .map(|(device, group)| {
+            let (total_bytes, available_bytes) = filesystem_space(Path::new(&group[0].storage_path)).unwrap_or((0, 0));
+            let committed_bytes = group.iter().map(|i| i.outstanding()).sum();
+            FilesystemUsage {
+                device,
+                example_path: group[0].storage_path.clone(),
+                total_bytes,
+                available_bytes,
+                committed_bytes,
+                unlimited: group.iter().filter(|i| i.quota.is_none()).count(),
+                overcommitted: total_bytes > 0 && committed_bytes > available_bytes,
+            }
+        })
+        .collect()
+}
+
+/// Default quota for a new instance on `data_dir`'s filesystem, next to `others`.
+pub fn default_quota(data_dir: &Path, others: &[InstanceStorage]) -> u64 {
+    let dev = device(data_dir);
+    let Some((_, available)) = filesystem_space(data_dir) else {
+        return DEFAULT_QUOTA;
+    };
+    let claimed: u64 = others.iter().filter(|i| i.device == dev).map(|i| i.outstanding()).sum();
+    available.saturating_sub(claimed).min(DEFAULT_QUOTA).max(MIN_QUOTA)
+}
+
+/// Write `max_storage_bytes` into an existing config.json.
+pub fn set_quota(data_dir: &Path, quota: u64) -> Result<(), String> {
+    let path = data_dir.join("config.json");
+    let raw = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut config: serde_json::Value =
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+    config["max_storage_bytes"] = quota.into();
+    let json = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+    state_file::write(&path, json.as_bytes())
+}
+
+impl DaemonManager {
+    /// Usage of every running instance and of the filesystems they are on, measured now.
+    /// Walks every store, so call it off the async threads.
+    pub fn storage_usage(&self) -> StorageReport {
+        let instances: Vec<InstanceStorage> = self
+            .list()
+            .iter()
+            .map(|d| measure(Some(d.pid), Path::new(&d.data_dir)))
+            .collect();
+        for usage in &instances {
+            self.usage.insert(usage);
+        }
+        let filesystems = filesystems(&instances);
+        StorageReport { instances, filesystems }
+    }
+
+    /// Quota for an instance about to start on `data_dir`: `requested`, else the one in its
+    /// config.json, else (for a new instance) a default that fits the filesystem. `None`
+    /// means unlimited. Errors when the combined quotas on the filesystem exceed its free
+    /// space and `strict` is set. Instances count with their last measured usage; nothing is
+    /// walked here.
+    pub(crate) fn plan_storage(&self, data_dir: &Path, requested: Option<u64>, strict: bool) -> Result<Option<u64>, String> {
+        let others: Vec<InstanceStorage> = self
+            .list()
+            .iter()
+            .filter(|d| Path::new(&d.data_dir) != data_dir)
+            .map(|d| self.usage.get(Some(d.pid), Path::new(&d.data_dir)))
+            .collect();
+        let mut planned = self.usage.get(None, data_dir);
+        if requested.is_some() {
+            planned.quota = requested;
+        } else if !data_dir.join("config.json").exists() {
+            planned.quota = Some(default_quota(data_dir, &others));
+        }
+        let (quota, planned_device) = (planned.quota, planned.device);
+
+        let mut all = others;
+        all.push(planned);
+        if let Some(fs) = filesystems(&all).into_iter().find(|fs| fs.device == planned_device) {
+            if fs.overcommitted {
+                let msg = format!(
+                    "Storage quotas on the filesystem of {} need {} more bytes but only {} are free",
+                    data_dir.display(),
+                    fs.committed_bytes,
+                    fs.available_bytes
+                );
+                if strict {
+                    return Err(msg);
+                }
+                warn!("{}", msg);
+            }
+        }
+        Ok(quota)
+    }
+}
@@ -0,0 +1,90 @@
+//! Typed access to swarm-level operations of an instance.
+//!
+//! The desktop crate doesn't own the swarm; CraftOBJ exposes peer operations as unprefixed
+//! methods on its IPC handler. Everything on the Rust side that needs to look at or steer
+//! connections goes through `SwarmControl`, so the method names live in one place. Each
+//! name is also listed in `compat::CORE_METHODS`, and a "method not found" answer is
+//! reported as a stale table.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use craftec_ipc::server::IpcHandler;
+use serde_json::{json, Value};
+
+use crate::compat;
+use crate::nat::{NatStatus, Reachability};
+
+#[derive(Clone)]
+pub struct SwarmControl(Arc<dyn IpcHandler>);
+
+impl SwarmControl {
+    pub fn new(handler: Arc<dyn IpcHandler>) -> Self {
+        Self(handler)
+    }
+
+    async fn call(&self, method: &str, params: Option<Value>) -> Result<Value, String> {
+        let result = self.0.handle(method, params).await;
+        compat::check_core(method, &result);
+        result
+    }
+
+    /// Peer IDs of current connections.
+    pub async fn connected_peers(&self) -> Result<HashSet<String>, String> {
+        let v = self.call("connected_peers", None).await?;
+        Ok(v.get("peers")
+            .and_then(|p| p.as_array())
+            .map(|peers| peers.iter().filter_map(|p| p.as_str().map(str::to_string)).collect())
+            .unwrap_or_default())
+    }
+
+    /// Dial a multiaddr (with or without a trailing `/p2p/<peer_id>`).
+    pub async fn dial(&self, addr: &str) -> Result<Value, String> {
+        self.call("connect", Some(json!({ "addr": addr }))).await
+    }
+
+    /// Close all connections to a peer.
+    pub async fn disconnect(&self, peer_id: &str) -> Result<Value, String> {
+        self.call("disconnect_peer", Some(json!({ "peer_id": peer_id }))).await
+    }
+
+    /// Refuse connections to and from a peer until `unban`.
+    pub async fn ban(&self, peer_id: &str) -> Result<Value, String> {
+        self.call("ban_peer", Some(json!({ "peer_id": peer_id }))).await
+    }
+
+    pub async fn unban(&self, peer_id: &str) -> Result<Value, String> {
+        self.call("unban_peer", Some(json!({ "peer_id": peer_id }))).await
+    }
+
+    /// Cap inbound plus outbound swarm traffic; `None` lifts the cap.
+    pub async fn set_bandwidth_limit(&self, bytes_per_sec: Option<u64>) -> Result<Value, String> {
+        self.call("set_bandwidth_limit", Some(json!({ "bytes_per_sec": bytes_per_sec }))).await
+    }
+
+    /// Total bytes received and sent by the swarm since it started.
+    pub async fn traffic(&self) -> Result<(u64, u64), String> {
+        let v = self.call("bandwidth_stats", None).await?;
+        let field = |k: &str| v.get(k).and_then(|n| n.as_u64()).unwrap_or(0);
+        Ok((field("bytes_in"), field("bytes_out")))
+    }
+
+    /// AutoNAT verdict and confirmed external addresses.
+    pub async fn nat_status(&self) -> Result<NatStatus, String> {
+        let v = self.call("nat_status", None).await?;
+        let reachability = match v.get("reachability").and_then(|r| r.as_str()) {
+            Some("public") => Reachability::Public,
+            Some("private") => Reachability::Private,
+            _ => Reachability::Unknown,
+        };
+        let external_addrs = v
+            .get("external_addrs")
+            .and_then(|a| a.as_array())
+            .map(|addrs| addrs.iter().filter_map(|a| a.as_str().map(str::to_string)).collect())
+            .unwrap_or_default();
+        Ok(NatStatus {
+            reachability,
+            external_addrs,
+        })
+    }
+}
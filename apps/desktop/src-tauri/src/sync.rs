@@ -0,0 +1,568 @@
+//! Watched-folder sync.
+//!
+//! An instance can keep local folders published. Each folder is watched recursively
+//! (inotify on Linux, through `notify`). Once a burst of changes has been quiet for the
+//! debounce interval, the folder is rescanned. Files are matched against the last scan by
+//! size and mtime, so only changed files are hashed, and only files whose hash changed are
+//! published again. The path → CID map lives in `{data_dir}/sync.json`. Each folder can
+//! also mirror the map into the VFS and drop the local copy of versions it replaced. A
+//! version is only dropped once no path in any folder, and no VFS file, maps to it any
+//! more, so renames and duplicate files keep their content.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::api_keys::now_secs;
+use crate::content_ctl::ContentControl;
+use crate::daemon_manager::DaemonManager;
+use crate::directory::{self, IgnoreRules};
+use crate::resources::IoLimiter;
+use crate::state_file;
+use crate::vfs::{self, Vfs};
+
+const DEFAULT_DEBOUNCE_MS: u64 = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncFolderRequest {
+    pub local_path: String,
+    /// Keep the folder's files linked into the VFS under this directory
+    #[serde(default)]
+    pub vfs_path: Option<String>,
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Drop the local copy of a file's previous version once the new one is published
+    #[serde(default)]
+    pub unpin_superseded: bool,
+    #[serde(default)]
+    pub debounce_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncedFile {
+    pub cid: String,
+    pub size: u64,
+    pub modified_at: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FolderRecord {
+    id: u64,
+    #[serde(flatten)]
+    config: SyncFolderRequest,
+    /// Relative path → last published version
+    #[serde(default)]
+    files: BTreeMap<String, SyncedFile>,
+    #[serde(default)]
+    last_sync_at: Option<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SyncBook {
+    next_id: u64,
+    folders: Vec<FolderRecord>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    /// Instance not running yet
+    #[default]
+    Stopped,
+    Idle,
+    Scanning,
+    Error,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncStatus {
+    pub id: u64,
+    pub local_path: String,
+    pub vfs_path: Option<String>,
+    pub state: SyncState,
+    pub files_tracked: usize,
+    /// Changed files left in the current scan
+    pub pending: usize,
+    /// Files published since the folder was added to this session
+    pub published: u64,
+    pub removed: u64,
+    pub last_sync_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// What scans need from the running instance.
+#[derive(Clone)]
+pub struct SyncContext {
+    pub content: ContentControl,
+    pub io: Option<Arc<IoLimiter>>,
+    pub vfs: Arc<Vfs>,
+}
+
+/// Folder watcher task and the channel that asks it to rescan.
+struct Running {
+    task: JoinHandle<()>,
+    rescan: mpsc::UnboundedSender<()>,
+}
+
+pub struct SyncManager {
+    path: PathBuf,
+    book: Mutex<SyncBook>,
+    status: Mutex<HashMap<u64, SyncStatus>>,
+    running: Mutex<HashMap<u64, Running>>,
+    ctx: Mutex<Option<(SyncContext, tokio::runtime::Handle)>>,
+}
+
+impl SyncManager {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join("sync.json");
+        let book: SyncBook = state_file::load(&path);
+        let status = book.folders.iter().map(|f| (f.id, initial_status(f))).collect();
+        Self {
+            path,
+            book: Mutex::new(book),
+            status: Mutex::new(status),
+            running: Mutex::new(HashMap::new()),
+            ctx: Mutex::new(None),
+        }
+    }
+
+    /// Start watching every configured folder. Called once the instance's handler is up.
+    pub fn start(self: &Arc<Self>, ctx: SyncContext) {
+        *self.ctx.lock().unwrap() = Some((ctx, tokio::runtime::Handle::current()));
+        let ids: Vec<u64> = self.book.lock().unwrap().folders.iter().map(|f| f.id).collect();
+        for id in ids {
+            self.spawn(id);
+        }
+    }
+
+    /// Abort every watcher. Returns their tasks, to wait until they have ended.
+    pub fn stop_all(&self) -> Vec<JoinHandle<()>> {
+        let tasks: Vec<JoinHandle<()>> = self.running.lock().unwrap().drain().map(|(_, r)| r.task).collect();
+        for task in &tasks {
+            task.abort();
+        }
+        tasks
+    }
+
+    pub fn statuses(&self) -> Vec<SyncStatus> {
+        let mut all: Vec<SyncStatus> = self.status.lock().unwrap().values().cloned().collect();
+        all.sort_by_key(|s| s.id);
+        all
+    }
+
+    pub fn files(&self, id: u64) -> Result<BTreeMap<String, SyncedFile>, String> {
+        let book = self.book.lock().unwrap();
+        let folder = book.folders.iter().find(|f| f.id == id).ok_or_else(|| format!("No sync folder {}", id))?;
+        Ok(folder.files.clone())
+    }
+
+    pub fn add(self: &Arc<Self>, req: SyncFolderRequest) -> Result<SyncStatus, String> {
+        let local = std::fs::canonicalize(&req.local_path)
+            .map_err(|e| format!("Cannot sync '{}': {}", req.local_path, e))?;
+        if !local.is_dir() {
+            return Err(format!("'{}' is not a directory", req.local_path));
+        }
+        let vfs_path = req.vfs_path.as_deref().map(vfs::normalize).transpose()?;
+        let local_path = local.to_string_lossy().into_owned();
+        let status = {
+            let mut book = self.book.lock().unwrap();
+            if let Some(existing) = book.folders.iter().find(|f| overlaps(&f.config.local_path, &local_path)) {
+                return Err(format!("'{}' overlaps sync folder '{}'", local_path, existing.config.local_path));
+            }
+            book.next_id += 1;
+            let record = FolderRecord {
+                id: book.next_id,
+                config: SyncFolderRequest {
+                    local_path,
+                    vfs_path,
+                    ..req
+                },
+                files: BTreeMap::new(),
+                last_sync_at: None,
+            };
+            let status = initial_status(&record);
+            book.folders.push(record);
+            status
+        };
+        self.save()?;
+        self.status.lock().unwrap().insert(status.id, status.clone());
+        self.spawn(status.id);
+        Ok(self.status(status.id).unwrap_or(status))
+    }
+
+    /// Stop syncing a folder. What was published stays published.
+    pub fn remove(&self, id: u64) -> Result<(), String> {
+        {
+            let mut book = self.book.lock().unwrap();
+            let before = book.folders.len();
+            book.folders.retain(|f| f.id != id);
+            if book.folders.len() == before {
+                return Err(format!("No sync folder {}", id));
+            }
+        }
+        if let Some(running) = self.running.lock().unwrap().remove(&id) {
+            running.task.abort();
+        }
+        self.status.lock().unwrap().remove(&id);
+        self.save()
+    }
+
+    pub fn rescan(&self, id: u64) -> Result<(), String> {
+        let running = self.running.lock().unwrap();
+        let folder = running
+            .get(&id)
+            .ok_or_else(|| format!("Sync folder {} is not being watched", id))?;
+        let _ = folder.rescan.send(());
+        Ok(())
+    }
+
+    fn status(&self, id: u64) -> Option<SyncStatus> {
+        self.status.lock().unwrap().get(&id).cloned()
+    }
+
+    fn set_status(&self, id: u64, f: impl FnOnce(&mut SyncStatus)) {
+        if let Some(status) = self.status.lock().unwrap().get_mut(&id) {
+            f(status);
+        }
+    }
+
+    fn spawn(self: &Arc<Self>, id: u64) {
+        let Some((ctx, rt)) = self.ctx.lock().unwrap().clone() else {
+            return;
+        };
+        let (rescan, rescan_rx) = mpsc::unbounded_channel();
+        let mut running = self.running.lock().unwrap();
+        let task = rt.spawn(watch_folder(Arc::clone(self), id, ctx, rescan_rx));
+        running.insert(
+            id,
+            Running {
+                task,
+                rescan,
+            },
+        );
+    }
+
+    /// Bring the published state of a folder in line with the disk.
+    async fn scan(&self, id: u64, ctx: &SyncContext) -> Result<(), String> {
+        let (config, known) = {
+            let book = self.book.lock().unwrap();
+            let folder = book.folders.iter().find(|f| f.id == id).ok_or_else(|| format!("No sync folder {}", id))?;
+            (folder.config.clone(), folder.files.clone())
+        };
+        self.set_status(id, |s| s.state = SyncState::Scanning);
+
+        let root = PathBuf::from(&config.local_path);
+        let rules = IgnoreRules::for_root(&root, &config.ignore);
+        let walk_root = root.clone();
+        let (local, _) = tokio::task::spawn_blocking(move || directory::walk(&walk_root, &rules))
+            .await
+            .map_err(|e| format!("Folder scan failed: {}", e))??;
+
+        let changed: Vec<_> = local
+            .iter()
+            .filter(|f| !matches!(known.get(&f.rel), Some(k) if k.size == f.size && k.modified_at == f.modified_at))
+            .collect();
+        let present: HashSet<&str> = local.iter().map(|f| f.rel.as_str()).collect();
+        let gone: Vec<String> = known.keys().filter(|rel| !present.contains(rel.as_str())).cloned().collect();
+        self.set_status(id, |s| s.pending = changed.len());
+
+        let mut errors = Vec::new();
+        for file in changed {
+            let result = self.sync_file(id, ctx, &config, known.get(&file.rel), file).await;
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", file.rel, e));
+            }
+            self.set_status(id, |s| s.pending = s.pending.saturating_sub(1));
+        }
+        for rel in gone {
+            let previous = self.record(id, &rel, None);
+            if let Some(vfs_root) = &config.vfs_path {
+                let _ = ctx.vfs.remove(&vfs::join(vfs_root, &rel), false);
+            }
+            if let (true, Some(prev)) = (config.unpin_superseded, previous) {
+                if !self.in_use(&prev.cid, ctx) {
+                    if let Err(e) = ctx.content.delete_local(&prev.cid).await {
+                        warn!("Failed to drop removed file {} ({}): {}", rel, prev.cid, e);
+                    }
+                }
+            }
+            self.set_status(id, |s| s.removed += 1);
+        }
+
+        let now = now_secs();
+        if let Some(folder) = self.book.lock().unwrap().folders.iter_mut().find(|f| f.id == id) {
+            folder.last_sync_at = Some(now);
+        }
+        self.save()?;
+        let files_tracked = self.files(id).map(|f| f.len()).unwrap_or(0);
+        self.set_status(id, |s| {
+            s.files_tracked = files_tracked;
+            s.last_sync_at = Some(now);
+            s.state = if errors.is_empty() { SyncState::Idle } else { SyncState::Error };
+            s.last_error = errors.first().cloned();
+        });
+        if !errors.is_empty() {
+            warn!("Sync of {} finished with {} errors", config.local_path, errors.len());
+        }
+        Ok(())
+    }
+
+    async fn sync_file(
+        &self,
+        id: u64,
+        ctx: &SyncContext,
+        config: &SyncFolderRequest,
+        known: Option<&SyncedFile>,
+        file: &directory::LocalFile,
+    ) -> Result<(), String> {
+        let sha256 = directory::hash_file(&file.abs, ctx.io.as_deref()).await?;
+        if let Some(k) = known.filter(|k| k.sha256 == sha256) {
+            // Touched but not changed
+            let same = SyncedFile {
+                modified_at: file.modified_at,
+                ..k.clone()
+            };
+            self.record(id, &file.rel, Some(same));
+            return Ok(());
+        }
+        let published = ctx.content.publish(&file.abs).await?;
+        info!("Synced {} as {}", file.abs.display(), published.cid);
+        let previous = self.record(
+            id,
+            &file.rel,
+            Some(SyncedFile {
+                cid: published.cid.clone(),
+                size: file.size,
+                modified_at: file.modified_at,
+                sha256,
+            }),
+        );
+        if let Some(vfs_root) = &config.vfs_path {
+            ctx.vfs
+                .put_file(&vfs::join(vfs_root, &file.rel), &published.cid, file.size, Some(file.mode))?;
+        }
+        self.set_status(id, |s| s.published += 1);
+        if let (true, Some(prev)) = (config.unpin_superseded, previous) {
+            if !self.in_use(&prev.cid, ctx) {
+                ctx.content.delete_local(&prev.cid).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a tracked path in any folder, or a VFS file, still maps to `cid`.
+    fn in_use(&self, cid: &str, ctx: &SyncContext) -> bool {
+        let book = self.book.lock().unwrap();
+        book.folders.iter().any(|f| f.files.values().any(|file| file.cid == cid)) || ctx.vfs.references(cid)
+    }
+
+    /// Set or clear a file's mapping and return the old one.
+    fn record(&self, id: u64, rel: &str, file: Option<SyncedFile>) -> Option<SyncedFile> {
+        let mut book = self.book.lock().unwrap();
+        let folder = book.folders.iter_mut().find(|f| f.id == id)?;
+        match file {
+            Some(file) => folder.files.insert(rel.to_string(), file),
+            None => folder.files.remove(rel),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(&*self.book.lock().unwrap())
+            .map_err(|e| format!("Failed to serialize sync folders: {}", e))?;
+        state_file::write(&self.path, json.as_bytes())
+    }
+}
+
+fn initial_status(folder: &FolderRecord) -> SyncStatus {
+    SyncStatus {
+        id: folder.id,
+        local_path: folder.config.local_path.clone(),
+        vfs_path: folder.config.vfs_path.clone(),
+        files_tracked: folder.files.len(),
+        last_sync_at: folder.last_sync_at,
+        ..Default::default()
+    }
+}
+
+/// One folder inside (or equal to) the other.
+fn overlaps(a: &str, b: &str) -> bool {
+    let (a, b) = (Path::new(a), Path::new(b));
+    a.starts_with(b) || b.starts_with(a)
+}
+
+/// Scan once, then rescan after each settled burst of file events until aborted.
+async fn watch_folder(sync: Arc<SyncManager>, id: u64, ctx: SyncContext, mut rescan: mpsc::UnboundedReceiver<()>) {
+    let Some(status) = sync.status(id) else { return };
+    let debounce = sync
+        .book
+        .lock()
+        .unwrap()
+        .folders
+        .iter()
+        .find(|f| f.id == id)
+        .and_then(|f| f.config.debounce_ms)
+        .unwrap_or(DEFAULT_DEBOUNCE_MS);
+    let debounce = Duration::from_millis(debounce);
+
+    let (tx, mut changes) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // Our own reads while hashing show up as access events
+        if event.is_ok_and(|e| !matches!(e.kind, notify::EventKind::Access(_))) {
+            let _ = tx.send(());
+        }
+    });
+    // Held for as long as the task runs; dropping it stops the watch
+    let _watcher = match watcher.and_then(|mut w| w.watch(Path::new(&status.local_path), RecursiveMode::Recursive).map(|_| w)) {
+        Ok(w) => w,
+        Err(e) => {
+            warn!("Cannot watch {}: {}", status.local_path, e);
+            sync.set_status(id, |s| {
+                s.state = SyncState::Error;
+                s.last_error = Some(format!("Cannot watch folder: {}", e));
+            });
+            return;
+        }
+    };
+    info!("Watching {} for changes", status.local_path);
+
+    loop {
+        if let Err(e) = sync.scan(id, &ctx).await {
+            sync.set_status(id, |s| {
+                s.state = SyncState::Error;
+                s.last_error = Some(e);
+            });
+        }
+        tokio::select! {
+            Some(()) = changes.recv() => {}
+            Some(()) = rescan.recv() => {}
+            else => break,
+        }
+        // Wait for the burst to settle
+        while let Ok(Some(())) = tokio::time::timeout(debounce, changes.recv()).await {}
+    }
+}
+
+impl DaemonManager {
+    pub fn list_sync_folders(&self, pid: u32) -> Result<Vec<SyncStatus>, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.sync))?.statuses())
+    }
+
+    pub fn add_sync_folder(&self, pid: u32, req: SyncFolderRequest) -> Result<SyncStatus, String> {
+        let status = self.with_daemon(pid, |d| Arc::clone(&d.sync))?.add(req)?;
+        info!("Instance {} syncing {} (folder {})", pid, status.local_path, status.id);
+        Ok(status)
+    }
+
+    pub fn remove_sync_folder(&self, pid: u32, id: u64) -> Result<(), String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.sync))?.remove(id)
+    }
+
+    pub fn rescan_sync_folder(&self, pid: u32, id: u64) -> Result<(), String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.sync))?.rescan(id)
+    }
+
+    pub fn synced_files(&self, pid: u32, id: u64) -> Result<BTreeMap<String, SyncedFile>, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.sync))?.files(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+
+    use craftec_ipc::server::IpcHandler;
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    /// Content-addressed stand-in for CraftOBJ that records `delete_local` calls.
+    #[derive(Default)]
+    struct FakeStore {
+        deleted: Mutex<Vec<String>>,
+    }
+
+    impl IpcHandler for FakeStore {
+        fn handle(
+            &self,
+            method: &str,
+            params: Option<Value>,
+        ) -> Pin<Box<dyn Future<Output = Result<Value, String>> + Send + '_>> {
+            let param = |k: &str| params.as_ref().and_then(|p| p.get(k)).and_then(|v| v.as_str()).map(str::to_string);
+            let result = match method {
+                "publish" => std::fs::read(param("path").unwrap_or_default())
+                    .map(|bytes| json!({ "cid": hex::encode(Sha256::digest(bytes)) }))
+                    .map_err(|e| e.to_string()),
+                "delete_local" => {
+                    self.deleted.lock().unwrap().push(param("cid").unwrap_or_default());
+                    Ok(Value::Null)
+                }
+                other => Err(format!("Method not found: {}", other)),
+            };
+            Box::pin(async move { result })
+        }
+    }
+
+    fn cid_of(bytes: &[u8]) -> String {
+        hex::encode(Sha256::digest(bytes))
+    }
+
+    #[test]
+    fn superseded_content_is_kept_while_another_path_maps_to_it() {
+        let root = std::env::temp_dir().join(format!("craftstudio-sync-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (data, folder) = (root.join("data"), root.join("folder"));
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("a.txt"), b"same").unwrap();
+
+        let sync = Arc::new(SyncManager::open(&data));
+        let id = sync
+            .add(SyncFolderRequest {
+                local_path: folder.to_string_lossy().into_owned(),
+                vfs_path: None,
+                ignore: Vec::new(),
+                unpin_superseded: true,
+                debounce_ms: None,
+            })
+            .unwrap()
+            .id;
+        let store = Arc::new(FakeStore::default());
+        let ctx = SyncContext {
+            content: ContentControl::new(store.clone()),
+            io: None,
+            vfs: Arc::new(Vfs::open(&data)),
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let scan = || rt.block_on(sync.scan(id, &ctx)).unwrap();
+        scan();
+
+        // A rename republishes the same CID under the new path
+        std::fs::rename(folder.join("a.txt"), folder.join("b.txt")).unwrap();
+        scan();
+        assert!(store.deleted.lock().unwrap().is_empty());
+        assert_eq!(sync.files(id).unwrap().keys().collect::<Vec<_>>(), ["b.txt"]);
+
+        // Editing one of two identical files leaves the other's content alone
+        std::fs::write(folder.join("c.txt"), b"same").unwrap();
+        scan();
+        std::fs::write(folder.join("c.txt"), b"changed").unwrap();
+        scan();
+        assert!(store.deleted.lock().unwrap().is_empty());
+
+        // Once nothing maps to it, it is dropped
+        std::fs::remove_file(folder.join("b.txt")).unwrap();
+        scan();
+        assert_eq!(*store.deleted.lock().unwrap(), [cid_of(b"same")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+//! Swarm listen addresses.
+//!
+//! An instance can listen on several multiaddrs at once: TCP and QUIC-v1 over IPv4 or IPv6.
+//! WebSocket listening is out of scope: `craftec_network` builds the swarm without a WebSocket
+//! transport and has no hook to add one, so `/ws` addresses are rejected instead of failing
+//! when the swarm binds them. Each address is validated before the swarm starts.
+//! The addresses the swarm actually bound are picked up from its `listening_on` events and
+//! reported in `DaemonInstance::bound_addrs`.
+
+use std::sync::{Arc, Mutex};
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+pub type BoundAddrs = Arc<Mutex<Vec<String>>>;
+
+/// Check that `addr` is a listen address the swarm's transports can bind.
+pub fn validate_listen_addr(addr: &str) -> Result<Multiaddr, String> {
+    let parsed: Multiaddr = addr.parse().map_err(|e| format!("Invalid listen addr '{}': {}", addr, e))?;
+    let protocols: Vec<Protocol> = parsed.iter().collect();
+    let ok = match protocols.as_slice() {
+        [Protocol::Ip4(_) | Protocol::Ip6(_), rest @ ..] => matches!(
+            rest,
+            [Protocol::Tcp(_)] | [Protocol::Udp(_), Protocol::QuicV1]
+        ),
+        _ => false,
+    };
+    if ok {
+        return Ok(parsed);
+    }
+    if protocols.iter().any(|p| matches!(p, Protocol::Quic)) {
+        return Err(format!("'{}' uses draft QUIC; use /quic-v1", addr));
+    }
+    if protocols.iter().any(|p| matches!(p, Protocol::Ws(_) | Protocol::Wss(_))) {
+        return Err(format!("'{}' needs a WebSocket transport, which the swarm does not have", addr));
+    }
+    Err(format!(
+        "Unsupported listen addr '{}' (expected /ip4|ip6/.../tcp/<port>, /ip4|ip6/.../udp/<port>/quic-v1)",
+        addr
+    ))
+}
+
+/// Validate every address; an empty list is an error.
+pub fn validate_listen_addrs(addrs: &[String]) -> Result<Vec<Multiaddr>, String> {
+    if addrs.is_empty() {
+        return Err("At least one listen address is required".to_string());
+    }
+    addrs.iter().map(|a| validate_listen_addr(a)).collect()
+}
+
+/// Accept `listen_addr` as a single string (older configs) or a list.
+pub fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(addr)) => Some(vec![addr]),
+        Some(OneOrMany::Many(addrs)) => Some(addrs),
+        None => None,
+    })
+}
+
+/// Record addresses from `listening_on` notifications until the bus closes.
+pub async fn track_bound(mut events: tokio::sync::broadcast::Receiver<String>, bound: BoundAddrs) {
+    loop {
+        match events.recv().await {
+            Ok(notification) => {
+                let Ok(v) = serde_json::from_str::<Value>(&notification) else {
+                    continue;
+                };
+                if v.get("method").and_then(|m| m.as_str()) != Some("listening_on") {
+                    continue;
+                }
+                if let Some(addr) = v.pointer("/params/address").and_then(|a| a.as_str()) {
+                    let mut bound = bound.lock().unwrap();
+                    if !bound.iter().any(|b| b == addr) {
+                        bound.push(addr.to_string());
+                    }
+                }
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_tcp_and_quic_v1_on_both_families() {
+        for addr in [
+            "/ip4/0.0.0.0/tcp/4001",
+            "/ip6/::/tcp/4001",
+            "/ip4/127.0.0.1/udp/0/quic-v1",
+            "/ip6/::1/udp/4001/quic-v1",
+        ] {
+            assert!(validate_listen_addr(addr).is_ok(), "{} should be accepted", addr);
+        }
+    }
+
+    #[test]
+    fn rejects_other_addresses() {
+        for (addr, reason) in [
+            ("/ip4/0.0.0.0/tcp/4001/ws", "WebSocket"),
+            ("/ip6/::/tcp/4001/wss", "WebSocket"),
+            ("/ip4/0.0.0.0/udp/4001/quic", "draft QUIC"),
+            ("/ip4/0.0.0.0/udp/4001", "Unsupported"),
+            ("/dns4/example.com/tcp/4001", "Unsupported"),
+            ("/tcp/4001", "Unsupported"),
+            ("not a multiaddr", "Invalid"),
+        ] {
+            let err = validate_listen_addr(addr).unwrap_err();
+            assert!(err.contains(reason), "{}: {}", addr, err);
+        }
+        assert!(validate_listen_addrs(&[]).is_err());
+    }
+}
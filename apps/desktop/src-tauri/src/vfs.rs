@@ -0,0 +1,576 @@
+//! Virtual filesystem over published content.
+//!
+//! Each instance keeps a directory tree in `{data_dir}/vfs.json`. Entries are directories,
+//! or files that point at a CraftOBJ content ID, with a size, Unix mode bits and
+//! timestamps. Paths are absolute and `/`-separated. The root always exists and is not
+//! stored.
+//!
+//! `snapshot` publishes the whole tree as a JSON content object. Each snapshot names the
+//! CID of the one before it, so versions form a chain, and `restore` can load any of them
+//! back, including one taken on another node.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::api_keys::now_secs;
+use crate::content_ctl::ContentControl;
+use crate::daemon_manager::DaemonManager;
+use crate::state_file;
+
+const ROOT: &str = "/";
+const DIR_MODE: u32 = 0o755;
+const FILE_MODE: u32 = 0o644;
+/// `format` field of published snapshots
+const SNAPSHOT_FORMAT: &str = "craftvfs-snapshot/1";
+/// Largest snapshot `restore` will read
+const MAX_SNAPSHOT_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VfsEntry {
+    pub path: String,
+    pub name: String,
+    pub kind: EntryKind,
+    /// File size; for directories, the total of all files below
+    pub size: u64,
+    pub cid: Option<String>,
+    /// Unix permission bits
+    pub mode: u32,
+    pub created_at: u64,
+    pub modified_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VfsSnapshot {
+    pub version: u64,
+    pub cid: String,
+    pub created_at: u64,
+    pub files: u64,
+    pub dirs: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VfsStats {
+    pub files: u64,
+    pub dirs: u64,
+    pub total_bytes: u64,
+    /// Size of `vfs.json`
+    pub db_bytes: u64,
+    pub latest_snapshot: Option<VfsSnapshot>,
+}
+
+/// Published form of the tree.
+#[derive(Serialize, Deserialize)]
+struct SnapshotDoc {
+    format: String,
+    version: u64,
+    previous: Option<String>,
+    created_at: u64,
+    entries: Vec<VfsEntry>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct VfsTree {
+    #[serde(default)]
+    entries: BTreeMap<String, VfsEntry>,
+    #[serde(default)]
+    snapshots: Vec<VfsSnapshot>,
+}
+
+impl VfsTree {
+    fn is_dir(&self, path: &str) -> bool {
+        path == ROOT || self.entries.get(path).is_some_and(|e| e.kind == EntryKind::Directory)
+    }
+
+    /// Entries strictly below `dir`.
+    fn descendants<'a>(&'a self, dir: &str) -> impl Iterator<Item = &'a VfsEntry> + 'a {
+        let prefix = child_prefix(dir);
+        self.entries
+            .range(prefix.clone()..)
+            .take_while(move |(k, _)| k.starts_with(&prefix))
+            .map(|(_, e)| e)
+    }
+
+    /// Entry as reported to callers, with directory sizes summed.
+    fn reported(&self, entry: &VfsEntry) -> VfsEntry {
+        let mut entry = entry.clone();
+        if entry.kind == EntryKind::Directory {
+            entry.size = self.descendants(&entry.path).map(|e| e.size).sum();
+        }
+        entry
+    }
+
+    fn touch(&mut self, path: &str, now: u64) {
+        if let Some(entry) = self.entries.get_mut(path) {
+            entry.modified_at = now;
+        }
+    }
+
+    fn totals(&self) -> (u64, u64, u64) {
+        let files = self.entries.values().filter(|e| e.kind == EntryKind::File);
+        let (count, bytes) = files.fold((0, 0), |(n, b), e| (n + 1, b + e.size));
+        (count, self.entries.len() as u64 - count, bytes)
+    }
+}
+
+pub struct Vfs {
+    path: PathBuf,
+    snapshot_dir: PathBuf,
+    tree: Mutex<VfsTree>,
+    /// Serializes `snapshot` so versions are numbered in order
+    snapshotting: tokio::sync::Mutex<()>,
+}
+
+impl Vfs {
+    pub fn open(data_dir: &Path) -> Self {
+        let path = data_dir.join("vfs.json");
+        let tree = state_file::load(&path);
+        Self {
+            path,
+            snapshot_dir: data_dir.join("vfs-snapshots"),
+            tree: Mutex::new(tree),
+            snapshotting: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Children of a directory, directories first, then by name.
+    pub fn list(&self, dir: &str) -> Result<Vec<VfsEntry>, String> {
+        let dir = normalize(dir)?;
+        let tree = self.tree.lock().unwrap();
+        if !tree.is_dir(&dir) {
+            return Err(format!("'{}' is not a directory", dir));
+        }
+        let mut children: Vec<VfsEntry> = tree
+            .descendants(&dir)
+            .filter(|e| parent_of(&e.path) == dir)
+            .map(|e| tree.reported(e))
+            .collect();
+        children.sort_by(|a, b| (a.kind != EntryKind::Directory, &a.name).cmp(&(b.kind != EntryKind::Directory, &b.name)));
+        Ok(children)
+    }
+
+    pub fn stat(&self, path: &str) -> Result<VfsEntry, String> {
+        let path = normalize(path)?;
+        let tree = self.tree.lock().unwrap();
+        if path == ROOT {
+            let root = VfsEntry {
+                path: ROOT.to_string(),
+                name: String::new(),
+                kind: EntryKind::Directory,
+                size: 0,
+                cid: None,
+                mode: DIR_MODE,
+                created_at: 0,
+                modified_at: 0,
+            };
+            return Ok(tree.reported(&root));
+        }
+        tree.entries
+            .get(&path)
+            .map(|e| tree.reported(e))
+            .ok_or_else(|| format!("'{}' does not exist", path))
+    }
+
+    /// Create a directory. With `parents`, missing ancestors are created and an existing
+    /// directory is not an error.
+    pub fn mkdir(&self, path: &str, parents: bool) -> Result<VfsEntry, String> {
+        let path = normalize(path)?;
+        if path == ROOT {
+            return Err("The root directory already exists".to_string());
+        }
+        let mut tree = self.tree.lock().unwrap();
+        if tree.entries.contains_key(&path) {
+            if parents && tree.is_dir(&path) {
+                return Ok(tree.reported(&tree.entries[&path]));
+            }
+            return Err(format!("'{}' already exists", path));
+        }
+        let parent = parent_of(&path);
+        if parents {
+            create_dirs(&mut tree, &parent)?;
+        } else if !tree.is_dir(&parent) {
+            return Err(format!("Parent directory '{}' does not exist", parent));
+        }
+        let now = now_secs();
+        let entry = new_entry(&path, EntryKind::Directory, 0, None, DIR_MODE, now);
+        tree.entries.insert(path.clone(), entry.clone());
+        tree.touch(&parent, now);
+        self.save(&tree)?;
+        Ok(entry)
+    }
+
+    /// Point `path` at published content, creating parent directories as needed. Replacing
+    /// a file keeps its creation time and mode. Returns the CID the file pointed at before.
+    pub fn put_file(&self, path: &str, cid: &str, size: u64, mode: Option<u32>) -> Result<Option<String>, String> {
+        let path = normalize(path)?;
+        if path == ROOT {
+            return Err("Cannot replace the root directory".to_string());
+        }
+        let mut tree = self.tree.lock().unwrap();
+        let parent = parent_of(&path);
+        create_dirs(&mut tree, &parent)?;
+        let now = now_secs();
+        let previous = match tree.entries.get(&path) {
+            Some(e) if e.kind == EntryKind::Directory => return Err(format!("'{}' is a directory", path)),
+            Some(e) => Some(e.clone()),
+            None => None,
+        };
+        let mut entry = new_entry(&path, EntryKind::File, size, Some(cid.to_string()), FILE_MODE, now);
+        if let Some(prev) = &previous {
+            entry.created_at = prev.created_at;
+            entry.mode = prev.mode;
+        }
+        if let Some(mode) = mode {
+            entry.mode = mode & 0o777;
+        }
+        tree.entries.insert(path, entry);
+        tree.touch(&parent, now);
+        self.save(&tree)?;
+        Ok(previous.and_then(|p| p.cid).filter(|old| old != cid))
+    }
+
+    /// Move an entry (and everything below it) to a new path. The destination's parent must
+    /// exist and the destination itself must not.
+    pub fn move_entry(&self, from: &str, to: &str) -> Result<VfsEntry, String> {
+        let from = normalize(from)?;
+        let to = normalize(to)?;
+        if from == ROOT || to == ROOT {
+            return Err("Cannot move the root directory".to_string());
+        }
+        if from == to {
+            return self.stat(&from);
+        }
+        if to.starts_with(&child_prefix(&from)) {
+            return Err(format!("Cannot move '{}' into itself", from));
+        }
+        let mut tree = self.tree.lock().unwrap();
+        if !tree.entries.contains_key(&from) {
+            return Err(format!("'{}' does not exist", from));
+        }
+        if tree.entries.contains_key(&to) {
+            return Err(format!("'{}' already exists", to));
+        }
+        let to_parent = parent_of(&to);
+        if !tree.is_dir(&to_parent) {
+            return Err(format!("Parent directory '{}' does not exist", to_parent));
+        }
+
+        let moved: Vec<String> = std::iter::once(from.clone())
+            .chain(tree.descendants(&from).map(|e| e.path.clone()))
+            .collect();
+        for old in moved {
+            let mut entry = tree.entries.remove(&old).expect("listed above");
+            entry.path = format!("{}{}", to, &old[from.len()..]);
+            entry.name = name_of(&entry.path).to_string();
+            tree.entries.insert(entry.path.clone(), entry);
+        }
+        let now = now_secs();
+        tree.touch(&parent_of(&from), now);
+        tree.touch(&to_parent, now);
+        self.save(&tree)?;
+        Ok(tree.reported(&tree.entries[&to]))
+    }
+
+    /// Rename an entry within its directory.
+    pub fn rename(&self, path: &str, new_name: &str) -> Result<VfsEntry, String> {
+        if new_name.is_empty() || new_name.contains('/') || new_name == "." || new_name == ".." {
+            return Err(format!("Invalid name '{}'", new_name));
+        }
+        let path = normalize(path)?;
+        self.move_entry(&path, &join(&parent_of(&path), new_name))
+    }
+
+    /// Remove an entry. Non-empty directories need `recursive`. Returns the removed entries.
+    pub fn remove(&self, path: &str, recursive: bool) -> Result<Vec<VfsEntry>, String> {
+        let path = normalize(path)?;
+        if path == ROOT {
+            return Err("Cannot delete the root directory".to_string());
+        }
+        let mut tree = self.tree.lock().unwrap();
+        if !tree.entries.contains_key(&path) {
+            return Err(format!("'{}' does not exist", path));
+        }
+        let below: Vec<String> = tree.descendants(&path).map(|e| e.path.clone()).collect();
+        if !below.is_empty() && !recursive {
+            return Err(format!("Directory '{}' is not empty", path));
+        }
+        let mut removed = Vec::with_capacity(below.len() + 1);
+        for p in std::iter::once(path.clone()).chain(below) {
+            removed.extend(tree.entries.remove(&p));
+        }
+        tree.touch(&parent_of(&path), now_secs());
+        self.save(&tree)?;
+        Ok(removed)
+    }
+
+    /// Whether any file points at `cid`.
+    pub fn references(&self, cid: &str) -> bool {
+        self.tree.lock().unwrap().entries.values().any(|e| e.cid.as_deref() == Some(cid))
+    }
+
+    pub fn stats(&self) -> VfsStats {
+        let tree = self.tree.lock().unwrap();
+        let (files, dirs, total_bytes) = tree.totals();
+        VfsStats {
+            files,
+            dirs,
+            total_bytes,
+            db_bytes: std::fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0),
+            latest_snapshot: tree.snapshots.last().cloned(),
+        }
+    }
+
+    pub fn snapshots(&self) -> Vec<VfsSnapshot> {
+        self.tree.lock().unwrap().snapshots.clone()
+    }
+
+    /// Publish the current tree and record it as the next version.
+    pub async fn snapshot(&self, content: &ContentControl) -> Result<VfsSnapshot, String> {
+        let _serial = self.snapshotting.lock().await;
+        let (doc, totals) = {
+            let tree = self.tree.lock().unwrap();
+            let doc = SnapshotDoc {
+                format: SNAPSHOT_FORMAT.to_string(),
+                version: tree.snapshots.last().map_or(1, |s| s.version + 1),
+                previous: tree.snapshots.last().map(|s| s.cid.clone()),
+                created_at: now_secs(),
+                entries: tree.entries.values().cloned().collect(),
+            };
+            (doc, tree.totals())
+        };
+
+        std::fs::create_dir_all(&self.snapshot_dir)
+            .map_err(|e| format!("Failed to create {}: {}", self.snapshot_dir.display(), e))?;
+        let file = self.snapshot_dir.join(format!("v{}.json", doc.version));
+        let json = serde_json::to_vec_pretty(&doc).map_err(|e| format!("Failed to serialize VFS snapshot: {}", e))?;
+        std::fs::write(&file, json).map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+        let published = content.publish(&file).await?;
+
+        let (files, dirs, total_bytes) = totals;
+        let snapshot = VfsSnapshot {
+            version: doc.version,
+            cid: published.cid,
+            created_at: doc.created_at,
+            files,
+            dirs,
+            total_bytes,
+        };
+        let mut tree = self.tree.lock().unwrap();
+        tree.snapshots.push(snapshot.clone());
+        self.save(&tree)?;
+        Ok(snapshot)
+    }
+
+    /// Replace the tree with a published snapshot. Snapshots taken here are read from disk;
+    /// anything else is fetched. Snapshots over `MAX_SNAPSHOT_BYTES`, or whose entries don't
+    /// form a tree, are refused. Snapshot history is kept.
+    pub async fn restore(&self, content: &ContentControl, cid: &str) -> Result<usize, String> {
+        let local = self
+            .snapshots()
+            .into_iter()
+            .find(|s| s.cid == cid)
+            .map(|s| self.snapshot_dir.join(format!("v{}.json", s.version)))
+            .filter(|p| p.exists());
+        let file = match local {
+            Some(file) => file,
+            None => {
+                let listed = content.list().await.unwrap_or_default().into_iter().find(|e| e.content_id == cid).map(|e| e.total_size);
+                if let Some(size) = listed.filter(|s| *s > MAX_SNAPSHOT_BYTES) {
+                    return Err(format!("{} is {} bytes, too large for a VFS snapshot", cid, size));
+                }
+                std::fs::create_dir_all(&self.snapshot_dir)
+                    .map_err(|e| format!("Failed to create {}: {}", self.snapshot_dir.display(), e))?;
+                content.fetch(cid, &self.snapshot_dir.join(format!("{}.json", cid))).await?
+            }
+        };
+        let size = std::fs::metadata(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?.len();
+        if size > MAX_SNAPSHOT_BYTES {
+            let _ = std::fs::remove_file(&file);
+            return Err(format!("{} is {} bytes, too large for a VFS snapshot", cid, size));
+        }
+        let raw = std::fs::read(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let doc: SnapshotDoc =
+            serde_json::from_slice(&raw).map_err(|e| format!("{} is not a VFS snapshot: {}", cid, e))?;
+        if doc.format != SNAPSHOT_FORMAT {
+            return Err(format!("Unsupported VFS snapshot format '{}'", doc.format));
+        }
+        let entries = checked_tree(doc.entries).map_err(|e| format!("Snapshot {} is invalid: {}", cid, e))?;
+        let count = entries.len();
+        let mut tree = self.tree.lock().unwrap();
+        tree.entries = entries;
+        self.save(&tree)?;
+        Ok(count)
+    }
+
+    fn save(&self, tree: &VfsTree) -> Result<(), String> {
+        let json = serde_json::to_vec(tree).map_err(|e| format!("Failed to serialize VFS: {}", e))?;
+        state_file::write(&self.path, &json)
+    }
+}
+
+/// Key snapshot entries by normalized path, checking that each one's name matches its path,
+/// that only files carry a CID and that every parent is a directory in the same snapshot.
+fn checked_tree(list: Vec<VfsEntry>) -> Result<BTreeMap<String, VfsEntry>, String> {
+    let mut entries = BTreeMap::new();
+    for mut entry in list {
+        let path = normalize(&entry.path)?;
+        if path == ROOT {
+            return Err("the root directory is listed as an entry".to_string());
+        }
+        if entry.name != name_of(&path) {
+            return Err(format!("'{}' is named '{}'", path, entry.name));
+        }
+        if (entry.kind == EntryKind::File) != entry.cid.is_some() {
+            return Err(format!("'{}' is a {:?} with cid {:?}", path, entry.kind, entry.cid));
+        }
+        entry.path = path.clone();
+        if entries.insert(path.clone(), entry).is_some() {
+            return Err(format!("'{}' is listed twice", path));
+        }
+    }
+    for path in entries.keys() {
+        let parent = parent_of(path);
+        if parent != ROOT && entries.get(&parent).is_none_or(|e| e.kind != EntryKind::Directory) {
+            return Err(format!("'{}' has no parent directory", path));
+        }
+    }
+    Ok(entries)
+}
+
+/// Canonical absolute form: leading `/`, no trailing `/`, no empty, `.` or `..` components.
+pub fn normalize(path: &str) -> Result<String, String> {
+    if !path.starts_with('/') {
+        return Err(format!("VFS paths must be absolute: '{}'", path));
+    }
+    let mut out = String::new();
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        if part == "." || part == ".." || part.contains('\0') {
+            return Err(format!("Invalid VFS path '{}'", path));
+        }
+        out.push('/');
+        out.push_str(part);
+    }
+    Ok(if out.is_empty() { ROOT.to_string() } else { out })
+}
+
+pub fn join(dir: &str, name: &str) -> String {
+    format!("{}{}", child_prefix(dir), name)
+}
+
+fn child_prefix(dir: &str) -> String {
+    if dir == ROOT {
+        ROOT.to_string()
+    } else {
+        format!("{}/", dir)
+    }
+}
+
+fn parent_of(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => ROOT.to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+fn name_of(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or_default()
+}
+
+fn new_entry(path: &str, kind: EntryKind, size: u64, cid: Option<String>, mode: u32, now: u64) -> VfsEntry {
+    VfsEntry {
+        path: path.to_string(),
+        name: name_of(path).to_string(),
+        kind,
+        size,
+        cid,
+        mode,
+        created_at: now,
+        modified_at: now,
+    }
+}
+
+/// Create `dir` and any missing ancestors.
+fn create_dirs(tree: &mut VfsTree, dir: &str) -> Result<(), String> {
+    if tree.is_dir(dir) {
+        return Ok(());
+    }
+    if tree.entries.contains_key(dir) {
+        return Err(format!("'{}' is not a directory", dir));
+    }
+    create_dirs(tree, &parent_of(dir))?;
+    let now = now_secs();
+    tree.entries
+        .insert(dir.to_string(), new_entry(dir, EntryKind::Directory, 0, None, DIR_MODE, now));
+    Ok(())
+}
+
+impl DaemonManager {
+    pub fn vfs_list(&self, pid: u32, path: &str) -> Result<Vec<VfsEntry>, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.list(path)
+    }
+
+    pub fn vfs_stat(&self, pid: u32, path: &str) -> Result<VfsEntry, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.stat(path)
+    }
+
+    pub fn vfs_mkdir(&self, pid: u32, path: &str, parents: bool) -> Result<VfsEntry, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.mkdir(path, parents)
+    }
+
+    /// Link already-published content into the tree.
+    pub fn vfs_add_file(&self, pid: u32, path: &str, cid: &str, size: u64) -> Result<VfsEntry, String> {
+        let vfs = self.with_daemon(pid, |d| Arc::clone(&d.vfs))?;
+        vfs.put_file(path, cid, size, None)?;
+        vfs.stat(path)
+    }
+
+    pub fn vfs_move(&self, pid: u32, from: &str, to: &str) -> Result<VfsEntry, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.move_entry(from, to)
+    }
+
+    pub fn vfs_rename(&self, pid: u32, path: &str, new_name: &str) -> Result<VfsEntry, String> {
+        self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.rename(path, new_name)
+    }
+
+    /// Removes entries from the tree only; the content they point at stays published.
+    pub fn vfs_delete(&self, pid: u32, path: &str, recursive: bool) -> Result<usize, String> {
+        let removed = self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.remove(path, recursive)?;
+        info!("Instance {} removed {} VFS entries under {}", pid, removed.len(), path);
+        Ok(removed.len())
+    }
+
+    pub fn vfs_stats(&self, pid: u32) -> Result<VfsStats, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.stats())
+    }
+
+    pub fn vfs_snapshots(&self, pid: u32) -> Result<Vec<VfsSnapshot>, String> {
+        Ok(self.with_daemon(pid, |d| Arc::clone(&d.vfs))?.snapshots())
+    }
+
+    pub async fn vfs_snapshot(&self, pid: u32) -> Result<VfsSnapshot, String> {
+        let vfs = self.with_daemon(pid, |d| Arc::clone(&d.vfs))?;
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        let snapshot = vfs.snapshot(&ContentControl::new(ipc.handler)).await?;
+        info!("Instance {} published VFS snapshot v{} as {}", pid, snapshot.version, snapshot.cid);
+        Ok(snapshot)
+    }
+
+    pub async fn vfs_restore(&self, pid: u32, cid: &str) -> Result<usize, String> {
+        let vfs = self.with_daemon(pid, |d| Arc::clone(&d.vfs))?;
+        let (ipc, _, _) = self.running_ipc(pid)?;
+        let count = vfs.restore(&ContentControl::new(ipc.handler), cid).await?;
+        info!("Instance {} restored VFS snapshot {} ({} entries)", pid, cid, count);
+        Ok(count)
+    }
+}
@@ -0,0 +1,101 @@
+//! Multi-node flows on the sim network. Run with `cargo test --features sim`.
+
+use std::time::Duration;
+
+use craftstudio_lib::sim::{LinkShape, SimHarness};
+use serde_json::json;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().expect("tokio runtime")
+}
+
+#[test]
+fn content_published_on_one_node_is_fetched_by_another() {
+    runtime().block_on(async {
+        let sim = SimHarness::new(1);
+        sim.add_node("storage", &["client", "storage"]).await.unwrap();
+        sim.add_node("client", &["client"]).await.unwrap();
+        sim.wait_connected("client", "storage", CONNECT_TIMEOUT).await.unwrap();
+
+        let input = sim.root().join("hello.txt");
+        std::fs::write(&input, b"hello from the sim network").unwrap();
+        let published = sim
+            .call("storage", "data.publish", json!({ "path": input }))
+            .await
+            .unwrap();
+        let cid = published["cid"].as_str().unwrap().to_string();
+
+        let output = sim.root().join("fetched.txt");
+        sim.call("client", "data.fetch", json!({ "cid": cid, "output": output }))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"hello from the sim network");
+        sim.shutdown().await;
+    });
+}
+
+#[test]
+fn partition_separates_nodes_until_healed() {
+    runtime().block_on(async {
+        let sim = SimHarness::new(2);
+        sim.add_node("a", &["client"]).await.unwrap();
+        sim.add_node("b", &["client"]).await.unwrap();
+        sim.wait_connected("a", "b", CONNECT_TIMEOUT).await.unwrap();
+
+        sim.partition(&[&["a"], &["b"]]).await.unwrap();
+        sim.wait_disconnected("a", "b", CONNECT_TIMEOUT).await.unwrap();
+        assert!(!sim.is_connected("b", "a").await.unwrap());
+
+        sim.heal().await.unwrap();
+        sim.wait_connected("a", "b", CONNECT_TIMEOUT).await.unwrap();
+        sim.shutdown().await;
+    });
+}
+
+#[test]
+fn nodes_connect_and_fetch_over_a_lossy_slow_link() {
+    runtime().block_on(async {
+        let sim = SimHarness::new(4);
+        sim.add_node("storage", &["client", "storage"]).await.unwrap();
+        sim.set_link(
+            "client",
+            "storage",
+            LinkShape {
+                latency: Duration::from_millis(50),
+                loss: 0.05,
+            },
+        );
+        sim.add_node("client", &["client"]).await.unwrap();
+        sim.wait_connected("client", "storage", CONNECT_TIMEOUT).await.unwrap();
+
+        let input = sim.root().join("slow.txt");
+        std::fs::write(&input, vec![7u8; 256 * 1024]).unwrap();
+        let published = sim
+            .call("storage", "data.publish", json!({ "path": input }))
+            .await
+            .unwrap();
+        let cid = published["cid"].as_str().unwrap().to_string();
+
+        let output = sim.root().join("slow-fetched.txt");
+        sim.call("client", "data.fetch", json!({ "cid": cid, "output": output }))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), vec![7u8; 256 * 1024]);
+        sim.shutdown().await;
+    });
+}
+
+#[test]
+fn legacy_methods_are_reachable_over_in_memory_ipc() {
+    runtime().block_on(async {
+        let sim = SimHarness::new(3);
+        sim.add_node("a", &["client"]).await.unwrap();
+
+        assert!(sim.call("a", "data.status", json!(null)).await.is_ok());
+        let err = sim.call("a", "pubish", json!(null)).await.unwrap_err();
+        assert!(err.contains("data.publish"), "expected a suggestion, got: {}", err);
+        sim.shutdown().await;
+    });
+}
@@ -1,4 +1,4 @@
-import { useState } from "react";
+import { useEffect, useState } from "react";
 import {
   FolderOpen, File, Upload, Download, FolderPlus, Trash2,
   HardDrive, Database, ChevronRight, ArrowLeft, FileText,
@@ -21,9 +21,14 @@ function shortenCid(cid: string | null): string {
 }
 
 export default function FilesystemPage() {
-  const { entries, currentPath, selectedEntry, stats, navigateTo, selectEntry, createDir, deleteEntry } = useFilesystemStore();
+  const { entries, currentPath, selectedEntry, stats, error, loadEntries, navigateTo, selectEntry, createDir, deleteEntry } = useFilesystemStore();
   const [showMkdir, setShowMkdir] = useState(false);
   const [newDirName, setNewDirName] = useState("");
+  const [confirmDelete, setConfirmDelete] = useState<VFSEntry | null>(null);
+
+  useEffect(() => {
+    loadEntries();
+  }, [loadEntries]);
 
   // Get current directory's parent ID
   const currentParentId = currentPath.length > 0 ? currentPath[currentPath.length - 1] : null;
@@ -54,11 +59,21 @@ export default function FilesystemPage() {
     setShowMkdir(false);
   };
 
+  // Directories may have contents, so deleting one asks first
+  const handleDelete = (entry: VFSEntry) => {
+    if (entry.type === "directory") setConfirmDelete(entry);
+    else deleteEntry(entry.id);
+  };
+
+  const handleRecursiveDelete = () => {
+    if (confirmDelete) deleteEntry(confirmDelete.id, true);
+    setConfirmDelete(null);
+  };
+
   // Build breadcrumb
   const breadcrumb: { label: string; path: string[] }[] = [{ label: "/", path: [] }];
   for (let i = 0; i < currentPath.length; i++) {
-    const entry = entries.find((e) => e.id === currentPath[i]);
-    breadcrumb.push({ label: entry?.name || "?", path: currentPath.slice(0, i + 1) });
+    breadcrumb.push({ label: currentPath[i].split("/").pop() || "?", path: currentPath.slice(0, i + 1) });
   }
 
   return (
@@ -79,6 +94,10 @@ export default function FilesystemPage() {
         </div>
       </div>
 
+      {error && (
+        <div className="mb-4 px-4 py-2 bg-red-50 border border-red-200 text-red-700 rounded-lg text-sm">{error}</div>
+      )}
+
       {/* Stats */}
       <div className="grid grid-cols-4 gap-4 mb-6">
         <StatCard icon={File} label="Total Files" value={String(stats.totalFiles)} sub={`${stats.totalDirs} directories`} />
@@ -136,7 +155,7 @@ export default function FilesystemPage() {
                   <div className="flex-1 min-w-0">
                     <p className="text-sm font-medium text-gray-900 truncate">{entry.name}</p>
                     <p className="text-xs text-gray-400">
-                      {entry.type === "file" ? formatBytes(entry.size) : `${entries.filter((e) => e.parentId === entry.id).length} items`}
+                      {formatBytes(entry.size)}
                     </p>
                   </div>
                   <span className="text-xs text-gray-400 font-mono">{entry.permissions}</span>
@@ -194,7 +213,7 @@ export default function FilesystemPage() {
                   </button>
                 )}
                 <button
-                  onClick={() => deleteEntry(selectedEntry.id)}
+                  onClick={() => handleDelete(selectedEntry)}
                   className="flex-1 flex items-center justify-center gap-1 py-2 bg-red-50 hover:bg-red-100 text-red-600 rounded-lg transition-colors text-xs"
                 >
                   <Trash2 size={14} /> Delete
@@ -210,6 +229,25 @@ export default function FilesystemPage() {
         </div>
       </div>
 
+      {/* Recursive Delete Modal */}
+      <Modal open={confirmDelete !== null} onClose={() => setConfirmDelete(null)} title="Delete Directory">
+        <div className="space-y-4">
+          <p className="text-sm text-gray-700">
+            <span className="font-mono">{confirmDelete?.id}</span> and everything inside it will be deleted.
+          </p>
+          <div className="flex gap-2">
+            <button onClick={() => setConfirmDelete(null)}
+              className="flex-1 py-2 bg-gray-100 hover:bg-gray-200 text-gray-700 rounded-lg transition-colors">
+              Cancel
+            </button>
+            <button onClick={handleRecursiveDelete}
+              className="flex-1 py-2 bg-red-600 hover:bg-red-700 text-white rounded-lg transition-colors">
+              Delete
+            </button>
+          </div>
+        </div>
+      </Modal>
+
       {/* Mkdir Modal */}
       <Modal open={showMkdir} onClose={() => setShowMkdir(false)} title="Create Directory">
         <div className="space-y-4">
@@ -158,6 +158,26 @@ export interface HealthHistoryResponse {
 const RECONNECT_MS = 3_000;
 const REQUEST_TIMEOUT_MS = 30_000;
 
+/** JSON-RPC "method not found". */
+export const METHOD_NOT_FOUND = -32601;
+
+/** Error response of a daemon call, with its JSON-RPC code. */
+export class RpcError extends Error {
+  constructor(message: string, readonly code: number) {
+    super(message);
+    this.name = 'RpcError';
+  }
+
+  /**
+   * IPC handlers can only fail with a message, so the daemon sends unknown methods with the
+   * server's generic code and a `Method not found:` message; report those as -32601.
+   */
+  static from(error: { message: string; code?: number }): RpcError {
+    const code = error.message.startsWith('Method not found:') ? METHOD_NOT_FOUND : error.code ?? -32603;
+    return new RpcError(error.message, code);
+  }
+}
+
 type Pending = {
   resolve: (v: unknown) => void;
   reject: (e: Error) => void;
@@ -263,7 +283,7 @@ class DaemonClient {
             const p = this.pending.get(msg.id)!;
             this.pending.delete(msg.id);
             clearTimeout(p.timer);
-            if (msg.error) p.reject(new Error(msg.error.message));
+            if (msg.error) p.reject(RpcError.from(msg.error));
             else p.resolve(msg.result);
           } else if (msg.method) {
             // Server-push event
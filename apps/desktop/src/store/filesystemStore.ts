@@ -1,9 +1,13 @@
 import { create } from "zustand";
+import { invoke } from "@tauri-apps/api/core";
+import { useInstanceStore } from "./instanceStore";
 
 export interface VFSEntry {
+  /** Absolute VFS path */
   id: string;
   name: string;
   type: "file" | "directory";
+  /** Parent directory path, null at the root */
   parentId: string | null;
   size: number;
   cid: string | null;
@@ -13,6 +17,25 @@ export interface VFSEntry {
   children?: VFSEntry[];
 }
 
+/** Entry as returned by the `vfs_*` commands */
+interface RawEntry {
+  path: string;
+  name: string;
+  kind: "file" | "directory";
+  size: number;
+  cid: string | null;
+  mode: number;
+  created_at: number;
+  modified_at: number;
+}
+
+interface RawStats {
+  files: number;
+  dirs: number;
+  total_bytes: number;
+  db_bytes: number;
+}
+
 interface FilesystemState {
   entries: VFSEntry[];
   currentPath: string[];
@@ -25,61 +48,117 @@ interface FilesystemState {
     totalSize: number;
     dbSize: number;
   };
-  loadEntries: () => void;
+  loadEntries: () => Promise<void>;
   navigateTo: (path: string[]) => void;
   selectEntry: (entry: VFSEntry | null) => void;
-  createDir: (name: string) => void;
-  deleteEntry: (id: string) => void;
+  createDir: (name: string) => Promise<void>;
+  /** Non-empty directories are only removed with `recursive` */
+  deleteEntry: (id: string, recursive?: boolean) => Promise<void>;
+  renameEntry: (id: string, newName: string) => Promise<void>;
+  moveEntry: (id: string, to: string) => Promise<void>;
+}
+
+/** Daemon instance ID of the active instance */
+async function activePid(): Promise<number> {
+  const { instances, activeId } = useInstanceStore.getState();
+  const instance = instances.find((i) => i.id === activeId);
+  if (!instance) throw new Error("No active instance");
+  const running = await invoke<Array<{ pid: number; ws_port: number }>>("list_craftobj_daemons");
+  const match = running.find((d) => d.ws_port === instance.ws_port);
+  if (!match) throw new Error("The active instance is not running");
+  return match.pid;
 }
 
-const mockEntries: VFSEntry[] = [
-  { id: "dir-root-docs", name: "documents", type: "directory", parentId: null, size: 0, cid: null, permissions: "rwxr-xr-x", createdAt: Date.now() - 86400000 * 30, modifiedAt: Date.now() - 3600000 },
-  { id: "dir-root-media", name: "media", type: "directory", parentId: null, size: 0, cid: null, permissions: "rwxr-xr-x", createdAt: Date.now() - 86400000 * 20, modifiedAt: Date.now() - 86400000 },
-  { id: "dir-root-data", name: "datasets", type: "directory", parentId: null, size: 0, cid: null, permissions: "rwxr-x---", createdAt: Date.now() - 86400000 * 10, modifiedAt: Date.now() - 7200000 },
-  { id: "file-readme", name: "README.md", type: "file", parentId: null, size: 2048, cid: "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzd", permissions: "rw-r--r--", createdAt: Date.now() - 86400000 * 30, modifiedAt: Date.now() - 86400000 * 2 },
-  { id: "file-config", name: "config.toml", type: "file", parentId: null, size: 512, cid: "bafkreixyz789abc123def456ghi789jkl012mno", permissions: "rw-------", createdAt: Date.now() - 86400000 * 15, modifiedAt: Date.now() - 43200000 },
-  { id: "file-doc1", name: "report-2026.pdf", type: "file", parentId: "dir-root-docs", size: 1048576, cid: "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuyl", permissions: "rw-r--r--", createdAt: Date.now() - 86400000 * 5, modifiedAt: Date.now() - 86400000 * 5 },
-  { id: "file-doc2", name: "notes.txt", type: "file", parentId: "dir-root-docs", size: 4096, cid: "bafkreiqwerty123456789asdfghjklzxcvbnm", permissions: "rw-r--r--", createdAt: Date.now() - 86400000 * 2, modifiedAt: Date.now() - 7200000 },
-  { id: "file-media1", name: "photo.jpg", type: "file", parentId: "dir-root-media", size: 3145728, cid: "bafybeie5gq4jnaenirfkl5rgq33ndkbmop3mpkr", permissions: "rw-r--r--", createdAt: Date.now() - 86400000 * 10, modifiedAt: Date.now() - 86400000 * 10 },
-  { id: "file-data1", name: "training-set.csv", type: "file", parentId: "dir-root-data", size: 52428800, cid: "bafybeiabc123def456ghi789jkl012mno345pqr", permissions: "rw-r-----", createdAt: Date.now() - 86400000 * 3, modifiedAt: Date.now() - 86400000 * 3 },
-];
+function modeString(mode: number): string {
+  const bits = "rwxrwxrwx";
+  return bits.split("").map((c, i) => (mode & (1 << (8 - i)) ? c : "-")).join("");
+}
 
-export const useFilesystemStore = create<FilesystemState>((set) => ({
-  entries: mockEntries,
+function toEntry(e: RawEntry): VFSEntry {
+  const parent = e.path.slice(0, e.path.lastIndexOf("/")) || "/";
+  return {
+    id: e.path,
+    name: e.name,
+    type: e.kind,
+    parentId: parent === "/" ? null : parent,
+    size: e.size,
+    cid: e.cid,
+    permissions: modeString(e.mode),
+    createdAt: e.created_at * 1000,
+    modifiedAt: e.modified_at * 1000,
+  };
+}
+
+function currentDir(path: string[]): string {
+  return path.length > 0 ? path[path.length - 1] : "/";
+}
+
+export const useFilesystemStore = create<FilesystemState>((set, get) => ({
+  entries: [],
   currentPath: [],
   selectedEntry: null,
   loading: false,
   error: null,
   stats: {
-    totalFiles: 6,
-    totalDirs: 3,
-    totalSize: 56629248,
-    dbSize: 1048576,
+    totalFiles: 0,
+    totalDirs: 0,
+    totalSize: 0,
+    dbSize: 0,
+  },
+  loadEntries: async () => {
+    set({ loading: true, error: null });
+    try {
+      const pid = await activePid();
+      const [raw, stats] = await Promise.all([
+        invoke<RawEntry[]>("vfs_list", { pid, path: currentDir(get().currentPath) }),
+        invoke<RawStats>("vfs_stats", { pid }),
+      ]);
+      set({
+        entries: raw.map(toEntry),
+        stats: { totalFiles: stats.files, totalDirs: stats.dirs, totalSize: stats.total_bytes, dbSize: stats.db_bytes },
+        loading: false,
+      });
+    } catch (e) {
+      set({ entries: [], loading: false, error: String(e) });
+    }
   },
-  loadEntries: () => {
-    set({ loading: true });
-    setTimeout(() => set({ loading: false }), 300);
+  navigateTo: (path) => {
+    set({ currentPath: path, selectedEntry: null });
+    get().loadEntries();
   },
-  navigateTo: (path) => set({ currentPath: path, selectedEntry: null }),
   selectEntry: (entry) => set({ selectedEntry: entry }),
-  createDir: (name) => {
-    const newDir: VFSEntry = {
-      id: `dir-${Date.now()}`,
-      name,
-      type: "directory",
-      parentId: null,
-      size: 0,
-      cid: null,
-      permissions: "rwxr-xr-x",
-      createdAt: Date.now(),
-      modifiedAt: Date.now(),
-    };
-    set((s) => ({ entries: [...s.entries, newDir], stats: { ...s.stats, totalDirs: s.stats.totalDirs + 1 } }));
+  createDir: async (name) => {
+    try {
+      const dir = currentDir(get().currentPath);
+      await invoke("vfs_mkdir", { pid: await activePid(), path: `${dir === "/" ? "" : dir}/${name}` });
+      await get().loadEntries();
+    } catch (e) {
+      set({ error: String(e) });
+    }
+  },
+  deleteEntry: async (id, recursive = false) => {
+    try {
+      await invoke("vfs_delete", { pid: await activePid(), path: id, recursive });
+      set((s) => ({ selectedEntry: s.selectedEntry?.id === id ? null : s.selectedEntry }));
+      await get().loadEntries();
+    } catch (e) {
+      set({ error: String(e) });
+    }
+  },
+  renameEntry: async (id, newName) => {
+    try {
+      await invoke("vfs_rename", { pid: await activePid(), path: id, newName });
+      await get().loadEntries();
+    } catch (e) {
+      set({ error: String(e) });
+    }
   },
-  deleteEntry: (id) => {
-    set((s) => ({
-      entries: s.entries.filter((e) => e.id !== id && e.parentId !== id),
-      selectedEntry: s.selectedEntry?.id === id ? null : s.selectedEntry,
-    }));
+  moveEntry: async (id, to) => {
+    try {
+      await invoke("vfs_move", { pid: await activePid(), from: id, to });
+      await get().loadEntries();
+    } catch (e) {
+      set({ error: String(e) });
+    }
   },
 }));